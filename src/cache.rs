@@ -0,0 +1,382 @@
+//! Cache hierarchy simulator: split L1 instruction and data caches and an
+//! optional unified L2, in front of a fixed latency memory.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use cpu::Cpu;
+use memory::AccessKind;
+use trace::{Observer, Record};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replacement {
+    Lru,
+    Fifo,
+    Random,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    pub size: u32, // bytes
+    pub associativity: u32,
+    pub line_size: u32, // bytes
+    pub replacement: Replacement,
+    pub write_back: bool, // otherwise write-through
+    pub write_allocate: bool,
+    pub latency: u32, // cycles added by an access to this cache
+}
+
+impl CacheConfig {
+    pub fn l1() -> CacheConfig {
+        CacheConfig {
+            size: 4096,
+            associativity: 2,
+            line_size: 16,
+            replacement: Replacement::Lru,
+            write_back: true,
+            write_allocate: true,
+            latency: 0,
+        }
+    }
+
+    pub fn l2() -> CacheConfig {
+        CacheConfig {
+            size: 64 * 1024,
+            associativity: 8,
+            line_size: 32,
+            latency: 10,
+            ..CacheConfig::l1()
+        }
+    }
+
+    /// Updates the configuration from `key=value` pairs separated by commas:
+    /// size (with an optional k or m suffix), assoc, line, repl (lru, fifo or
+    /// random), write (back or through), alloc (yes or no) and latency.
+    pub fn parse(&mut self, options: &str) -> Result<(), String> {
+        for option in options.split(',').filter(|option| !option.is_empty()) {
+            let mut parts = option.splitn(2, '=');
+            let key = parts.next().unwrap();
+            let value = parts.next().ok_or_else(|| format!("Missing value for {}.", key))?;
+            let invalid = || format!("Invalid value {} for {}.", value, key);
+
+            match key {
+                "size" => self.size = parse_size(value).ok_or_else(invalid)?,
+                "assoc" => self.associativity = value.parse().map_err(|_| invalid())?,
+                "line" => self.line_size = parse_size(value).ok_or_else(invalid)?,
+                "latency" => self.latency = value.parse().map_err(|_| invalid())?,
+                "repl" => self.replacement = match value {
+                    "lru" => Replacement::Lru,
+                    "fifo" => Replacement::Fifo,
+                    "random" => Replacement::Random,
+                    _ => return Err(invalid()),
+                },
+                "write" => self.write_back = match value {
+                    "back" => true,
+                    "through" => false,
+                    _ => return Err(invalid()),
+                },
+                "alloc" => self.write_allocate = match value {
+                    "yes" => true,
+                    "no" => false,
+                    _ => return Err(invalid()),
+                },
+                _ => return Err(format!("Unknown cache option {}.", key)),
+            }
+        }
+        self.validate()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if !self.line_size.is_power_of_two() || self.line_size < 4 {
+            return Err(format!("Line size {} is not a power of two of at least 4.", self.line_size));
+        }
+        if self.associativity == 0 {
+            return Err("Associativity is 0.".to_string());
+        }
+        let set_size = self.line_size * self.associativity;
+        if self.size == 0 || !self.size.is_multiple_of(set_size) || !(self.size / set_size).is_power_of_two() {
+            return Err(format!("Size {} is not a power of two number of sets of {} bytes.", self.size, set_size));
+        }
+        Ok(())
+    }
+}
+
+fn parse_size(value: &str) -> Option<u32> {
+    let lower = value.to_lowercase();
+    let (digits, unit) = if lower.ends_with('k') {
+        (&lower[..lower.len() - 1], 1024)
+    } else if lower.ends_with('m') {
+        (&lower[..lower.len() - 1], 1024 * 1024)
+    } else {
+        (&lower[..], 1)
+    };
+    digits.parse::<u32>().ok().and_then(|n| n.checked_mul(unit))
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub reads: u64,
+    pub read_misses: u64,
+    pub writes: u64,
+    pub write_misses: u64,
+    pub evictions: u64,
+    pub writebacks: u64,
+}
+
+impl CacheStats {
+    pub fn accesses(&self) -> u64 {
+        self.reads + self.writes
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.read_misses + self.write_misses
+    }
+
+    pub fn miss_rate(&self) -> f64 {
+        if self.accesses() == 0 {
+            0.0
+        } else {
+            self.misses() as f64 * 100.0 / self.accesses() as f64
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Line {
+    valid: bool,
+    dirty: bool,
+    tag: u32,
+    stamp: u64, // last use for LRU, insertion for FIFO
+}
+
+/// Result of an access to one cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub hit: bool,
+    pub allocated: bool,
+    pub writeback: Option<u32>, // address of the dirty line evicted
+}
+
+#[derive(Debug, Clone)]
+pub struct Cache {
+    pub name: String,
+    pub config: CacheConfig,
+    pub stats: CacheStats,
+    sets: Vec<Vec<Line>>,
+    clock: u64,
+    rng_state: u32,
+}
+
+impl Cache {
+    pub fn new(name: &str, config: CacheConfig) -> Cache {
+        let set_count = config.size / (config.line_size * config.associativity);
+        Cache {
+            name: name.to_string(),
+            config,
+            stats: CacheStats::default(),
+            sets: vec![vec![Line::default(); config.associativity as usize]; set_count as usize],
+            clock: 0,
+            rng_state: 0x2545f491,
+        }
+    }
+
+    pub fn access(&mut self, addr: u32, write: bool) -> Access {
+        self.clock += 1;
+        let line_addr = addr / self.config.line_size;
+        let set_index = (line_addr as usize) & (self.sets.len() - 1);
+        let tag = line_addr / self.sets.len() as u32;
+
+        if write {
+            self.stats.writes += 1;
+        } else {
+            self.stats.reads += 1;
+        }
+
+        let lru = self.config.replacement == Replacement::Lru;
+        let clock = self.clock;
+        if let Some(line) = self.sets[set_index].iter_mut().find(|line| line.valid && line.tag == tag) {
+            if lru {
+                line.stamp = clock;
+            }
+            line.dirty |= write && self.config.write_back;
+            return Access { hit: true, allocated: false, writeback: None };
+        }
+
+        if write {
+            self.stats.write_misses += 1;
+            if !self.config.write_allocate {
+                return Access { hit: false, allocated: false, writeback: None };
+            }
+        } else {
+            self.stats.read_misses += 1;
+        }
+
+        let way = self.victim(set_index);
+        let set_count = self.sets.len() as u32;
+        let line_size = self.config.line_size;
+        let victim = &mut self.sets[set_index][way];
+        let mut writeback = None;
+        if victim.valid {
+            self.stats.evictions += 1;
+            if victim.dirty {
+                self.stats.writebacks += 1;
+                writeback = Some((victim.tag * set_count + set_index as u32) * line_size);
+            }
+        }
+        *victim = Line {
+            valid: true,
+            dirty: write && self.config.write_back,
+            tag,
+            stamp: clock,
+        };
+        Access { hit: false, allocated: true, writeback }
+    }
+
+    fn victim(&mut self, set_index: usize) -> usize {
+        let set = &self.sets[set_index];
+        if let Some(way) = set.iter().position(|line| !line.valid) {
+            return way;
+        }
+
+        match self.config.replacement {
+            Replacement::Lru | Replacement::Fifo => {
+                (0..set.len()).min_by_key(|&way| set[way].stamp).unwrap()
+            },
+            Replacement::Random => {
+                // xorshift32, fixed seed to keep runs reproducible
+                self.rng_state ^= self.rng_state << 13;
+                self.rng_state ^= self.rng_state >> 17;
+                self.rng_state ^= self.rng_state << 5;
+                self.rng_state as usize % set.len()
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct SymbolStats {
+    accesses: u64,
+    misses: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheHierarchy {
+    pub l1i: Cache,
+    pub l1d: Cache,
+    pub l2: Option<Cache>,
+    pub memory_latency: u32,
+    // per function doing the access, for L1I and L1D
+    symbols: [HashMap<Option<String>, SymbolStats>; 2],
+}
+
+impl CacheHierarchy {
+    pub fn new(l1i: CacheConfig, l1d: CacheConfig, l2: Option<CacheConfig>, memory_latency: u32) -> CacheHierarchy {
+        CacheHierarchy {
+            l1i: Cache::new("L1I", l1i),
+            l1d: Cache::new("L1D", l1d),
+            l2: l2.map(|config| Cache::new("L2", config)),
+            memory_latency,
+            symbols: [HashMap::new(), HashMap::new()],
+        }
+    }
+
+    /// Simulates an instruction fetch and returns its latency in cycles.
+    pub fn fetch(&mut self, addr: u32) -> u32 {
+        let access = self.l1i.access(addr, false);
+        self.l1i.config.latency + self.next_level(&access, addr, false, false)
+    }
+
+    /// Simulates a data access and returns its latency in cycles.
+    pub fn data(&mut self, addr: u32, write: bool) -> u32 {
+        let access = self.l1d.access(addr, write);
+        let write_through = write && !self.l1d.config.write_back;
+        self.l1d.config.latency + self.next_level(&access, addr, write, write_through)
+    }
+
+    /// Latency of the accesses that go past L1: the line fill, the dirty
+    /// victim written back (buffered, so free) and the written word of a
+    /// write-through cache or a write miss without allocation.
+    fn next_level(&mut self, access: &Access, addr: u32, write: bool, write_through: bool) -> u32 {
+        let mut latency = 0;
+        if !access.hit && access.allocated {
+            latency += self.below_l1(addr, false);
+        }
+        if write && (write_through || !access.allocated && !access.hit) {
+            latency += self.below_l1(addr, true);
+        }
+        if let Some(victim) = access.writeback {
+            self.below_l1(victim, true);
+        }
+        latency
+    }
+
+    fn below_l1(&mut self, addr: u32, write: bool) -> u32 {
+        match self.l2 {
+            Some(ref mut l2) => {
+                let access = l2.access(addr, write);
+                let mut latency = l2.config.latency;
+                if !access.hit || write && !l2.config.write_back {
+                    latency += self.memory_latency;
+                }
+                latency
+            },
+            None => self.memory_latency,
+        }
+    }
+
+    /// Simulates the fetch and data accesses of an executed instruction,
+    /// returns their latencies.
+    pub fn simulate(&mut self, record: &Record, cpu: &Cpu) -> (u32, u32) {
+        let function = cpu.symbols.lookup(record.pc).map(|symbol| symbol.name.clone());
+
+        let misses = self.l1i.stats.misses();
+        let fetch = self.fetch(record.pc);
+        let stats = self.symbols[0].entry(function.clone()).or_default();
+        stats.accesses += 1;
+        stats.misses += self.l1i.stats.misses() - misses;
+
+        let mut data = 0;
+        for access in &record.memory {
+            let misses = self.l1d.stats.misses();
+            data += self.data(access.addr, access.kind == AccessKind::Write);
+            let stats = self.symbols[1].entry(function.clone()).or_default();
+            stats.accesses += 1;
+            stats.misses += self.l1d.stats.misses() - misses;
+        }
+        (fetch, data)
+    }
+
+    pub fn report<W: Write>(&self, writer: &mut W, top: usize) -> io::Result<()> {
+        writeln!(writer, "{:<5} {:>12} {:>12} {:>12} {:>12} {:>8} {:>10} {:>10}",
+                 "cache", "reads", "read miss", "writes", "write miss", "miss %", "evictions", "writebacks")?;
+        for cache in [Some(&self.l1i), Some(&self.l1d), self.l2.as_ref()].iter().flatten() {
+            let stats = &cache.stats;
+            writeln!(writer, "{:<5} {:>12} {:>12} {:>12} {:>12} {:>7.2}% {:>10} {:>10}",
+                     cache.name, stats.reads, stats.read_misses, stats.writes, stats.write_misses,
+                     stats.miss_rate(), stats.evictions, stats.writebacks)?;
+        }
+
+        for (cache, symbols) in [&self.l1i, &self.l1d].iter().zip(self.symbols.iter()) {
+            let mut symbols: Vec<_> = symbols.iter().filter(|&(_, stats)| stats.misses != 0).collect();
+            if symbols.is_empty() {
+                continue;
+            }
+            symbols.sort_by(|a, b| b.1.misses.cmp(&a.1.misses).then(a.0.cmp(b.0)));
+
+            writeln!(writer, "\n{} misses per function:", cache.name)?;
+            for (name, stats) in symbols.into_iter().take(top) {
+                let name = name.as_ref().map_or("<unknown>", |name| name.as_str());
+                writeln!(writer, "  {:>12} / {:<12} {:>6.2}%  {}",
+                         stats.misses, stats.accesses,
+                         stats.misses as f64 * 100.0 / stats.accesses as f64, name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Observer for CacheHierarchy {
+    fn observe(&mut self, record: &Record, cpu: &Cpu) {
+        self.simulate(record, cpu);
+    }
+}
@@ -0,0 +1,262 @@
+//! Call-stack aware profile, exported as folded stacks or callgrind files.
+//!
+//! Calls are `jal`, `jalr` and taken `bgezal`/`bltzal`, returns are
+//! `jr $ra`. Both take effect after their delay slot, which is counted in
+//! the caller.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+use cpu::Cpu;
+use instruction::Instruction;
+use symbols::SymbolTable;
+use trace::{Observer, Record};
+
+#[derive(Debug, Clone)]
+struct Node {
+    func: u32,
+    parent: Option<usize>,
+    children: HashMap<u32, usize>,
+    count: u64, // instructions executed with exactly this stack
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    node: usize,
+    call_site: u32,
+    start_total: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Transfer {
+    Call(u32, u32), // call site, target
+    Return,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CallCost {
+    pub calls: u64,
+    pub inclusive: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CallGraph {
+    pub total: u64,
+    nodes: Vec<Node>,
+    stack: Vec<Frame>,
+    pending: Option<Transfer>,
+    /// Exclusive instruction count per function and pc.
+    costs: BTreeMap<u32, BTreeMap<u32, u64>>,
+    /// Completed calls per (caller, call site, callee).
+    calls: BTreeMap<(u32, u32, u32), CallCost>,
+}
+
+impl CallGraph {
+    pub fn new() -> CallGraph {
+        CallGraph::default()
+    }
+
+    /// Instructions executed in each function, `(exclusive, inclusive)`.
+    /// Recursive calls are only counted once in the inclusive count.
+    pub fn functions(&self) -> BTreeMap<u32, (u64, u64)> {
+        let mut functions = BTreeMap::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            functions.entry(node.func).or_insert((0, 0)).0 += node.count;
+
+            let mut seen = Vec::new();
+            let mut current = Some(index);
+            while let Some(i) = current {
+                let func = self.nodes[i].func;
+                if !seen.contains(&func) {
+                    seen.push(func);
+                    functions.entry(func).or_insert((0, 0)).1 += node.count;
+                }
+                current = self.nodes[i].parent;
+            }
+        }
+        functions
+    }
+
+    pub fn report<W: Write>(&self, writer: &mut W, symbols: &SymbolTable, top: usize) -> io::Result<()> {
+        let mut functions: Vec<(u32, (u64, u64))> = self.functions().into_iter().collect();
+        functions.sort_by_key(|&(func, (_, inclusive))| (Reverse(inclusive), func));
+
+        writeln!(writer, "{:>12} {:>7} {:>12} {:>7}  function", "inclusive", "", "exclusive", "")?;
+        for (func, (exclusive, inclusive)) in functions.into_iter().take(top) {
+            writeln!(writer, "{:>12} {:>6.2}% {:>12} {:>6.2}%  {}",
+                     inclusive, self.percent(inclusive),
+                     exclusive, self.percent(exclusive),
+                     function_name(symbols, func))?;
+        }
+        Ok(())
+    }
+
+    /// One line per stack, `outer;inner count`, as read by flamegraph.pl.
+    pub fn write_folded<W: Write>(&self, writer: &mut W, symbols: &SymbolTable) -> io::Result<()> {
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.count == 0 {
+                continue;
+            }
+
+            let mut names = Vec::new();
+            let mut current = Some(index);
+            while let Some(i) = current {
+                names.push(function_name(symbols, self.nodes[i].func));
+                current = self.nodes[i].parent;
+            }
+            names.reverse();
+            writeln!(writer, "{} {}", names.join(";"), node.count)?;
+        }
+        Ok(())
+    }
+
+    /// Callgrind profile with per instruction costs, readable by KCachegrind.
+    pub fn write_callgrind<W: Write>(&self, writer: &mut W, symbols: &SymbolTable, command: &str) -> io::Result<()> {
+        writeln!(writer, "# callgrind format")?;
+        writeln!(writer, "version: 1")?;
+        writeln!(writer, "creator: mips_emu")?;
+        writeln!(writer, "cmd: {}", command)?;
+        writeln!(writer, "positions: instr")?;
+        writeln!(writer, "events: Ir")?;
+        writeln!(writer, "summary: {}", self.total)?;
+
+        // the calls still running when the profile is written
+        let mut calls = self.calls.clone();
+        for (i, frame) in self.stack.iter().enumerate().skip(1) {
+            let caller = self.nodes[self.stack[i - 1].node].func;
+            let callee = self.nodes[frame.node].func;
+            let cost = calls.entry((caller, frame.call_site, callee)).or_default();
+            cost.calls += 1;
+            cost.inclusive += self.total - frame.start_total;
+        }
+
+        let mut functions: Vec<u32> = self.costs.keys().cloned().collect();
+        functions.extend(calls.keys().map(|&(caller, _, _)| caller));
+        functions.sort();
+        functions.dedup();
+
+        for func in functions {
+            writeln!(writer)?;
+            writeln!(writer, "fn={}", function_name(symbols, func))?;
+            if let Some(costs) = self.costs.get(&func) {
+                for (pc, count) in costs {
+                    writeln!(writer, "{:#x} {}", pc, count)?;
+                }
+            }
+            for (&(_, call_site, callee), cost) in calls.range((func, 0, 0)..=(func, u32::MAX, u32::MAX)) {
+                writeln!(writer, "cfn={}", function_name(symbols, callee))?;
+                writeln!(writer, "calls={} {:#x}", cost.calls, callee)?;
+                writeln!(writer, "{:#x} {}", call_site, cost.inclusive)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            count as f64 * 100.0 / self.total as f64
+        }
+    }
+
+    fn node(&mut self, parent: Option<usize>, func: u32) -> usize {
+        if let Some(&index) = parent.and_then(|parent| self.nodes[parent].children.get(&func)) {
+            return index;
+        }
+
+        self.nodes.push(Node {
+            func,
+            parent,
+            children: HashMap::new(),
+            count: 0,
+        });
+        let index = self.nodes.len() - 1;
+        if let Some(parent) = parent {
+            self.nodes[parent].children.insert(func, index);
+        }
+        index
+    }
+
+    fn transfer(&mut self, transfer: Transfer) {
+        match transfer {
+            Transfer::Call(call_site, target) => {
+                let parent = self.stack.last().map(|frame| frame.node);
+                let node = self.node(parent, target);
+                self.stack.push(Frame {
+                    node,
+                    call_site,
+                    start_total: self.total,
+                });
+            },
+            // returning from the outermost function is ignored
+            Transfer::Return if self.stack.len() > 1 => {
+                let frame = self.stack.pop().unwrap();
+                let caller = self.nodes[self.stack.last().unwrap().node].func;
+                let callee = self.nodes[frame.node].func;
+                let cost = self.calls.entry((caller, frame.call_site, callee)).or_default();
+                cost.calls += 1;
+                cost.inclusive += self.total - frame.start_total;
+            },
+            Transfer::Return => {},
+        }
+    }
+}
+
+impl Observer for CallGraph {
+    fn observe(&mut self, record: &Record, cpu: &Cpu) {
+        if self.stack.is_empty() {
+            let func = cpu.symbols.lookup(record.pc).map_or(record.pc, |symbol| symbol.addr);
+            let node = self.node(None, func);
+            self.stack.push(Frame {
+                node,
+                call_site: 0,
+                start_total: 0,
+            });
+        }
+
+        let node = self.stack.last().unwrap().node;
+        self.nodes[node].count += 1;
+        let func = self.nodes[node].func;
+        *self.costs.entry(func).or_default().entry(record.pc).or_insert(0) += 1;
+        self.total += 1;
+
+        // the previous instruction was a call or a return, this one was its delay slot
+        if let Some(transfer) = self.pending.take() {
+            self.transfer(transfer);
+        }
+
+        let inst = record.instruction(cpu.isa);
+        let destination = cpu.branch_destination(&inst);
+        let transfer = match inst {
+            Instruction::JAL(..) | Instruction::JIALC(..) => Some(Transfer::Call(record.pc, destination)),
+            Instruction::JALR(_, rd) if rd != 0 => Some(Transfer::Call(record.pc, destination)),
+            Instruction::BGEZAL(..) | Instruction::BLTZAL(..) |
+            Instruction::BGEZALL(..) | Instruction::BLTZALL(..) |
+            Instruction::BALC(..) | Instruction::BEQZALC(..) | Instruction::BGEZALC(..) |
+            Instruction::BGTZALC(..) | Instruction::BLEZALC(..) | Instruction::BLTZALC(..) |
+            Instruction::BNEZALC(..) => {
+                if inst.branch_target(record.pc) == Some(destination) {
+                    Some(Transfer::Call(record.pc, destination))
+                } else {
+                    None
+                }
+            },
+            Instruction::JR(31) | Instruction::JIC(31, 0) => Some(Transfer::Return),
+            _ => None,
+        };
+        // a compact call or return has no delay slot to wait for
+        if inst.is_compact() {
+            if let Some(transfer) = transfer {
+                self.transfer(transfer);
+            }
+        } else {
+            self.pending = transfer;
+        }
+    }
+}
+
+fn function_name(symbols: &SymbolTable, func: u32) -> String {
+    symbols.describe(func).unwrap_or_else(|| format!("{:#x}", func))
+}
@@ -0,0 +1,350 @@
+//! System control coprocessor (COP0): the registers for exceptions,
+//! interrupts and the Count/Compare timer.
+//!
+//! Count is incremented once per executed instruction. The interrupt lines
+//! of the devices are wired to the Cause IP bits, line `n` to IP`n` (bit
+//! `8 + n`), so that the MARS keyboard and display raise Cause bits 8 and 9
+//! as in MARS. The Count/Compare timer raises IP7.
+//!
+//! The TLB registers only matter when the MMU is enabled, `tlb_size` being
+//! the number of TLB entries then and 0 otherwise.
+
+use std::fmt;
+
+pub const INDEX: u32 = 0;
+pub const RANDOM: u32 = 1;
+pub const ENTRY_LO0: u32 = 2;
+pub const ENTRY_LO1: u32 = 3;
+pub const CONTEXT: u32 = 4;
+pub const PAGE_MASK: u32 = 5;
+pub const WIRED: u32 = 6;
+pub const HWRENA: u32 = 7;
+pub const BAD_VADDR: u32 = 8;
+pub const COUNT: u32 = 9;
+pub const ENTRY_HI: u32 = 10;
+pub const COMPARE: u32 = 11;
+pub const STATUS: u32 = 12;
+pub const CAUSE: u32 = 13;
+pub const EPC: u32 = 14;
+pub const PRID: u32 = 15;
+pub const CONFIG: u32 = 16;
+pub const ERROR_EPC: u32 = 30;
+
+pub const RESET_VECTOR: u32 = 0xbfc00000;
+
+pub const STATUS_IE: u32 = 1 << 0;
+pub const STATUS_EXL: u32 = 1 << 1;
+pub const STATUS_ERL: u32 = 1 << 2;
+pub const STATUS_KSU: u32 = 0x3 << 3;
+pub const STATUS_USER: u32 = 0x2 << 3;
+pub const STATUS_CU0: u32 = 1 << 28;
+pub const STATUS_BEV: u32 = 1 << 22;
+pub const STATUS_IM: u32 = 0xff << 8;
+// the fields that are implemented, the others reading as zero
+const STATUS_WRITABLE: u32 = STATUS_CU0 | STATUS_BEV | STATUS_IM | STATUS_KSU | STATUS_ERL | STATUS_EXL | STATUS_IE;
+
+pub const CAUSE_IP: u32 = 0xff << 8;
+pub const CAUSE_SOFTWARE_IP: u32 = 0x3 << 8;
+pub const CAUSE_TI: u32 = 1 << 30;
+pub const CAUSE_BD: u32 = 1 << 31;
+const CAUSE_EXC_CODE: u32 = 0x1f << 2;
+const TIMER_IP: u32 = 1 << 15;
+
+/// Exception codes of Cause.
+pub const EXC_INTERRUPT: u32 = 0;
+pub const EXC_MOD: u32 = 1;
+pub const EXC_TLBL: u32 = 2;
+pub const EXC_TLBS: u32 = 3;
+pub const EXC_ADEL: u32 = 4;
+pub const EXC_ADES: u32 = 5;
+pub const EXC_RI: u32 = 10;
+pub const EXC_CPU: u32 = 11;
+pub const EXC_TR: u32 = 13;
+
+pub const INDEX_PROBE_FAILURE: u32 = 1 << 31;
+pub const ENTRY_HI_ASID: u32 = 0xff;
+const ENTRY_HI_VPN2: u32 = 0xffffe000;
+
+/// Number of words of the state of COP0, see `Cop0::words`.
+pub const WORDS: usize = 19;
+
+/// Exception raised by an instruction, delivered before the next one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Exception {
+    pub code: u32,
+    pub bad_vaddr: Option<u32>,
+    pub refill: bool, // TLB miss, handled by the refill vector outside of exceptions
+}
+
+impl Exception {
+    pub fn new(code: u32) -> Exception {
+        Exception { code, bad_vaddr: None, refill: false }
+    }
+
+    pub fn address(code: u32, vaddr: u32) -> Exception {
+        Exception { code, bad_vaddr: Some(vaddr), refill: false }
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.code, self.refill) {
+            (EXC_INTERRUPT, _) => write!(f, "interrupt")?,
+            (EXC_MOD, _) => write!(f, "TLB modified")?,
+            (EXC_TLBL, true) => write!(f, "TLB miss on load")?,
+            (EXC_TLBL, false) => write!(f, "TLB invalid on load")?,
+            (EXC_TLBS, true) => write!(f, "TLB miss on store")?,
+            (EXC_TLBS, false) => write!(f, "TLB invalid on store")?,
+            (EXC_ADEL, _) => write!(f, "address error on load")?,
+            (EXC_ADES, _) => write!(f, "address error on store")?,
+            (EXC_RI, _) => write!(f, "reserved instruction")?,
+            (EXC_CPU, _) => write!(f, "coprocessor unusable")?,
+            (EXC_TR, _) => write!(f, "trap")?,
+            (code, _) => write!(f, "exception {}", code)?,
+        }
+        match self.bad_vaddr {
+            Some(vaddr) => write!(f, " at {:#010x}", vaddr),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Cop0 {
+    pub status: u32,
+    pub cause: u32,
+    pub epc: u32,
+    pub count: u32,
+    pub compare: u32,
+    pub bad_vaddr: u32,
+    pub error_epc: u32,
+    pub index: u32,
+    pub random: u32,
+    pub entry_lo0: u32,
+    pub entry_lo1: u32,
+    pub context: u32,
+    pub page_mask: u32,
+    pub wired: u32,
+    pub entry_hi: u32,
+    pub hwrena: u32, // hardware registers readable by `rdhwr` in user mode
+    pub user_local: u32,
+    pub tlb_size: u32,
+    software_ip: u32, // IP bits written by software, the others follow the lines
+}
+
+impl Cop0 {
+    pub fn new() -> Cop0 {
+        Cop0::default()
+    }
+
+    pub fn with_tlb(tlb_size: u32) -> Cop0 {
+        Cop0 {
+            tlb_size,
+            random: tlb_size.saturating_sub(1),
+            ..Cop0::default()
+        }
+    }
+
+    /// Every register in a fixed order, the internal ones included, for the
+    /// snapshots.
+    pub fn words(&self) -> [u32; WORDS] {
+        [
+            self.status, self.cause, self.epc, self.count, self.compare, self.bad_vaddr,
+            self.error_epc, self.index, self.random, self.entry_lo0, self.entry_lo1,
+            self.context, self.page_mask, self.wired, self.entry_hi, self.hwrena,
+            self.user_local, self.tlb_size, self.software_ip,
+        ]
+    }
+
+    pub fn from_words(words: [u32; WORDS]) -> Cop0 {
+        let [status, cause, epc, count, compare, bad_vaddr, error_epc, index, random,
+             entry_lo0, entry_lo1, context, page_mask, wired, entry_hi, hwrena,
+             user_local, tlb_size, software_ip] = words;
+        Cop0 {
+            status, cause, epc, count, compare, bad_vaddr, error_epc, index, random,
+            entry_lo0, entry_lo1, context, page_mask, wired, entry_hi, hwrena,
+            user_local, tlb_size, software_ip,
+        }
+    }
+
+    /// State after a reset: bootstrap exception vectors and error level.
+    pub fn reset(&mut self) {
+        *self = Cop0 {
+            status: STATUS_BEV | STATUS_ERL,
+            ..Cop0::with_tlb(self.tlb_size)
+        };
+    }
+
+    /// Value of register `reg`, select `sel`, for `mfc0`.
+    pub fn read(&self, reg: u32, sel: u32) -> u32 {
+        match (reg, sel) {
+            (INDEX, 0) => self.index,
+            (RANDOM, 0) => self.random,
+            (ENTRY_LO0, 0) => self.entry_lo0,
+            (ENTRY_LO1, 0) => self.entry_lo1,
+            (CONTEXT, 0) => self.context,
+            (CONTEXT, 2) => self.user_local,
+            (PAGE_MASK, 0) => self.page_mask,
+            (WIRED, 0) => self.wired,
+            (HWRENA, 0) => self.hwrena,
+            (ENTRY_HI, 0) => self.entry_hi,
+            (BAD_VADDR, 0) => self.bad_vaddr,
+            (COUNT, 0) => self.count,
+            (COMPARE, 0) => self.compare,
+            (STATUS, 0) => self.status,
+            (CAUSE, 0) => self.cause,
+            (EPC, 0) => self.epc,
+            (PRID, 0) => 0x00019300, // MIPS 24K
+            (ERROR_EPC, 0) => self.error_epc,
+            // release 2 of the architecture, Config1 is present, with the MMU
+            // type and size when there is a TLB, then Config2 and Config3
+            // telling that UserLocal is implemented
+            (CONFIG, 0) => 1 << 31 | 1 << 10 | if self.tlb_size > 0 { 1 << 7 } else { 0 },
+            (CONFIG, 1) => 1 << 31 | self.tlb_size.saturating_sub(1) << 25,
+            (CONFIG, 2) => 1 << 31,
+            (CONFIG, 3) => 1 << 13,
+            _ => 0,
+        }
+    }
+
+    /// Writes register `reg`, select `sel`, for `mtc0`. Read-only bits are kept.
+    pub fn write(&mut self, reg: u32, sel: u32, value: u32) {
+        match (reg, sel) {
+            (INDEX, 0) => self.index = (self.index & INDEX_PROBE_FAILURE) | (value & 0x3f),
+            (ENTRY_LO0, 0) => self.entry_lo0 = value & 0x3fffffff,
+            (ENTRY_LO1, 0) => self.entry_lo1 = value & 0x3fffffff,
+            (CONTEXT, 0) => self.context = (self.context & 0x7fffff) | (value & 0xff800000),
+            (CONTEXT, 2) => self.user_local = value,
+            (PAGE_MASK, 0) => self.page_mask = value & 0x1fffe000,
+            (WIRED, 0) => {
+                self.wired = value & 0x3f;
+                self.random = self.tlb_size.saturating_sub(1);
+            },
+            (HWRENA, 0) => self.hwrena = value & (0xf | 1 << 29),
+            (ENTRY_HI, 0) => self.entry_hi = value & (ENTRY_HI_VPN2 | ENTRY_HI_ASID),
+            (COUNT, 0) => self.count = value,
+            (COMPARE, 0) => {
+                // writing Compare acknowledges the timer interrupt
+                self.compare = value;
+                self.cause &= !(CAUSE_TI | TIMER_IP);
+            },
+            (STATUS, 0) => self.status = value & STATUS_WRITABLE,
+            (CAUSE, 0) => {
+                self.software_ip = value & CAUSE_SOFTWARE_IP;
+                self.cause = (self.cause & !CAUSE_SOFTWARE_IP) | self.software_ip;
+            },
+            (EPC, 0) => self.epc = value,
+            (ERROR_EPC, 0) => self.error_epc = value,
+            _ => {},
+        }
+    }
+
+    /// Advances Count by one instruction, raising the timer interrupt when it
+    /// reaches Compare, and Random between Wired and the last TLB entry.
+    pub fn tick(&mut self) {
+        self.count = self.count.wrapping_add(1);
+        if self.count == self.compare {
+            self.cause |= CAUSE_TI;
+        }
+        if self.tlb_size > 0 {
+            self.random = if self.random <= self.wired { self.tlb_size - 1 } else { self.random - 1 };
+        }
+    }
+
+    /// Updates the Cause IP bits from the interrupt lines of the devices.
+    pub fn set_lines(&mut self, lines: u32) {
+        let timer = if self.cause & CAUSE_TI != 0 { TIMER_IP } else { 0 };
+        let ip = self.software_ip | ((lines & 0xff) << 8) | timer;
+        self.cause = (self.cause & !CAUSE_IP) | ip;
+    }
+
+    /// Interrupts both pending in Cause and enabled in Status.
+    pub fn pending_interrupts(&self) -> u32 {
+        self.cause & self.status & STATUS_IM
+    }
+
+    pub fn interrupts_enabled(&self) -> bool {
+        self.status & (STATUS_IE | STATUS_EXL | STATUS_ERL) == STATUS_IE
+    }
+
+    /// Whether the processor runs in user mode, outside of exceptions.
+    pub fn user_mode(&self) -> bool {
+        self.status & (STATUS_KSU | STATUS_EXL | STATUS_ERL) == STATUS_USER
+    }
+
+    /// Whether `rdhwr` can read hardware register `reg`.
+    pub fn hardware_register_enabled(&self, reg: u32) -> bool {
+        !self.user_mode() || self.hwrena & (1 << reg) != 0
+    }
+
+    /// Whether the privileged instructions are allowed.
+    pub fn coprocessor_usable(&self) -> bool {
+        !self.user_mode() || self.status & STATUS_CU0 != 0
+    }
+
+    /// Enters exception mode and returns the address of the handler. `pc` is
+    /// the address of the instruction interrupted, in the delay slot of a
+    /// branch if `delay_slot`.
+    pub fn enter_exception(&mut self, code: u32, pc: u32, delay_slot: bool) -> u32 {
+        if self.status & STATUS_EXL == 0 {
+            if delay_slot {
+                self.epc = pc.wrapping_sub(4);
+                self.cause |= CAUSE_BD;
+            } else {
+                self.epc = pc;
+                self.cause &= !CAUSE_BD;
+            }
+        }
+        self.cause = (self.cause & !CAUSE_EXC_CODE) | (code << 2);
+        self.status |= STATUS_EXL;
+
+        self.exception_vector()
+    }
+
+    /// Address of the general exception handler, bootstrap while BEV is set.
+    pub fn exception_vector(&self) -> u32 {
+        if self.status & STATUS_BEV != 0 { 0xbfc00380 } else { 0x80000180 }
+    }
+
+    /// Enters exception mode for an exception raised by an instruction,
+    /// recording the faulting address, and returns the address of the handler.
+    pub fn raise(&mut self, exception: Exception, pc: u32, delay_slot: bool) -> u32 {
+        if let Some(vaddr) = exception.bad_vaddr {
+            self.bad_vaddr = vaddr;
+            if let EXC_MOD | EXC_TLBL | EXC_TLBS = exception.code {
+                // the handler finds the page table entry from Context
+                self.context = (self.context & 0xff800000) | ((vaddr >> 9) & 0x7ffff0);
+                self.entry_hi = (vaddr & ENTRY_HI_VPN2) | (self.entry_hi & ENTRY_HI_ASID);
+            }
+        }
+        let refill = exception.refill && self.status & STATUS_EXL == 0;
+        let vector = self.enter_exception(exception.code, pc, delay_slot);
+        if refill { vector - 0x180 } else { vector }
+    }
+
+    /// Leaves exception mode, or the error level first, for `eret` and
+    /// returns the address to resume at.
+    pub fn exception_return(&mut self) -> u32 {
+        if self.status & STATUS_ERL != 0 {
+            self.status &= !STATUS_ERL;
+            self.error_epc
+        } else {
+            self.status &= !STATUS_EXL;
+            self.epc
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_keeps_the_writable_fields() {
+        let mut cop0 = Cop0::new();
+        cop0.write(STATUS, 0, 0xffff_ffff);
+        assert_eq!(cop0.read(STATUS, 0), STATUS_CU0 | STATUS_BEV | STATUS_IM | STATUS_KSU | STATUS_ERL | STATUS_EXL | STATUS_IE);
+        cop0.write(STATUS, 0, STATUS_USER | 1 << 27 | 1 << 18);
+        assert_eq!(cop0.read(STATUS, 0), STATUS_USER);
+    }
+}
@@ -0,0 +1,228 @@
+//! Code coverage of a program, collected as a trace observer.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use cpu::Cpu;
+use instruction::Instruction;
+use trace::{Observer, Record};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchCoverage {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+impl BranchCoverage {
+    /// Number of directions taken at least once, out of 2.
+    pub fn covered(&self) -> u32 {
+        (self.taken != 0) as u32 + (self.not_taken != 0) as u32
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    pub executed: BTreeMap<u32, u64>,
+    pub branches: BTreeMap<u32, BranchCoverage>,
+}
+
+#[derive(Debug, Default)]
+struct SourceFile {
+    lines: BTreeMap<u32, u64>,
+    branches: Vec<(u32, Option<BranchCoverage>)>, // line, coverage if executed
+    functions: Vec<(u32, String, u64)>, // line, name, count
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    pub fn count(&self, pc: u32) -> u64 {
+        self.executed.get(&pc).cloned().unwrap_or(0)
+    }
+
+    /// lcov tracefile of the source files in the line information of the program.
+    pub fn write_lcov<W: Write>(&self, writer: &mut W, cpu: &Cpu, test_name: &str) -> io::Result<()> {
+        let mut files: BTreeMap<&str, SourceFile> = BTreeMap::new();
+
+        for range in cpu.lines.ranges() {
+            let file = files.entry(&cpu.lines.files[range.file]).or_default();
+            let mut addr = range.start;
+            while addr < range.end {
+                let count = file.lines.entry(range.line).or_insert(0);
+                *count = (*count).max(self.count(addr));
+
+                let inst = Instruction::from_word(cpu.peek_word(addr).unwrap_or(0), cpu.isa);
+                if inst.is_conditional_branch() {
+                    let coverage = self.branches.get(&addr).cloned();
+                    file.branches.push((range.line, coverage));
+                }
+                addr = addr.wrapping_add(4);
+            }
+        }
+
+        for index in functions(cpu) {
+            let symbol = cpu.symbols.iter().nth(index).unwrap();
+            if let Some((path, line)) = cpu.lines.lookup(symbol.addr) {
+                if let Some(file) = files.get_mut(path) {
+                    file.functions.push((line, symbol.name.clone(), self.count(symbol.addr)));
+                }
+            }
+        }
+
+        for (path, file) in files {
+            writeln!(writer, "TN:{}", test_name)?;
+            writeln!(writer, "SF:{}", path)?;
+
+            for &(line, ref name, _) in &file.functions {
+                writeln!(writer, "FN:{},{}", line, name)?;
+            }
+            for &(_, ref name, count) in &file.functions {
+                writeln!(writer, "FNDA:{},{}", count, name)?;
+            }
+            writeln!(writer, "FNF:{}", file.functions.len())?;
+            writeln!(writer, "FNH:{}", file.functions.iter().filter(|f| f.2 != 0).count())?;
+
+            let mut block = 0;
+            let mut last_line = 0;
+            for &(line, coverage) in &file.branches {
+                block = if line == last_line { block + 1 } else { 0 };
+                last_line = line;
+                match coverage {
+                    Some(coverage) => {
+                        writeln!(writer, "BRDA:{},{},0,{}", line, block, coverage.taken)?;
+                        writeln!(writer, "BRDA:{},{},1,{}", line, block, coverage.not_taken)?;
+                    },
+                    None => {
+                        writeln!(writer, "BRDA:{},{},0,-", line, block)?;
+                        writeln!(writer, "BRDA:{},{},1,-", line, block)?;
+                    },
+                }
+            }
+            let branches_hit: u32 = file.branches.iter().filter_map(|b| b.1).map(|b| b.covered()).sum();
+            writeln!(writer, "BRF:{}", file.branches.len() * 2)?;
+            writeln!(writer, "BRH:{}", branches_hit)?;
+
+            for (line, count) in &file.lines {
+                writeln!(writer, "DA:{},{}", line, count)?;
+            }
+            writeln!(writer, "LF:{}", file.lines.len())?;
+            writeln!(writer, "LH:{}", file.lines.values().filter(|&&count| count != 0).count())?;
+            writeln!(writer, "end_of_record")?;
+        }
+        Ok(())
+    }
+
+    /// Coverage per symbol and per address, for programs without line information.
+    pub fn write_report<W: Write>(&self, writer: &mut W, cpu: &Cpu) -> io::Result<()> {
+        let last_pc = self.executed.keys().next_back().map_or(0, |&pc| pc.wrapping_add(4));
+
+        let mut uncovered = Vec::new();
+        let mut partial = Vec::new();
+        let (mut total_instructions, mut total_executed) = (0, 0);
+        let (mut total_directions, mut total_covered) = (0, 0);
+
+        writeln!(writer, "{:>20} {:>20}  symbol", "instructions", "branch directions")?;
+        for index in functions(cpu) {
+            let symbol = cpu.symbols.iter().nth(index).unwrap();
+            let (start, end) = cpu.symbols.extent(index, last_pc.max(symbol.addr.wrapping_add(4)));
+
+            let (mut instructions, mut executed, mut directions, mut covered) = (0, 0, 0, 0);
+            let mut addr = start;
+            while addr < end {
+                instructions += 1;
+                if self.count(addr) != 0 {
+                    executed += 1;
+                } else if uncovered.last().is_some_and(|&(_, last_end)| last_end == addr) {
+                    uncovered.last_mut().unwrap().1 = addr.wrapping_add(4);
+                } else {
+                    uncovered.push((addr, addr.wrapping_add(4)));
+                }
+
+                let inst = Instruction::from_word(cpu.peek_word(addr).unwrap_or(0), cpu.isa);
+                if inst.is_conditional_branch() {
+                    directions += 2;
+                    let coverage = self.branches.get(&addr).cloned().unwrap_or_default();
+                    covered += coverage.covered();
+                    if coverage.covered() == 1 {
+                        partial.push((addr, inst, coverage));
+                    }
+                }
+                addr = addr.wrapping_add(4);
+            }
+
+            writeln!(writer, "{:>8}/{:<8} {:>3}% {:>8}/{:<8} {:>3}%  {}",
+                     executed, instructions, percent(executed, instructions),
+                     covered, directions, percent(covered, directions),
+                     symbol.name)?;
+            total_instructions += instructions;
+            total_executed += executed;
+            total_directions += directions;
+            total_covered += covered;
+        }
+        writeln!(writer, "{:>8}/{:<8} {:>3}% {:>8}/{:<8} {:>3}%  total",
+                 total_executed, total_instructions, percent(total_executed, total_instructions),
+                 total_covered, total_directions, percent(total_covered, total_directions))?;
+
+        let outside = self.executed.keys().filter(|&&pc| cpu.symbols.lookup(pc).is_none()).count();
+        if outside != 0 {
+            writeln!(writer, "{} executed instructions are outside any symbol.", outside)?;
+        }
+
+        if !uncovered.is_empty() {
+            writeln!(writer, "\nNever executed:")?;
+            for (start, end) in uncovered {
+                writeln!(writer, "  {:#010x}-{:#010x}  {}", start, end.wrapping_sub(4), describe(cpu, start))?;
+            }
+        }
+        if !partial.is_empty() {
+            writeln!(writer, "\nBranches going one way only:")?;
+            for (addr, inst, coverage) in partial {
+                writeln!(writer, "  {:#010x} {:<24} taken {}, not taken {}  {}",
+                         addr, inst, coverage.taken, coverage.not_taken, describe(cpu, addr))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Observer for Coverage {
+    fn observe(&mut self, record: &Record, cpu: &Cpu) {
+        *self.executed.entry(record.pc).or_insert(0) += 1;
+
+        let inst = record.instruction(cpu.isa);
+        if let Some(target) = inst.branch_target(record.pc) {
+            let branch = self.branches.entry(record.pc).or_default();
+            if cpu.branch_destination(&inst) == target {
+                branch.taken += 1;
+            } else {
+                branch.not_taken += 1;
+            }
+        }
+    }
+}
+
+/// Indexes of the code symbols, without the labels inside a sized function.
+fn functions(cpu: &Cpu) -> Vec<usize> {
+    let mut functions = Vec::new();
+    let mut function_end = 0;
+    for (index, symbol) in cpu.symbols.iter().enumerate() {
+        if !symbol.code || symbol.addr < function_end {
+            continue;
+        }
+        functions.push(index);
+        if symbol.size != 0 {
+            function_end = symbol.addr.wrapping_add(symbol.size);
+        }
+    }
+    functions
+}
+
+fn percent(count: u32, total: u32) -> u32 {
+    (count * 100).checked_div(total).unwrap_or(100)
+}
+
+fn describe(cpu: &Cpu, addr: u32) -> String {
+    cpu.symbols.describe(addr).unwrap_or_default()
+}
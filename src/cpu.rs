@@ -1,21 +1,47 @@
-use std;
+use std::cell::RefCell;
 use std::collections::HashSet;
+use std::rc::Rc;
 use elf;
 
-use memory::Memory;
-use instruction::Instruction;
+use memory::{AccessKind, Memory};
+use cop0::{self, Cop0, Exception};
+use mmu::{self, Tlb};
+use instruction::{Instruction, InstructionClass, Isa};
+use history::{History, Step, Checkpoint};
+use input::Input;
+use loader::{Image, Region};
+use symbols::SymbolTable;
+use debug_line::LineTable;
+use trace::{self, Observer, Record, RegisterChange};
 use utils;
 
+/// Bytes watched by `ll`: a store to the linked line makes `sc` fail.
+const LINK_LINE_MASK: u32 = !0x1f;
+
 #[derive(Debug, Clone)]
 pub struct Cpu {
     registers: [u32; 31],
     pub hi: u32,
     pub lo: u32,
     pub pc: u32,
-    npc: u32,
+    pub npc: u32,
+    pub cop0: Cop0,
+    delay_slot: bool, // whether pc is in the delay slot of the previous instruction
+    forbidden_slot: bool, // whether pc follows a compact branch not taken
+    pub isa: Isa,
+    pub bare_metal: bool, // booted at the reset vector, the guest handles every exception
+    pub link: Option<u32>, // line linked by `ll`, None when the link bit is clear
+    pub tlb: Option<Tlb>, // None without MMU, the memory being flat
     pub memory: Memory,
+    pub regions: Vec<Region>, // loaded in memory, at physical addresses
     pub breakpoints: HashSet<u32>,
     waiting_breakpoint: Option<u32>,
+    pub instruction_count: u64,
+    pub history: Option<History>,
+    pub input: Input,
+    pub symbols: SymbolTable,
+    pub lines: LineTable,
+    pub observers: Vec<Rc<RefCell<dyn Observer>>>,
 }
 
 impl Cpu {
@@ -26,9 +52,23 @@ impl Cpu {
             lo: 0,
             pc: 0,
             npc: 4,
+            cop0: Cop0::new(),
+            delay_slot: false,
+            forbidden_slot: false,
+            isa: Isa::default(),
+            bare_metal: false,
+            link: None,
+            tlb: None,
             memory: Memory::new(),
+            regions: Vec::new(),
             breakpoints: HashSet::new(),
             waiting_breakpoint: None,
+            instruction_count: 0,
+            history: None,
+            input: Input::live(),
+            symbols: SymbolTable::new(),
+            lines: LineTable::new(),
+            observers: Vec::new(),
         }
     }
 
@@ -57,35 +97,320 @@ impl Cpu {
         self.lo = 0;
         self.pc = 0;
         self.npc = 4;
+        self.delay_slot = false;
+        self.forbidden_slot = false;
+        self.link = None;
+        match self.tlb {
+            Some(ref mut tlb) => {
+                tlb.clear();
+                self.cop0 = Cop0::with_tlb(tlb.len() as u32);
+            },
+            None => self.cop0 = Cop0::new(),
+        }
+        // the devices stay attached to the new memory
+        let bus = self.memory.bus.clone();
         self.memory = memory;
+        self.memory.bus = bus;
+        self.memory.bus.reset();
+        self.regions.clear();
+        self.instruction_count = 0;
+        if let Some(ref mut history) = self.history {
+            history.clear();
+        }
     }
 
     pub fn run(&mut self, single_step: bool, log: bool) -> Option<Signal> {
         loop {
+            if let Err(signal) = self.check_interrupts() {
+                return Some(signal);
+            }
+
             if self.breakpoints.contains(&self.pc) {
                 self.transfer_bp();
-                self.waiting_breakpoint = Some(self.pc);
-                self.breakpoints.remove(&self.pc);
-                return Some(Signal::Breakpoint(self.pc));
+                return Some(self.stop_on_breakpoint());
             }
 
-            let word = self.memory.get_word(self.pc);
-            let inst = Instruction::from_word(word);
+            let word = match self.translate(self.pc, AccessKind::Read) {
+                Ok(addr) => self.memory.get_word(addr),
+                Err(Signal::Exception(exception)) if self.has_exception_handler() => {
+                    self.take_exception(exception);
+                    continue;
+                },
+                Err(Signal::Exception(exception)) => return Some(self.unhandled_exception(exception)),
+                Err(signal) => return Some(signal),
+            };
+            let inst = Instruction::from_word(word, self.isa);
+            let pc = self.pc;
 
             if log {
                 println!("Executing (pc={:#x}): {}", self.pc, inst);
             }
 
-            let res = inst.apply(self);
+            let res = if self.history.is_some() || !self.observers.is_empty() {
+                let (step, registers) = self.begin_step();
+                let res = inst.apply(self);
+                self.end_step(step, registers, word, &res);
+                res
+            } else {
+                inst.apply(self)
+            };
+            self.instruction_count += 1;
+            // a branch likely not taken skips its delay slot, a compact branch has none
+            let falls_through = matches!(inst.class(), InstructionClass::Branch | InstructionClass::Jump) &&
+                self.pc == pc.wrapping_add(4);
+            self.delay_slot = falls_through && !inst.is_compact();
+            self.forbidden_slot = falls_through && inst.is_compact();
+            self.cop0.tick();
+            self.memory.bus.tick(1);
+            if let Err(err) = self.poll_stdin() {
+                return Some(Signal::Trap(err));
+            }
 
             self.transfer_bp();
 
-            if single_step {
-                return res.err()
+            // exceptions are handled by the guest
+            let signal = res.err().filter(|signal| !matches!(signal, Signal::Exception(_)));
+            if single_step || signal.is_some() {
+                return signal;
+            }
+        }
+    }
+
+    /// Delivers the pending interrupt if they are enabled, the handler is
+    /// then the next instruction to execute. Stops when there is no handler.
+    fn check_interrupts(&mut self) -> Result<(), Signal> {
+        self.cop0.set_lines(self.memory.bus.interrupts());
+        if self.cop0.interrupts_enabled() && self.cop0.pending_interrupts() != 0 {
+            if !self.has_exception_handler() {
+                return Err(self.unhandled_exception(Exception::new(cop0::EXC_INTERRUPT)));
+            }
+            let handler = self.cop0.enter_exception(cop0::EXC_INTERRUPT, self.pc, self.delay_slot);
+            self.pc = handler;
+            self.npc = handler.wrapping_add(4);
+            self.delay_slot = false;
+            self.forbidden_slot = false;
+            self.link = None;
+        }
+        Ok(())
+    }
+
+    /// Gives the next byte of stdin to the device waiting for one, through
+    /// the input to record and replay it.
+    fn poll_stdin(&mut self) -> Result<(), String> {
+        let device = match self.memory.bus.stdin_reader() {
+            Some(device) => device.clone(),
+            None => return Ok(()),
+        };
+        if let Some(byte) = self.input.poll_stdin(self.instruction_count)? {
+            device.borrow_mut().receive_stdin(byte);
+        }
+        Ok(())
+    }
+
+    /// Enables address translation with a TLB of `size` entries.
+    pub fn enable_mmu(&mut self, size: usize) {
+        self.tlb = Some(Tlb::new(size));
+        self.cop0 = Cop0::with_tlb(size as u32);
+    }
+
+    /// Physical address of the virtual address `vaddr`, or the exception
+    /// raised by the access.
+    pub fn translate(&self, vaddr: u32, kind: AccessKind) -> Result<u32, Signal> {
+        mmu::translate(&self.cop0, self.tlb.as_ref(), vaddr, kind).map_err(Signal::Exception)
+    }
+
+    /// Reads a byte at the virtual address `vaddr` as a load does, for the
+    /// syscalls.
+    pub fn load_byte(&self, vaddr: u32) -> Result<u8, Signal> {
+        let addr = self.translate(vaddr, AccessKind::Read)?;
+        Ok(self.memory.get_byte(addr))
+    }
+
+    /// Writes a byte at the virtual address `vaddr` as a store does, for the
+    /// store instructions, the syscalls and the debugger. The link of `ll`
+    /// is broken by the stores to its line.
+    pub fn store_byte(&mut self, vaddr: u32, byte: u8) -> Result<(), Signal> {
+        let addr = self.translate(vaddr, AccessKind::Write)?;
+        self.memory.set_byte(addr, byte);
+        self.monitor_store(addr);
+        Ok(())
+    }
+
+    pub fn store_half_word(&mut self, vaddr: u32, half_word: u16) -> Result<(), Signal> {
+        let addr = self.translate(vaddr, AccessKind::Write)?;
+        self.memory.set_half_word(addr, half_word);
+        self.monitor_store(addr);
+        Ok(())
+    }
+
+    pub fn store_word(&mut self, vaddr: u32, word: u32) -> Result<(), Signal> {
+        let addr = self.translate(vaddr, AccessKind::Write)?;
+        self.memory.set_word(addr, word);
+        self.monitor_store(addr);
+        Ok(())
+    }
+
+    /// Reads a byte at the virtual address `vaddr` without any side effect,
+    /// for the debugger and the other inspections.
+    pub fn peek_byte(&self, vaddr: u32) -> Result<u8, Signal> {
+        let addr = self.translate(vaddr, AccessKind::Read)?;
+        Ok(self.memory.peek_byte(addr))
+    }
+
+    pub fn peek_half_word(&self, vaddr: u32) -> Result<u16, Signal> {
+        let addr = self.translate(vaddr, AccessKind::Read)?;
+        Ok(self.memory.peek_half_word(addr))
+    }
+
+    pub fn peek_word(&self, vaddr: u32) -> Result<u32, Signal> {
+        let addr = self.translate(vaddr, AccessKind::Read)?;
+        Ok(self.memory.peek_word(addr))
+    }
+
+    /// Whether the guest has an exception handler: in bare-metal mode, or
+    /// when a file is loaded at the exception vector. Otherwise, delivering
+    /// an exception would run the empty memory there.
+    pub fn has_exception_handler(&self) -> bool {
+        let vector = self.load_address(self.cop0.exception_vector());
+        self.bare_metal || self.regions.iter().any(|region| region.start <= vector && vector <= region.last)
+    }
+
+    /// The signal stopping the emulator on an exception the guest has no
+    /// handler for, pc being left on the instruction that raised it.
+    pub fn unhandled_exception(&self, exception: Exception) -> Signal {
+        Signal::Trap(format!("{} without exception handler (pc={:#x})", exception, self.pc))
+    }
+
+    /// Delivers an exception raised by the instruction at pc, the handler is
+    /// then the next instruction to execute.
+    pub fn take_exception(&mut self, exception: Exception) {
+        let handler = self.cop0.raise(exception, self.pc, self.delay_slot);
+        self.pc = handler;
+        self.npc = handler.wrapping_add(4);
+        self.forbidden_slot = false;
+        self.link = None;
+    }
+
+    /// Whether pc is in the delay slot or the forbidden slot of the previous
+    /// instruction.
+    pub fn in_branch_slot(&self) -> bool {
+        self.delay_slot || self.forbidden_slot
+    }
+
+    /// Whether pc is in a delay slot and whether it is in a forbidden slot.
+    pub fn branch_slots(&self) -> (bool, bool) {
+        (self.delay_slot, self.forbidden_slot)
+    }
+
+    pub fn set_branch_slots(&mut self, delay_slot: bool, forbidden_slot: bool) {
+        self.delay_slot = delay_slot;
+        self.forbidden_slot = forbidden_slot;
+    }
+
+    /// Where the branch or jump `inst` that was just executed goes: after its
+    /// delay slot, or right away for a compact one.
+    pub fn branch_destination(&self, inst: &Instruction) -> u32 {
+        if inst.is_compact() { self.pc } else { self.npc }
+    }
+
+    /// Sets the link bit on the line of the physical address `addr`, for `ll`.
+    pub fn set_link(&mut self, addr: u32) {
+        self.link = Some(addr & LINK_LINE_MASK);
+    }
+
+    /// Clears the link bit, returns whether it was set on the line of the
+    /// physical address `addr`, for `sc`.
+    pub fn take_link(&mut self, addr: u32) -> bool {
+        self.link.take() == Some(addr & LINK_LINE_MASK)
+    }
+
+    /// A store to the physical address `addr` breaks the link on its line.
+    pub fn monitor_store(&mut self, addr: u32) {
+        if self.link == Some(addr & LINK_LINE_MASK) {
+            self.link = None;
+        }
+    }
+
+    /// Undo the last `n` executed instructions.
+    pub fn reverse(&mut self, n: u64) -> Option<Signal> {
+        self.transfer_bp();
+
+        let target = self.instruction_count.saturating_sub(n);
+        if let Some(checkpoint) = self.history.as_mut().and_then(|h| h.rewind_to_checkpoint(target)) {
+            self.restore_checkpoint(checkpoint);
+        }
+
+        while self.instruction_count > target {
+            if !self.undo_step() {
+                return Some(Signal::StartOfHistory);
+            }
+        }
+        None
+    }
+
+    /// Undo instructions until a breakpoint is reached.
+    pub fn reverse_continue(&mut self) -> Option<Signal> {
+        self.transfer_bp();
+
+        loop {
+            if !self.undo_step() {
+                return Some(Signal::StartOfHistory);
+            }
+            if self.breakpoints.contains(&self.pc) {
+                return Some(self.stop_on_breakpoint());
             }
+        }
+    }
 
-            if let Err(signal) = res {
-                return Some(signal)
+    /// Undo instructions until the call of the current function.
+    pub fn reverse_finish(&mut self) -> Option<Signal> {
+        self.transfer_bp();
+
+        let mut depth = 0;
+        loop {
+            let next = self.pc;
+            if !self.undo_step() {
+                return Some(Signal::StartOfHistory);
+            }
+
+            // the history restores the TLB, pc is mapped as when it was executed
+            let inst = Instruction::from_word(self.peek_word(self.pc).unwrap_or(0), self.isa);
+            match inst {
+                Instruction::JR(31) | Instruction::JIC(31, 0) => depth += 1,
+                Instruction::JAL(_) | Instruction::JALR(_, _) | Instruction::JIALC(..) => {
+                    if depth == 0 {
+                        return None;
+                    }
+                    depth -= 1;
+                },
+                Instruction::BGEZAL(rs, _) | Instruction::BLTZAL(rs, _) |
+                Instruction::BGEZALL(rs, _) | Instruction::BLTZALL(rs, _) => {
+                    let rs_value = utils::u2i(self.get_register(rs));
+                    let taken = match inst {
+                        Instruction::BGEZAL(..) | Instruction::BGEZALL(..) => rs_value >= 0,
+                        _ => rs_value < 0,
+                    };
+                    if taken {
+                        if depth == 0 {
+                            return None;
+                        }
+                        depth -= 1;
+                    }
+                },
+                // a compact call is taken when it went to its target
+                Instruction::BALC(_) | Instruction::BEQZALC(..) | Instruction::BGEZALC(..) |
+                Instruction::BGTZALC(..) | Instruction::BLEZALC(..) | Instruction::BLTZALC(..) |
+                Instruction::BNEZALC(..) if inst.branch_target(self.pc) == Some(next) => {
+                    if depth == 0 {
+                        return None;
+                    }
+                    depth -= 1;
+                },
+                _ => {},
+            }
+
+            if self.breakpoints.contains(&self.pc) {
+                return Some(self.stop_on_breakpoint());
             }
         }
     }
@@ -103,6 +428,26 @@ impl Cpu {
         }
     }
 
+    /// Redirect execution to `addr`, dropping any branch pending in a delay slot.
+    pub fn jump(&mut self, addr: u32) {
+        self.transfer_bp();
+        self.pc = addr;
+        self.npc = addr.wrapping_add(4);
+    }
+
+    /// Breakpoint at the current pc that the next run will not stop on.
+    pub fn skipped_breakpoint(&self) -> Option<u32> {
+        self.waiting_breakpoint
+    }
+
+    /// Do not stop on the breakpoint at `pc` if it is the next instruction executed.
+    pub fn skip_breakpoint(&mut self, pc: u32) {
+        self.transfer_bp();
+        if self.breakpoints.remove(&pc) {
+            self.waiting_breakpoint = Some(pc);
+        }
+    }
+
     pub fn add_or_remove_breakpoint(&mut self, pc: u32) {
         if !self.breakpoints.remove(&pc) {
             self.breakpoints.insert(pc);
@@ -110,38 +455,211 @@ impl Cpu {
     }
 
     pub fn load_elf(&mut self, file: elf::File) -> Result<(), String> {
-        let mut memory = Memory::new();
+        let image = Image::from_elf(&file)?;
+        // line information is optional, a malformed one is ignored
+        let lines = LineTable::from_elf(&file).unwrap_or_default();
 
-        for section in file.sections {
-            if section.shdr.shtype.0 == 1 { //PT_LOAD
-                let addr = section.shdr.addr as usize;
-                let size = section.data.len();
+        self.load_image(&image, "program")?;
+        self.symbols = SymbolTable::from_elf(&file);
+        self.lines = lines;
+        Ok(())
+    }
+
+    /// Loads the segments of a program image in a new memory, starting at
+    /// its entry point or at its first segment.
+    pub fn load_image(&mut self, image: &Image, name: &str) -> Result<(), String> {
+        let entry = image.start().ok_or_else(|| format!("{} is empty.", name))?;
 
-                if addr + size > std::u32::MAX as usize {
-                    return Err(format!("Section {} is too big.", section.shdr.name));
-                }
+        self.reset_with_memory(Memory::new());
+        self.add_image(image, name)?;
+        self.symbols = SymbolTable::new();
+        self.lines = LineTable::new();
+        self.pc = entry & !0b11;
+        self.npc = self.pc.wrapping_add(4);
+        Ok(())
+    }
 
-                for i in 0..size {
-                    memory.set_byte((addr + i) as u32, section.data[i]);
-                }
+    /// Maps the segments of an image in the current memory, next to the
+    /// ones already loaded. Nothing is written when a segment overlaps one
+    /// of them.
+    pub fn add_image(&mut self, image: &Image, name: &str) -> Result<(), String> {
+        let mut regions = Vec::new();
+        for segment in image.segments.iter().filter(|segment| !segment.data.is_empty()) {
+            if segment.addr as usize + segment.data.len() > u32::MAX as usize + 1 {
+                return Err(format!("{}: segment at {:#x} is too big.", name, segment.addr));
             }
+            let start = self.load_address(segment.addr);
+            let region = Region {
+                name: name.to_string(),
+                start,
+                last: start.wrapping_add(segment.data.len() as u32 - 1),
+            };
+            if let Some(other) = self.regions.iter().chain(&regions).find(|other| other.overlaps(&region)) {
+                return Err(format!("{}: {} overlaps {}: {}.", name, region, other.name, other));
+            }
+            regions.push(region);
         }
 
-        if file.ehdr.elftype.0 != 2 {
-            return Err("File is not executable.".to_string());
+        for (segment, region) in image.segments.iter().filter(|segment| !segment.data.is_empty()).zip(&regions) {
+            for (i, &byte) in segment.data.iter().enumerate() {
+                self.memory.set_byte(region.start.wrapping_add(i as u32), byte);
+            }
         }
-        if file.ehdr.machine.0 != 8 {
-            return Err("File is not MIPS.".to_string());
+        self.regions.extend(regions);
+        Ok(())
+    }
+
+    /// Starts over at the reset vector, in kernel mode with the reset state
+    /// of COP0, keeping the memory.
+    pub fn boot(&mut self) {
+        self.transfer_bp();
+        self.cop0.reset();
+        self.delay_slot = false;
+        self.forbidden_slot = false;
+        self.link = None;
+        self.pc = cop0::RESET_VECTOR;
+        self.npc = self.pc.wrapping_add(4);
+    }
+
+    /// Where a program loaded at `addr` is put in memory: with an MMU, kseg0
+    /// and kseg1 go to their physical address.
+    fn load_address(&self, addr: u32) -> u32 {
+        match self.tlb {
+            Some(_) => mmu::unmapped(addr).unwrap_or(addr),
+            None => addr,
         }
+    }
 
-        let entry = (file.ehdr.entry / 4) * 4;
-        
-        self.reset_with_memory(memory);
+    fn begin_step(&mut self) -> (Step, [u32; 31]) {
+        let count = self.instruction_count;
+        if self.history.as_ref().is_some_and(|h| h.wants_checkpoint(count)) {
+            let checkpoint = Checkpoint {
+                count,
+                registers: self.registers,
+                hi: self.hi,
+                lo: self.lo,
+                pc: self.pc,
+                npc: self.npc,
+                cop0: self.cop0,
+                delay_slot: self.delay_slot,
+                forbidden_slot: self.forbidden_slot,
+                link: self.link,
+                tlb: self.tlb.clone(),
+                memory: self.memory.clone(),
+            };
+            self.history.as_mut().unwrap().push_checkpoint(checkpoint);
+        }
 
-        self.pc = entry as u32;
-        self.npc = self.pc + 4;
+        if self.history.is_some() {
+            self.memory.start_journal();
+        }
+        if !self.observers.is_empty() {
+            self.memory.start_access_log();
+        }
+        let step = Step {
+            pc: self.pc,
+            npc: self.npc,
+            hi: self.hi,
+            lo: self.lo,
+            cop0: self.cop0,
+            delay_slot: self.delay_slot,
+            forbidden_slot: self.forbidden_slot,
+            link: self.link,
+            tlb: None,
+            registers: Vec::new(),
+            memory: Vec::new(),
+        };
+        (step, self.registers)
+    }
 
-        Ok(())
+    fn end_step(&mut self, mut step: Step, registers: [u32; 31], word: u32, res: &Result<(), Signal>) {
+        let mut changes = Vec::new();
+        for (i, (&old, &new)) in registers.iter().zip(self.registers.iter()).enumerate() {
+            if old != new {
+                changes.push(RegisterChange { reg: i as u32 + 1, old, new });
+            }
+        }
+
+        if !self.observers.is_empty() {
+            if step.hi != self.hi {
+                changes.push(RegisterChange { reg: trace::HI, old: step.hi, new: self.hi });
+            }
+            if step.lo != self.lo {
+                changes.push(RegisterChange { reg: trace::LO, old: step.lo, new: self.lo });
+            }
+
+            let record = Record {
+                count: self.instruction_count,
+                pc: step.pc,
+                word,
+                registers: changes.clone(),
+                memory: self.memory.take_access_log(),
+                signal: res.clone().err(),
+            };
+            for observer in &self.observers {
+                observer.borrow_mut().observe(&record, self);
+            }
+        }
+
+        let count = self.instruction_count;
+        if let Some(ref mut history) = self.history {
+            step.memory = self.memory.take_journal();
+            step.tlb = self.tlb.as_mut().and_then(Tlb::take_last_write);
+            step.registers = changes
+                .iter()
+                .filter(|change| change.reg < 32)
+                .map(|change| (change.reg, change.old))
+                .collect();
+            history.push(count, step);
+        }
+    }
+
+    fn undo_step(&mut self) -> bool {
+        let step = match self.history.as_mut().and_then(History::pop) {
+            Some(step) => step,
+            None => return false,
+        };
+
+        for &(index, value) in &step.registers {
+            self.set_register(index, value);
+        }
+        for &(addr, byte) in step.memory.iter().rev() {
+            self.memory.set_byte(addr, byte);
+        }
+        self.hi = step.hi;
+        self.lo = step.lo;
+        self.pc = step.pc;
+        self.npc = step.npc;
+        self.cop0 = step.cop0;
+        self.delay_slot = step.delay_slot;
+        self.forbidden_slot = step.forbidden_slot;
+        self.link = step.link;
+        if let (Some((index, entry)), Some(tlb)) = (step.tlb, self.tlb.as_mut()) {
+            tlb.restore(index, entry);
+        }
+        self.instruction_count -= 1;
+        true
+    }
+
+    fn restore_checkpoint(&mut self, checkpoint: Checkpoint) {
+        self.registers = checkpoint.registers;
+        self.hi = checkpoint.hi;
+        self.lo = checkpoint.lo;
+        self.pc = checkpoint.pc;
+        self.npc = checkpoint.npc;
+        self.cop0 = checkpoint.cop0;
+        self.delay_slot = checkpoint.delay_slot;
+        self.forbidden_slot = checkpoint.forbidden_slot;
+        self.link = checkpoint.link;
+        self.tlb = checkpoint.tlb;
+        self.memory = checkpoint.memory;
+        self.instruction_count = checkpoint.count;
+    }
+
+    fn stop_on_breakpoint(&mut self) -> Signal {
+        self.waiting_breakpoint = Some(self.pc);
+        self.breakpoints.remove(&self.pc);
+        Signal::Breakpoint(self.pc)
     }
 
     fn transfer_bp(&mut self) {
@@ -161,7 +679,9 @@ pub enum PCOperation {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Signal {
     Trap(String), // TODO add an enum
+    Exception(Exception), // delivered to the guest, only seen by the observers
     Breakpoint(u32), // the bp pc
+    StartOfHistory,
     Exit,
 }
 
@@ -171,7 +691,40 @@ impl fmt::Display for Signal {
         match *self {
             Signal::Trap(ref reason) => write!(f, "Trapped on {}.", reason),
             Signal::Breakpoint(pc) => write!(f, "Stopped on breakpoint (pc={:#x}).", pc),
+            Signal::Exception(ref exception) => write!(f, "Exception {}.", exception.code),
+            Signal::StartOfHistory => write!(f, "Reached the start of the recorded history."),
             Signal::Exit => write!(f, "Cpu halted.")
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kuseg_program() -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.enable_mmu(16);
+        cpu.load_image(&Image::raw(vec![0; 8], 0x0040_0000), "program").unwrap();
+        cpu
+    }
+
+    #[test]
+    fn kuseg_miss_stops_without_exception_handler() {
+        let mut cpu = kuseg_program();
+        let signal = Signal::Trap("TLB miss on load at 0x00400000 without exception handler (pc=0x400000)".to_string());
+        assert_eq!(cpu.run(false, false), Some(signal));
+        assert_eq!(cpu.pc, 0x0040_0000);
+        assert_eq!(cpu.instruction_count, 0);
+    }
+
+    #[test]
+    fn kuseg_miss_enters_the_refill_handler() {
+        let mut cpu = kuseg_program();
+        cpu.add_image(&Image::raw(vec![0; 0x200], 0x8000_0000), "handler").unwrap();
+        assert_eq!(cpu.run(true, false), None);
+        assert_eq!(cpu.pc, 0x8000_0004);
+        assert_eq!(cpu.cop0.epc, 0x0040_0000);
+        assert_eq!(cpu.cop0.bad_vaddr, 0x0040_0000);
+    }
+}
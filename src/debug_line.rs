@@ -0,0 +1,355 @@
+//! Address to source line mapping, read from the DWARF `.debug_line`
+//! section (versions 2 to 5, 32-bit DWARF only).
+
+use elf;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: u32,
+    pub end: u32, // excluded
+    pub file: usize, // index in `LineTable::files`
+    pub line: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LineTable {
+    pub files: Vec<String>,
+    ranges: Vec<LineRange>,
+}
+
+impl LineTable {
+    pub fn new() -> LineTable {
+        LineTable::default()
+    }
+
+    pub fn from_elf(file: &elf::File) -> Result<LineTable, String> {
+        let section = |name: &str| file.sections.iter()
+            .find(|section| section.shdr.name == name)
+            .map(|section| &section.data[..]);
+
+        match section(".debug_line") {
+            Some(data) => parse(data, section(".debug_str"), section(".debug_line_str")),
+            None => Ok(LineTable::new()),
+        }
+    }
+
+    /// Adds the lines of another program.
+    pub fn extend(&mut self, other: LineTable) {
+        let files: Vec<usize> = other.files.into_iter().map(|file| self.file_index(file)).collect();
+        for range in other.ranges {
+            self.ranges.push(LineRange { file: files[range.file], ..range });
+        }
+        self.ranges.sort_by_key(|range| range.start);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Ranges sorted by start address.
+    pub fn ranges(&self) -> &[LineRange] {
+        &self.ranges
+    }
+
+    /// Source file and line of the instruction at `addr`.
+    pub fn lookup(&self, addr: u32) -> Option<(&str, u32)> {
+        let index = match self.ranges.binary_search_by_key(&addr, |range| range.start) {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+
+        let range = &self.ranges[index];
+        if addr < range.end {
+            Some((&self.files[range.file], range.line))
+        } else {
+            None
+        }
+    }
+
+    fn file_index(&mut self, path: String) -> usize {
+        match self.files.iter().position(|file| *file == path) {
+            Some(index) => index,
+            None => {
+                self.files.push(path);
+                self.files.len() - 1
+            },
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> Result<u8, String> {
+        let byte = *self.data.get(self.pos).ok_or("Truncated line program.")?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(self.u8()? as u16 | (self.u8()? as u16) << 8)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(self.u16()? as u32 | (self.u16()? as u32) << 16)
+    }
+
+    fn uleb(&mut self) -> Result<u64, String> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn sleb(&mut self) -> Result<i64, String> {
+        let mut value = 0i64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                return Ok(value);
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let end = self.data[self.pos..].iter()
+            .position(|&byte| byte == 0)
+            .ok_or("Unterminated string.")?;
+        let s = String::from_utf8_lossy(&self.data[self.pos..self.pos + end]).into_owned();
+        self.pos += end + 1;
+        Ok(s)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), String> {
+        if self.pos + n > self.data.len() {
+            return Err("Truncated line program.".to_string());
+        }
+        self.pos += n;
+        Ok(())
+    }
+}
+
+fn string_at(section: Option<&[u8]>, offset: u32) -> Result<String, String> {
+    let data = section.ok_or("Missing string section.")?;
+    let mut reader = Reader { data, pos: offset as usize };
+    if reader.pos >= data.len() {
+        return Err("Invalid string offset.".to_string());
+    }
+    reader.string()
+}
+
+/// Reads a DWARF 5 directory or file name entry, as `(path, directory index)`.
+fn read_entry(reader: &mut Reader, formats: &[(u64, u64)], debug_str: Option<&[u8]>, line_str: Option<&[u8]>)
+    -> Result<(String, u64), String>
+{
+    const DW_LNCT_PATH: u64 = 1;
+    const DW_LNCT_DIRECTORY_INDEX: u64 = 2;
+
+    let mut path = String::new();
+    let mut directory = 0;
+    for &(content, form) in formats {
+        let (string, number) = match form {
+            0x08 => (Some(reader.string()?), 0), // string
+            0x0e => (Some(string_at(debug_str, reader.u32()?)?), 0), // strp
+            0x1f => (Some(string_at(line_str, reader.u32()?)?), 0), // line_strp
+            0x0b => (None, reader.u8()? as u64), // data1
+            0x05 => (None, reader.u16()? as u64), // data2
+            0x06 => (None, reader.u32()? as u64), // data4
+            0x07 => (None, reader.u32()? as u64 | (reader.u32()? as u64) << 32), // data8
+            0x0f => (None, reader.uleb()?), // udata
+            0x1e => { reader.skip(16)?; (None, 0) }, // data16
+            0x09 => { let len = reader.uleb()? as usize; reader.skip(len)?; (None, 0) }, // block
+            form => return Err(format!("Unsupported form {:#x}.", form)),
+        };
+        match content {
+            DW_LNCT_PATH => path = string.unwrap_or_default(),
+            DW_LNCT_DIRECTORY_INDEX => directory = number,
+            _ => {},
+        }
+    }
+    Ok((path, directory))
+}
+
+fn join(directory: &str, name: &str) -> String {
+    if directory.is_empty() || name.starts_with('/') {
+        name.to_string()
+    } else {
+        format!("{}/{}", directory.trim_end_matches('/'), name)
+    }
+}
+
+fn parse(data: &[u8], debug_str: Option<&[u8]>, line_str: Option<&[u8]>) -> Result<LineTable, String> {
+    let mut table = LineTable::new();
+    let mut reader = Reader { data, pos: 0 };
+
+    while reader.pos < data.len() {
+        let unit_length = reader.u32()?;
+        if unit_length >= 0xfffffff0 {
+            return Err("64-bit DWARF is not supported.".to_string());
+        }
+        let unit_end = reader.pos + unit_length as usize;
+        if unit_end > data.len() {
+            return Err("Truncated line program.".to_string());
+        }
+
+        let version = reader.u16()?;
+        if !(2..=5).contains(&version) {
+            return Err(format!("Unsupported DWARF version {}.", version));
+        }
+        if version >= 5 {
+            reader.skip(2)?; // address and segment selector sizes
+        }
+        let header_length = reader.u32()?;
+        let program_start = reader.pos + header_length as usize;
+        let min_inst_length = reader.u8()? as u32;
+        if version >= 4 {
+            reader.u8()?; // maximum operations per instruction
+        }
+        reader.u8()?; // default is_stmt
+        let line_base = reader.u8()? as i8 as i64;
+        let line_range = reader.u8()?;
+        let opcode_base = reader.u8()?;
+        if line_range == 0 {
+            return Err("Invalid line range.".to_string());
+        }
+        let mut opcode_lengths = Vec::new();
+        for _ in 1..opcode_base {
+            opcode_lengths.push(reader.u8()?);
+        }
+
+        // file indexes of the unit mapped to the table ones
+        let mut files = Vec::new();
+        if version >= 5 {
+            let mut formats = Vec::new();
+            for _ in 0..reader.u8()? {
+                formats.push((reader.uleb()?, reader.uleb()?));
+            }
+            let mut directories = Vec::new();
+            for _ in 0..reader.uleb()? {
+                directories.push(read_entry(&mut reader, &formats, debug_str, line_str)?.0);
+            }
+
+            formats.clear();
+            for _ in 0..reader.u8()? {
+                formats.push((reader.uleb()?, reader.uleb()?));
+            }
+            for _ in 0..reader.uleb()? {
+                let (name, directory) = read_entry(&mut reader, &formats, debug_str, line_str)?;
+                let directory = directories.get(directory as usize).map_or("", |d| d.as_str());
+                files.push(table.file_index(join(directory, &name)));
+            }
+        } else {
+            let mut directories = Vec::new();
+            loop {
+                let directory = reader.string()?;
+                if directory.is_empty() {
+                    break;
+                }
+                directories.push(directory);
+            }
+
+            files.push(usize::MAX); // file indexes start at 1
+            loop {
+                let name = reader.string()?;
+                if name.is_empty() {
+                    break;
+                }
+                let directory = reader.uleb()? as usize;
+                reader.uleb()?; // modification time
+                reader.uleb()?; // length
+                let directory = if directory == 0 { "" } else { directories.get(directory - 1).map_or("", |d| d.as_str()) };
+                files.push(table.file_index(join(directory, &name)));
+            }
+        }
+
+        reader.pos = program_start;
+        let mut rows: Vec<(u32, usize, u32, bool)> = Vec::new(); // address, file, line, end of sequence
+        let mut address = 0u32;
+        let mut file = 1u64;
+        let mut line = 1i64;
+
+        while reader.pos < unit_end {
+            let mut emit = false;
+            let mut end_sequence = false;
+
+            match reader.u8()? {
+                0 => {
+                    let len = reader.uleb()? as usize;
+                    let next = reader.pos + len;
+                    match reader.u8()? {
+                        1 => { emit = true; end_sequence = true; },
+                        2 => address = reader.u32()?,
+                        3 => {
+                            let name = reader.string()?;
+                            files.push(table.file_index(name));
+                        },
+                        _ => {},
+                    }
+                    reader.pos = next;
+                },
+                opcode if opcode >= opcode_base => {
+                    let adjusted = opcode - opcode_base;
+                    address = address.wrapping_add((adjusted / line_range) as u32 * min_inst_length);
+                    line += line_base + (adjusted % line_range) as i64;
+                    emit = true;
+                },
+                1 => emit = true,
+                2 => address = address.wrapping_add(reader.uleb()? as u32 * min_inst_length),
+                3 => line += reader.sleb()?,
+                4 => file = reader.uleb()?,
+                8 => {
+                    let adjusted = (255 - opcode_base) / line_range;
+                    address = address.wrapping_add(adjusted as u32 * min_inst_length);
+                },
+                9 => address = address.wrapping_add(reader.u16()? as u32),
+                opcode => {
+                    for _ in 0..opcode_lengths[opcode as usize - 1] {
+                        reader.uleb()?;
+                    }
+                },
+            }
+
+            if emit {
+                let file = files.get(file as usize).cloned().unwrap_or(usize::MAX);
+                rows.push((address, file, line as u32, end_sequence));
+            }
+            if end_sequence {
+                address = 0;
+                file = 1;
+                line = 1;
+            }
+        }
+
+        for pair in rows.windows(2) {
+            let (start, file, line, end_sequence) = pair[0];
+            let end = pair[1].0;
+            if !end_sequence && end > start && file != usize::MAX {
+                table.ranges.push(LineRange { start, end, file, line });
+            }
+        }
+        reader.pos = unit_end;
+    }
+
+    table.ranges.sort_by_key(|range| range.start);
+    Ok(table)
+}
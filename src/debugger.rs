@@ -1,15 +1,15 @@
 use std::io::{self, Write};
 use std::collections::HashMap;
-use std::path::Path;
-
-use elf;
-
 use lib_mips_emu::cpu::Cpu;
+use lib_mips_emu::device::bitmap::BitmapConfig;
+use lib_mips_emu::loader::{self, LoadOptions};
 
 pub struct Debugger {
-    cpu: Cpu,
+    pub cpu: Cpu,
     log: bool,
     saved_cpu: Option<Cpu>,
+    pub bitmap: BitmapConfig,
+    pub load_options: LoadOptions,
 }
 
 impl Debugger {
@@ -17,7 +17,9 @@ impl Debugger {
         Debugger {
            cpu,
            log: false,
-           saved_cpu: None
+           saved_cpu: None,
+           bitmap: BitmapConfig::default(),
+           load_options: LoadOptions::default(),
         }
     }
 
@@ -58,6 +60,18 @@ impl Debugger {
         cmds.insert("log", commands::log);
         cmds.insert("breakpoint", commands::breakpoint);
         cmds.insert("b", commands::breakpoint);
+        cmds.insert("set", commands::set);
+        cmds.insert("jump", commands::jump);
+        cmds.insert("reverse-step", commands::reverse_step);
+        cmds.insert("rs", commands::reverse_step);
+        cmds.insert("reverse-continue", commands::reverse_continue);
+        cmds.insert("rc", commands::reverse_continue);
+        cmds.insert("reverse-finish", commands::reverse_finish);
+        cmds.insert("history", commands::history);
+        cmds.insert("save", commands::save);
+        cmds.insert("restore", commands::restore);
+        cmds.insert("bitmap", commands::bitmap);
+        cmds.insert("tlb", commands::tlb);
 
         if let Some(cmd_func) = cmds.get(cmd) {
             if let Err(err) = cmd_func(self, args) {
@@ -66,9 +80,8 @@ impl Debugger {
         }
     }
 
-    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<(), String> {
-        let elf_file = elf::File::open_path(path).expect("Can't read elf.");
-        self.cpu.load_elf(elf_file)?;
+    pub fn load(&mut self, paths: &[&str]) -> Result<(), String> {
+        loader::load(&mut self.cpu, paths, &self.load_options)?;
         self.saved_cpu = Some(self.cpu.clone());
         Ok(())
     }
@@ -78,7 +91,11 @@ mod commands {
     use std::collections::HashMap;
     use regex::Regex;
     use super::Debugger;
+    use super::expr::{self, Location, Width};
     use lib_mips_emu::cpu::Signal;
+    use lib_mips_emu::device::bitmap::BitmapDisplay;
+    use lib_mips_emu::history::{self, History};
+    use lib_mips_emu::snapshot;
 
     macro_rules! expect_n_args {
         ($n:expr, $args:expr) => {
@@ -114,7 +131,7 @@ mod commands {
         static ref REGISTER_DIRECT_REGEX: Regex = Regex::new(r"\$([0-9]{1,2})").unwrap();
         static ref REGISTER_ALIAS_REGEX: Regex = Regex::new(r"\$([a-zA-Z0-9]+)").unwrap();
         static ref MEMORY_REGEX: Regex = Regex::new(r"0x([a-fA-F0-9]{0,8})").unwrap();
-        static ref REGISTER_ALIASES: HashMap<&'static str, u32> = {
+        pub static ref REGISTER_ALIASES: HashMap<&'static str, u32> = {
             let mut map = HashMap::new();
             map.insert("zero", 0u32);
             map.insert("at", 1);
@@ -156,23 +173,40 @@ mod commands {
 
     pub fn help(_: &mut Debugger, _: Vec<&str>) -> Result<(), String> {
         println!("Debugger help:");
-        println!("  load <path> - load elf file");
+        println!("  load <path> [<path>[@<address>]...] - load an ELF, raw, Intel HEX or S-record file, and more files next to it");
         println!("  restart - restart the current program");
+        println!("  save <path> - save the machine state to a snapshot file");
+        println!("  restore <path> - restore the machine state from a snapshot file");
         println!("  r[egisters] - print value of all registers");
         println!("  s[tep] - execute the next instruction");
         println!("  c[ontinue] - run the program until breakpoint/exit");
+        println!("  r[everse-]s[tep] [n] - undo the last n instructions");
+        println!("  r[everse-]c[ontinue] - go back until the previous breakpoint");
+        println!("  reverse-finish - go back to the call of the current function");
+        println!("  history - print the state of the execution history");
+        println!("  history on [budget] [interval] - record up to budget instructions, with a checkpoint every interval");
+        println!("  history off - stop recording the execution history");
         println!("  b[reakpoint] - list breakpoints");
         println!("  b[reakpoint] 0xXXXXXXXX - add/remove breakpoint");
         println!("  print $XX - print register");
         println!("  print 0xXXXXXXXX - print memory byre");
         println!("  log [on|off] - (de)activate the execution logging");
+        println!("  set $XX = <expr> - write a register ($pc, $hi and $lo included)");
+        println!("  set mem{{8,16,32}}[<expr>] = <expr> - write memory");
+        println!("  set mem{{8,16,32}}[<expr>] = {{<expr>, ...}} - write an array to memory");
+        println!("  set mem8[<expr>] = \"text\" - write a nul-terminated string to memory");
+        println!("  jump <expr> - resume execution at the given address");
+        println!("  bitmap FILE - write the bitmap display as a PPM or PNG image");
+        println!("  tlb - print the valid TLB entries");
         Ok(())
     }
 
     pub fn load(dbg: &mut Debugger, args: Vec<&str>) -> Result<(), String> {
-        expect_n_args!(1, args);
+        if args.is_empty() {
+            return Err("Expected at least 1 argument (given 0)".to_string());
+        }
 
-        dbg.load(args[0])
+        dbg.load(&args)
     }
 
     pub fn restart(dbg: &mut Debugger, args: Vec<&str>) -> Result<(), String> {
@@ -186,12 +220,65 @@ mod commands {
         }
     }
 
+    pub fn save(dbg: &mut Debugger, args: Vec<&str>) -> Result<(), String> {
+        expect_n_args!(1, args);
+
+        snapshot::save_to_path(&dbg.cpu, args[0])
+    }
+
+    pub fn restore(dbg: &mut Debugger, args: Vec<&str>) -> Result<(), String> {
+        expect_n_args!(1, args);
+
+        let mut cpu = snapshot::restore_from_path(args[0], dbg.cpu.memory.bus.clone())?;
+        cpu.input.continue_log(&dbg.cpu.input);
+        // the debug information is not part of the machine state
+        cpu.symbols = dbg.cpu.symbols.clone();
+        cpu.lines = dbg.cpu.lines.clone();
+        cpu.observers = dbg.cpu.observers.clone();
+        cpu.history = dbg.cpu.history.as_ref().map(|history| {
+            History::new(history.budget(), history.checkpoint_interval())
+        });
+        dbg.cpu = cpu;
+        Ok(())
+    }
+
+    pub fn bitmap(dbg: &mut Debugger, args: Vec<&str>) -> Result<(), String> {
+        expect_n_args!(1, args);
+
+        let display = BitmapDisplay::new(dbg.bitmap, args[0], None);
+        display.write(&dbg.cpu, args[0]).map_err(|err| err.to_string())
+    }
+
+    pub fn tlb(dbg: &mut Debugger, args: Vec<&str>) -> Result<(), String> {
+        expect_n_args!(0, args);
+
+        let tlb = dbg.cpu.tlb.as_ref().ok_or_else(|| "The MMU is disabled.".to_string())?;
+        for (i, entry) in tlb.entries.iter().enumerate() {
+            if !entry.valid() {
+                continue;
+            }
+            println!("{:2}: entryhi = {:#010x}, entrylo0 = {:#010x}, entrylo1 = {:#010x}, pagemask = {:#010x}{}",
+                     i, entry.entry_hi, entry.entry_lo0, entry.entry_lo1, entry.page_mask,
+                     if entry.global() { " (global)" } else { "" });
+        }
+        Ok(())
+    }
+
     pub fn registers(dbg: &mut Debugger, args: Vec<&str>) -> Result<(), String> {
         expect_n_args!(0, args);
 
         println!("pc = {:#010x}", dbg.cpu.pc);
         println!("hi = {:#010x}", dbg.cpu.hi);
         println!("lo = {:#010x}", dbg.cpu.lo);
+        let cop0 = &dbg.cpu.cop0;
+        println!("status = {:#010x}, cause = {:#010x}, epc = {:#010x}", cop0.status, cop0.cause, cop0.epc);
+        println!("count = {:#010x}, compare = {:#010x}", cop0.count, cop0.compare);
+        if dbg.cpu.tlb.is_some() {
+            println!("index = {:#010x}, random = {}, wired = {}, badvaddr = {:#010x}",
+                     cop0.index, cop0.random, cop0.wired, cop0.bad_vaddr);
+            println!("entryhi = {:#010x}, entrylo0 = {:#010x}, entrylo1 = {:#010x}, pagemask = {:#010x}",
+                     cop0.entry_hi, cop0.entry_lo0, cop0.entry_lo1, cop0.page_mask);
+        }
         for i in 0..32 {
             println!("${} = {:#010x}", i, dbg.cpu.get_register(i));
         }
@@ -233,6 +320,98 @@ mod commands {
         Ok(())
     }
 
+    pub fn reverse_step(dbg: &mut Debugger, args: Vec<&str>) -> Result<(), String> {
+        expect_max_n_args!(1, args);
+        check_reversible(dbg)?;
+
+        let n = if args.is_empty() {
+            1
+        } else if let Ok(n) = args[0].parse::<u64>() {
+            n
+        } else {
+            return Err(format!("Can't parse {}.", args[0]));
+        };
+
+        if let Some(signal) = dbg.cpu.reverse(n) {
+            println!("{}", signal);
+        }
+        Ok(())
+    }
+
+    pub fn reverse_continue(dbg: &mut Debugger, args: Vec<&str>) -> Result<(), String> {
+        expect_n_args!(0, args);
+        check_reversible(dbg)?;
+
+        if let Some(signal) = dbg.cpu.reverse_continue() {
+            println!("{}", signal);
+        }
+        Ok(())
+    }
+
+    pub fn reverse_finish(dbg: &mut Debugger, args: Vec<&str>) -> Result<(), String> {
+        expect_n_args!(0, args);
+        check_reversible(dbg)?;
+
+        if let Some(signal) = dbg.cpu.reverse_finish() {
+            println!("{}", signal);
+        }
+        Ok(())
+    }
+
+    // the devices keep their state when the memory is rewound
+    fn check_reversible(dbg: &Debugger) -> Result<(), String> {
+        if dbg.cpu.history.is_none() {
+            Err("The execution history is off, turn it on with `history on`.".to_string())
+        } else if dbg.cpu.memory.bus.is_empty() {
+            Ok(())
+        } else {
+            Err("Reverse execution is not supported while devices are attached.".to_string())
+        }
+    }
+
+    // the history only undoes instructions, it can't go back past a change made by hand
+    fn forget_history(dbg: &mut Debugger) {
+        if let Some(ref mut history) = dbg.cpu.history {
+            if !history.is_empty() {
+                println!("The execution history is cleared, reverse execution stops here.");
+            }
+            history.clear();
+        }
+    }
+
+    pub fn history(dbg: &mut Debugger, args: Vec<&str>) -> Result<(), String> {
+        expect_max_n_args!(3, args);
+
+        let parse = |arg: Option<&&str>, default: u64| match arg {
+            Some(arg) => arg.parse::<u64>().map_err(|_| format!("Can't parse {}.", arg)),
+            None => Ok(default),
+        };
+
+        match args.first() {
+            None => match dbg.cpu.history {
+                Some(ref history) => {
+                    println!("history: on");
+                    println!("  budget: {} instructions", history.budget());
+                    println!("  checkpoint interval: {} instructions", history.checkpoint_interval());
+                    println!("  recorded: {} instructions", history.len());
+                    println!("  checkpoints: {}", history.checkpoint_count());
+                },
+                None => println!("history: off"),
+            },
+            Some(&"on") => {
+                let budget = parse(args.get(1), history::DEFAULT_BUDGET as u64)?;
+                let interval = parse(args.get(2), history::DEFAULT_CHECKPOINT_INTERVAL)?;
+                dbg.cpu.history = Some(History::new(budget as usize, interval));
+            },
+            Some(&"off") => {
+                expect_n_args!(1, args);
+                dbg.cpu.history = None;
+            },
+            Some(_) => return Err("Unrecognized argument.".to_string()),
+        }
+        Ok(())
+    }
+
     pub fn breakpoint(dbg: &mut Debugger, args: Vec<&str>) -> Result<(), String> {
         expect_max_n_args!(1, args);
 
@@ -276,7 +455,8 @@ mod commands {
         } else if let Some(capt) = MEMORY_REGEX.captures(arg) {
             let mem_str = &capt[1];
             let mem_id = u32::from_str_radix(mem_str, 16).unwrap();
-            println!("memory[{:#x}] = {:#x}", mem_id, dbg.cpu.memory.get_byte(mem_id));
+            let byte = dbg.cpu.peek_byte(mem_id).map_err(|_| expr::inaccessible(mem_id))?;
+            println!("memory[{:#x}] = {:#x}", mem_id, byte);
         } else {
             return Err(format!("Can't read {}.", arg));
         }
@@ -294,4 +474,334 @@ mod commands {
 
         Ok(())
     }
+
+    pub fn set(dbg: &mut Debugger, args: Vec<&str>) -> Result<(), String> {
+        let line = args.join(" ");
+        let (target, value) = match line.find('=') {
+            Some(index) => (&line[..index], line[index + 1..].trim()),
+            None => return Err("Expected `set <location> = <value>`.".to_string()),
+        };
+
+        let location = expr::parse_location(target, &dbg.cpu)?;
+        forget_history(dbg);
+        match location {
+            Location::Memory(width, addr) if value.starts_with('"') => {
+                if width != Width::Byte {
+                    return Err("Strings can only be written with mem8.".to_string());
+                }
+                for (i, byte) in expr::parse_string(value)?.into_iter().enumerate() {
+                    let byte_addr = addr.wrapping_add(i as u32);
+                    dbg.cpu.store_byte(byte_addr, byte).map_err(|_| expr::inaccessible(byte_addr))?;
+                }
+            },
+            Location::Memory(width, addr) if value.starts_with('{') => {
+                for (i, item) in expr::parse_list(value, &dbg.cpu)?.into_iter().enumerate() {
+                    let item_addr = addr.wrapping_add(i as u32 * width.size());
+                    Location::Memory(width, item_addr).write(&mut dbg.cpu, item)?;
+                }
+            },
+            Location::Pc => {
+                let addr = expr::evaluate(value, &dbg.cpu)?;
+                jump_to(dbg, addr)?;
+            },
+            _ => {
+                let value = expr::evaluate(value, &dbg.cpu)?;
+                location.write(&mut dbg.cpu, value)?;
+            },
+        }
+        Ok(())
+    }
+
+    pub fn jump(dbg: &mut Debugger, args: Vec<&str>) -> Result<(), String> {
+        if args.is_empty() {
+            return Err("Expected an address.".to_string());
+        }
+
+        let addr = expr::evaluate(&args.join(" "), &dbg.cpu)?;
+        jump_to(dbg, addr)
+    }
+
+    fn jump_to(dbg: &mut Debugger, addr: u32) -> Result<(), String> {
+        if addr & 0b11 != 0 {
+            return Err(format!("Address {:#x} is not aligned on word boundary.", addr));
+        }
+        forget_history(dbg);
+        dbg.cpu.jump(addr);
+        Ok(())
+    }
+}
+
+mod expr {
+    use lib_mips_emu::cpu::Cpu;
+    use super::commands::REGISTER_ALIASES;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Width {
+        Byte,
+        HalfWord,
+        Word,
+    }
+
+    impl Width {
+        pub fn size(&self) -> u32 {
+            match *self {
+                Width::Byte => 1,
+                Width::HalfWord => 2,
+                Width::Word => 4,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub enum Location {
+        Register(u32),
+        Pc,
+        Hi,
+        Lo,
+        Memory(Width, u32),
+    }
+
+    impl Location {
+        pub fn read(&self, cpu: &Cpu) -> Result<u32, String> {
+            let value = match *self {
+                Location::Register(reg) => cpu.get_register(reg),
+                Location::Pc => cpu.pc,
+                Location::Hi => cpu.hi,
+                Location::Lo => cpu.lo,
+                Location::Memory(width, addr) => {
+                    let value = match width {
+                        Width::Byte => cpu.peek_byte(addr).map(u32::from),
+                        Width::HalfWord => cpu.peek_half_word(addr).map(u32::from),
+                        Width::Word => cpu.peek_word(addr),
+                    };
+                    value.map_err(|_| inaccessible(addr))?
+                },
+            };
+            Ok(value)
+        }
+
+        pub fn write(&self, cpu: &mut Cpu, value: u32) -> Result<(), String> {
+            match *self {
+                Location::Register(reg) => cpu.set_register(reg, value),
+                Location::Pc => cpu.jump(value),
+                Location::Hi => cpu.hi = value,
+                Location::Lo => cpu.lo = value,
+                Location::Memory(width, addr) => {
+                    let res = match width {
+                        Width::Byte => cpu.store_byte(addr, value as u8),
+                        Width::HalfWord => cpu.store_half_word(addr, value as u16),
+                        Width::Word => cpu.store_word(addr, value),
+                    };
+                    res.map_err(|_| inaccessible(addr))?
+                },
+            }
+            Ok(())
+        }
+    }
+
+    /// Error for a virtual address the TLB refuses.
+    pub fn inaccessible(addr: u32) -> String {
+        format!("Can't access the address {:#010x}.", addr)
+    }
+
+    /// Parses `$reg` or `memN[<expr>]`.
+    pub fn parse_location(input: &str, cpu: &Cpu) -> Result<Location, String> {
+        let mut parser = Parser::new(input, cpu);
+        let location = parser.location()?;
+        parser.finish()?;
+        Ok(location)
+    }
+
+    /// Evaluates an expression made of numbers, registers, memory reads,
+    /// `+`, `-` and parentheses.
+    pub fn evaluate(input: &str, cpu: &Cpu) -> Result<u32, String> {
+        let mut parser = Parser::new(input, cpu);
+        let value = parser.expr()?;
+        parser.finish()?;
+        Ok(value)
+    }
+
+    /// Parses `{<expr>, <expr>, ...}`.
+    pub fn parse_list(input: &str, cpu: &Cpu) -> Result<Vec<u32>, String> {
+        let mut parser = Parser::new(input, cpu);
+        let mut values = Vec::new();
+        parser.expect('{')?;
+        if !parser.eat('}') {
+            loop {
+                values.push(parser.expr()?);
+                if parser.eat('}') {
+                    break
+                }
+                parser.expect(',')?;
+            }
+        }
+        parser.finish()?;
+        Ok(values)
+    }
+
+    /// Parses a double-quoted string literal, the result is nul-terminated.
+    pub fn parse_string(input: &str) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        let mut chars = input.trim().chars();
+        if chars.next() != Some('"') {
+            return Err("Expected a string.".to_string());
+        }
+
+        loop {
+            let c = match chars.next() {
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('0') => '\0',
+                    Some('\\') => '\\',
+                    Some('"') => '"',
+                    Some(c) => return Err(format!("Unknown escape sequence \\{}.", c)),
+                    None => return Err("Unterminated string.".to_string()),
+                },
+                Some(c) => c,
+                None => return Err("Unterminated string.".to_string()),
+            };
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+        }
+
+        if chars.next().is_some() {
+            return Err("Unexpected characters after string.".to_string());
+        }
+        bytes.push(0);
+        Ok(bytes)
+    }
+
+    struct Parser<'a> {
+        chars: Vec<char>,
+        pos: usize,
+        cpu: &'a Cpu,
+    }
+
+    impl<'a> Parser<'a> {
+        fn new(input: &str, cpu: &'a Cpu) -> Parser<'a> {
+            Parser {
+                chars: input.chars().collect(),
+                pos: 0,
+                cpu,
+            }
+        }
+
+        fn peek(&mut self) -> Option<char> {
+            while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+                self.pos += 1;
+            }
+            self.chars.get(self.pos).cloned()
+        }
+
+        fn eat(&mut self, c: char) -> bool {
+            if self.peek() == Some(c) {
+                self.pos += 1;
+                true
+            } else {
+                false
+            }
+        }
+
+        fn expect(&mut self, c: char) -> Result<(), String> {
+            if self.eat(c) {
+                Ok(())
+            } else {
+                Err(format!("Expected `{}`.", c))
+            }
+        }
+
+        fn finish(&mut self) -> Result<(), String> {
+            match self.peek() {
+                None => Ok(()),
+                Some(c) => Err(format!("Unexpected `{}`.", c)),
+            }
+        }
+
+        fn word(&mut self) -> String {
+            self.peek();
+            let start = self.pos;
+            while self.chars.get(self.pos).is_some_and(|c| c.is_alphanumeric() || *c == '_') {
+                self.pos += 1;
+            }
+            self.chars[start..self.pos].iter().collect()
+        }
+
+        fn expr(&mut self) -> Result<u32, String> {
+            let mut value = self.term()?;
+            loop {
+                if self.eat('+') {
+                    value = value.wrapping_add(self.term()?);
+                } else if self.eat('-') {
+                    value = value.wrapping_sub(self.term()?);
+                } else {
+                    return Ok(value);
+                }
+            }
+        }
+
+        fn term(&mut self) -> Result<u32, String> {
+            if self.eat('-') {
+                return Ok(self.term()?.wrapping_neg());
+            }
+            if self.eat('(') {
+                let value = self.expr()?;
+                self.expect(')')?;
+                return Ok(value);
+            }
+
+            match self.peek() {
+                Some(c) if c.is_ascii_digit() => self.number(),
+                Some(_) => self.location()?.read(self.cpu),
+                None => Err("Unexpected end of expression.".to_string()),
+            }
+        }
+
+        fn number(&mut self) -> Result<u32, String> {
+            let word = self.word();
+            let result = if word.starts_with("0x") || word.starts_with("0X") {
+                u32::from_str_radix(&word[2..], 16)
+            } else if word.starts_with("0b") || word.starts_with("0B") {
+                u32::from_str_radix(&word[2..], 2)
+            } else {
+                word.parse()
+            };
+            result.map_err(|_| format!("Can't parse {}.", word))
+        }
+
+        fn location(&mut self) -> Result<Location, String> {
+            if self.eat('$') {
+                let name = self.word();
+                return register_location(&name)
+                    .ok_or_else(|| format!("Unknown register ${}.", name));
+            }
+
+            let width = match self.word().as_str() {
+                "mem8" => Width::Byte,
+                "mem16" => Width::HalfWord,
+                "mem32" => Width::Word,
+                "" => return Err("Expected a register or a memory location.".to_string()),
+                word => return Err(format!("Unknown location {}.", word)),
+            };
+            self.expect('[')?;
+            let addr = self.expr()?;
+            self.expect(']')?;
+            Ok(Location::Memory(width, addr))
+        }
+    }
+
+    fn register_location(name: &str) -> Option<Location> {
+        if let Ok(index) = name.parse::<u32>() {
+            return if index < 32 { Some(Location::Register(index)) } else { None };
+        }
+
+        match name {
+            "pc" => Some(Location::Pc),
+            "hi" => Some(Location::Hi),
+            "lo" => Some(Location::Lo),
+            alias => REGISTER_ALIASES.get(alias).map(|&reg| Location::Register(reg)),
+        }
+    }
 }
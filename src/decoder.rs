@@ -1,22 +1,33 @@
-use instruction::Instruction;
+use instruction::{Instruction, Isa};
 use utils;
 
-pub fn decode_instruction(word: u32) -> Instruction {
+pub fn decode_instruction(word: u32, isa: Isa) -> Instruction {
     if word == 0 { // because NOP is really frequent
         return Instruction::SLL(0, 0, 0);
     }
+    if isa == Isa::R6 {
+        if let Some(inst) = decode_r6(word) {
+            return inst;
+        }
+    }
 
     let instruction = word >> 26;
     match instruction {
         0b000000 => decode_r_inst(word),
         0b011100 => decode_r2_inst(word),
+        0b011111 => decode_r3_inst(word),
         0b000001 => decode_branch_comp(word),
+        0b010000 => decode_cop0(word),
         0b000010 => decode_jump(word, Instruction::J),
         0b000011 => decode_jump(word, Instruction::JAL),
         0b000100 => decode_i_sign_extend(word, Instruction::BEQ),
         0b000101 => decode_i_sign_extend(word, Instruction::BNE),
         0b000110 => decode_i_sign_extend(word, |rs, _, imm| Instruction::BLEZ(rs, imm)),
         0b000111 => decode_i_sign_extend(word, |rs, _, imm| Instruction::BGTZ(rs, imm)),
+        0b010100 => decode_i_sign_extend(word, Instruction::BEQL),
+        0b010101 => decode_i_sign_extend(word, Instruction::BNEL),
+        0b010110 => decode_i_sign_extend(word, |rs, _, imm| Instruction::BLEZL(rs, imm)),
+        0b010111 => decode_i_sign_extend(word, |rs, _, imm| Instruction::BGTZL(rs, imm)),
         0b001000 => decode_i_sign_extend(word, Instruction::ADDI),
         0b001001 => decode_i_sign_extend(word, Instruction::ADDIU),
         0b001010 => decode_i_sign_extend(word, Instruction::SLTI),
@@ -37,6 +48,32 @@ pub fn decode_instruction(word: u32) -> Instruction {
         0b101010 => decode_i_sign_extend(word, Instruction::SWL),
         0b101011 => decode_i_sign_extend(word, Instruction::SW),
         0b101110 => decode_i_sign_extend(word, Instruction::SWR),
+        0b101111 => decode_i_sign_extend(word, Instruction::CACHE),
+        0b110000 => decode_i_sign_extend(word, Instruction::LL),
+        0b110011 => decode_i_sign_extend(word, Instruction::PREF),
+        0b111000 => decode_i_sign_extend(word, Instruction::SC),
+        _ => Instruction::Unknown(word),
+    }
+}
+
+fn decode_cop0(word: u32) -> Instruction {
+    let rs = (word << 6) >> 27;
+    let rt = (word << 11) >> 27;
+    let rd = (word << 16) >> 27;
+    let sel = word & 0b111;
+    match rs {
+        0b00000 => Instruction::MFC0(rt, rd, sel),
+        0b00100 => Instruction::MTC0(rt, rd, sel),
+        0b01011 if rd == 12 && word & 0x7ff == 0 => Instruction::DI(rt),
+        0b01011 if rd == 12 && word & 0x7ff == 0x20 => Instruction::EI(rt),
+        0b10000 => match word & 0b111111 {
+            0b000001 => Instruction::TLBR,
+            0b000010 => Instruction::TLBWI,
+            0b000110 => Instruction::TLBWR,
+            0b001000 => Instruction::TLBP,
+            0b011000 => Instruction::ERET,
+            _ => Instruction::Unknown(word),
+        },
         _ => Instruction::Unknown(word),
     }
 }
@@ -62,10 +99,14 @@ fn decode_i_sign_extend(word: u32, constructor: ISignExtendConstructor) -> Instr
 fn decode_r_inst(word: u32) -> Instruction {
     let sub_op_code = (word << 26) >> 26;
     match sub_op_code {
+        0b000000 if word == 0x40 => Instruction::SSNOP,
+        0b000000 if word == 0xc0 => Instruction::EHB,
         0b000000 => decode_r_shift(word, Instruction::SLL),
+        0b000010 if word & (1 << 21) != 0 => decode_r_shift(word, Instruction::ROTR),
         0b000010 => decode_r_shift(word, Instruction::SRL),
         0b000011 => decode_r_shift(word, Instruction::SRA),
         0b000100 => decode_r_no_shift(word, Instruction::SLLV),
+        0b000110 if word & (1 << 6) != 0 => decode_r_no_shift(word, Instruction::ROTRV),
         0b000110 => decode_r_no_shift(word, Instruction::SRLV),
         0b000111 => decode_r_no_shift(word, Instruction::SRAV),
         0b001000 => decode_r_no_shift(word, |rs, _, _| Instruction::JR(rs)),
@@ -74,6 +115,7 @@ fn decode_r_inst(word: u32) -> Instruction {
         0b001011 => decode_r_no_shift(word, Instruction::MOVN),
         0b001100 => Instruction::SYSCALL,
         0b001101 => Instruction::BREAK,
+        0b001111 => decode_r_shift(word, |_, _, stype| Instruction::SYNC(stype)),
         0b010000 => decode_r_no_shift(word, |_, _, rd| Instruction::MFHI(rd)),
         0b010001 => decode_r_no_shift(word, |rs, _, _| Instruction::MTHI(rs)),
         0b010010 => decode_r_no_shift(word, |_, _, rd| Instruction::MFLO(rd)),
@@ -92,7 +134,12 @@ fn decode_r_inst(word: u32) -> Instruction {
         0b100111 => decode_r_no_shift(word, Instruction::NOR),
         0b101010 => decode_r_no_shift(word, Instruction::SLT),
         0b101011 => decode_r_no_shift(word, Instruction::SLTU),
+        0b110000 => decode_r_div_mul(word, Instruction::TGE),
+        0b110001 => decode_r_div_mul(word, Instruction::TGEU),
+        0b110010 => decode_r_div_mul(word, Instruction::TLT),
+        0b110011 => decode_r_div_mul(word, Instruction::TLTU),
         0b110100 => decode_r_no_shift(word, |rs, rt, _| Instruction::TEQ(rs, rt)),
+        0b110110 => decode_r_div_mul(word, Instruction::TNE),
         _ => Instruction::Unknown(word),
     }
 }
@@ -100,7 +147,35 @@ fn decode_r_inst(word: u32) -> Instruction {
 fn decode_r2_inst(word: u32) -> Instruction {
     let sub_op_code = (word << 26) >> 26;
     match sub_op_code {
+        0b000000 => decode_r_div_mul(word, Instruction::MADD),
+        0b000001 => decode_r_div_mul(word, Instruction::MADDU),
         0b000010 => decode_r_no_shift(word, Instruction::MUL),
+        0b000100 => decode_r_div_mul(word, Instruction::MSUB),
+        0b000101 => decode_r_div_mul(word, Instruction::MSUBU),
+        0b100000 => decode_r_no_shift(word, |rs, _, rd| Instruction::CLZ(rs, rd)),
+        0b100001 => decode_r_no_shift(word, |rs, _, rd| Instruction::CLO(rs, rd)),
+        _ => Instruction::Unknown(word),
+    }
+}
+
+fn decode_r3_inst(word: u32) -> Instruction {
+    let rs = (word << 6) >> 27;
+    let rt = (word << 11) >> 27;
+    let rd = (word << 16) >> 27;
+    let shift = (word << 21) >> 27;
+
+    let sub_op_code = (word << 26) >> 26;
+    match sub_op_code {
+        // the rd field holds the last bit of the field, shift the first one
+        0b000000 if shift + rd < 32 => Instruction::EXT(rs, rt, shift, rd + 1),
+        0b000100 if shift <= rd => Instruction::INS(rs, rt, shift, rd + 1 - shift),
+        0b100000 => match shift {
+            0b00010 => Instruction::WSBH(rt, rd),
+            0b10000 => Instruction::SEB(rt, rd),
+            0b11000 => Instruction::SEH(rt, rd),
+            _ => Instruction::Unknown(word),
+        },
+        0b111011 => Instruction::RDHWR(rt, rd),
         _ => Instruction::Unknown(word),
     }
 }
@@ -139,16 +214,238 @@ fn decode_branch_comp(word: u32) -> Instruction {
     let constructor = match op {
         0b00000 => Instruction::BLTZ,
         0b00001 => Instruction::BGEZ,
+        0b00010 => Instruction::BLTZL,
+        0b00011 => Instruction::BGEZL,
+        0b01000 => Instruction::TGEI,
+        0b01001 => Instruction::TGEIU,
+        0b01010 => Instruction::TLTI,
+        0b01011 => Instruction::TLTIU,
+        0b01100 => Instruction::TEQI,
+        0b01110 => Instruction::TNEI,
         0b10000 => Instruction::BLTZAL,
         0b10001 => Instruction::BGEZAL,
+        0b10010 => Instruction::BLTZALL,
+        0b10011 => Instruction::BGEZALL,
+        0b11111 => Instruction::SYNCI,
         _ => return Instruction::Unknown(word),
     };
 
     constructor(rs, offset)
 }
 
+/// Encodings that Release 6 changes, None for the ones it keeps. The removed
+/// instructions are unknown.
+fn decode_r6(word: u32) -> Option<Instruction> {
+    let rs = (word << 6) >> 27;
+    let rt = (word << 11) >> 27;
+    let offset = utils::u2i(word << 16) >> 16;
+
+    let inst = match word >> 26 {
+        0b000000 => return decode_r6_special(word),
+        0b000001 => match rt {
+            0b00000 | 0b00001 | 0b11111 => return None,
+            0b10000 | 0b10001 if rs == 0 => return None, // nal and bal
+            _ => Instruction::Unknown(word),
+        },
+        // the compact branches take the encodings where rs and rt used to be invalid
+        0b000110 if rt != 0 => match rs {
+            0 => Instruction::BLEZALC(rt, offset),
+            _ if rs == rt => Instruction::BGEZALC(rt, offset),
+            _ => Instruction::BGEUC(rs, rt, offset),
+        },
+        0b000111 if rt != 0 => match rs {
+            0 => Instruction::BGTZALC(rt, offset),
+            _ if rs == rt => Instruction::BLTZALC(rt, offset),
+            _ => Instruction::BLTUC(rs, rt, offset),
+        },
+        0b010110 if rt != 0 => match rs {
+            0 => Instruction::BLEZC(rt, offset),
+            _ if rs == rt => Instruction::BGEZC(rt, offset),
+            _ => Instruction::BGEC(rs, rt, offset),
+        },
+        0b010111 if rt != 0 => match rs {
+            0 => Instruction::BGTZC(rt, offset),
+            _ if rs == rt => Instruction::BLTZC(rt, offset),
+            _ => Instruction::BLTC(rs, rt, offset),
+        },
+        0b001000 if rs >= rt => Instruction::BOVC(rs, rt, offset),
+        0b001000 if rs == 0 => Instruction::BEQZALC(rt, offset),
+        0b001000 => Instruction::BEQC(rs, rt, offset),
+        0b011000 if rs >= rt => Instruction::BNVC(rs, rt, offset),
+        0b011000 if rs == 0 => Instruction::BNEZALC(rt, offset),
+        0b011000 => Instruction::BNEC(rs, rt, offset),
+        0b001111 if rs != 0 => Instruction::AUI(rs, rt, word & 0xffff),
+        0b011111 => return decode_r6_special3(word),
+        0b110010 => Instruction::BC(utils::u2i(word << 6) >> 6),
+        0b111010 => Instruction::BALC(utils::u2i(word << 6) >> 6),
+        0b110110 if rs == 0 => Instruction::JIC(rt, offset),
+        0b110110 => Instruction::BEQZC(rs, utils::u2i(word << 11) >> 11),
+        0b111110 if rs == 0 => Instruction::JIALC(rt, offset),
+        0b111110 => Instruction::BNEZC(rs, utils::u2i(word << 11) >> 11),
+        0b111011 => match rt {
+            0b11110 => Instruction::AUIPC(rs, word & 0xffff),
+            0b11111 => Instruction::ALUIPC(rs, word & 0xffff),
+            _ => match rt >> 3 {
+                0b00 => Instruction::ADDIUPC(rs, utils::u2i(word << 13) >> 13),
+                0b01 => Instruction::LWPC(rs, utils::u2i(word << 13) >> 13),
+                _ => Instruction::Unknown(word),
+            },
+        },
+        // SPECIAL2, the branches likely, the unaligned accesses and the old
+        // encodings of ll, sc, cache and pref
+        0b010100 | 0b010101 | 0b010110 | 0b010111 | 0b011100 | 0b100010 | 0b100110 |
+        0b101010 | 0b101110 | 0b101111 | 0b110000 | 0b110011 | 0b111000 => Instruction::Unknown(word),
+        _ => return None,
+    };
+    Some(inst)
+}
+
+fn decode_r6_special(word: u32) -> Option<Instruction> {
+    let rs = (word << 6) >> 27;
+    let rt = (word << 11) >> 27;
+    let rd = (word << 16) >> 27;
+    let shift = (word << 21) >> 27;
+
+    let inst = match (word << 26) >> 26 {
+        0b000101 if shift >> 2 == 0 => Instruction::LSA(rs, rt, rd, (shift & 0b11) + 1),
+        0b001000 => Instruction::Unknown(word), // jr is jalr with rd=0
+        0b001001 if rd == 0 => Instruction::JR(rs),
+        0b010000 if shift == 1 => Instruction::CLZ(rs, rd),
+        0b010001 if shift == 1 => Instruction::CLO(rs, rd),
+        0b011000 if shift == 2 => Instruction::MUL(rs, rt, rd),
+        0b011000 if shift == 3 => Instruction::MUH(rs, rt, rd),
+        0b011001 if shift == 2 => Instruction::MULU(rs, rt, rd),
+        0b011001 if shift == 3 => Instruction::MUHU(rs, rt, rd),
+        0b011010 if shift == 2 => Instruction::DIVR6(rs, rt, rd),
+        0b011010 if shift == 3 => Instruction::MOD(rs, rt, rd),
+        0b011011 if shift == 2 => Instruction::DIVUR6(rs, rt, rd),
+        0b011011 if shift == 3 => Instruction::MODU(rs, rt, rd),
+        0b110101 => Instruction::SELEQZ(rs, rt, rd),
+        0b110111 => Instruction::SELNEZ(rs, rt, rd),
+        // movz, movn, the hi/lo moves and the hi/lo multiplications and divisions
+        0b001010 | 0b001011 | 0b010000 | 0b010001 | 0b010010 | 0b010011 |
+        0b011000 | 0b011001 | 0b011010 | 0b011011 => Instruction::Unknown(word),
+        _ => return None,
+    };
+    Some(inst)
+}
+
+fn decode_r6_special3(word: u32) -> Option<Instruction> {
+    let rs = (word << 6) >> 27;
+    let rt = (word << 11) >> 27;
+    let rd = (word << 16) >> 27;
+    let shift = (word << 21) >> 27;
+    // the memory instructions moved here have a 9-bit offset
+    let offset = utils::u2i(word << 16) >> 23;
+
+    let inst = match (word << 26) >> 26 {
+        0b100101 => Instruction::CACHE(rs, rt, offset),
+        0b100110 => Instruction::SC(rs, rt, offset),
+        0b110101 => Instruction::PREF(rs, rt, offset),
+        0b110110 => Instruction::LL(rs, rt, offset),
+        0b100000 if shift == 0 => Instruction::BITSWAP(rt, rd),
+        0b100000 if shift >> 2 == 0b010 => Instruction::ALIGN(rs, rt, rd, shift & 0b11),
+        _ => return None,
+    };
+    Some(inst)
+}
+
 type JumpConstructor = fn(u32) -> Instruction;
 fn decode_jump(word: u32, constructor: JumpConstructor) -> Instruction {
     let instr_index = (word << 6) >> 6;
     constructor(instr_index)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn r(op: u32, rs: u32, rt: u32, rd: u32, shift: u32, funct: u32) -> u32 {
+        op << 26 | rs << 21 | rt << 16 | rd << 11 | shift << 6 | funct
+    }
+
+    fn r2(word: u32) -> Instruction {
+        decode_instruction(word, Isa::R2)
+    }
+
+    #[test]
+    fn ext_and_ins_reach_bit_31() {
+        // ext's rd field is size - 1, ins' rd field is pos + size - 1
+        assert_eq!(r2(r(0x1f, 4, 5, 28, 3, 0x00)), Instruction::EXT(4, 5, 3, 29));
+        assert_eq!(r2(r(0x1f, 4, 5, 28, 4, 0x00)), Instruction::Unknown(r(0x1f, 4, 5, 28, 4, 0x00)));
+        assert_eq!(r2(r(0x1f, 4, 5, 31, 3, 0x04)), Instruction::INS(4, 5, 3, 29));
+        assert_eq!(r2(r(0x1f, 4, 5, 31, 0, 0x04)), Instruction::INS(4, 5, 0, 32));
+        assert_eq!(r2(r(0x1f, 4, 5, 2, 3, 0x04)), Instruction::Unknown(r(0x1f, 4, 5, 2, 3, 0x04)));
+    }
+
+    #[test]
+    fn rotr_is_srl_with_bit_21() {
+        assert_eq!(r2(r(0, 1, 5, 6, 0, 0x02)), Instruction::ROTR(5, 6, 0));
+        assert_eq!(r2(r(0, 0, 5, 6, 0, 0x02)), Instruction::SRL(5, 6, 0));
+        assert_eq!(r2(r(0, 7, 5, 6, 1, 0x06)), Instruction::ROTRV(7, 5, 6));
+        assert_eq!(r2(r(0, 7, 5, 6, 0, 0x06)), Instruction::SRLV(7, 5, 6));
+    }
+
+    #[test]
+    fn release_2_special2_and_special3() {
+        assert_eq!(r2(r(0x1c, 4, 0, 6, 0, 0x20)), Instruction::CLZ(4, 6));
+        assert_eq!(r2(r(0x1c, 4, 0, 6, 0, 0x21)), Instruction::CLO(4, 6));
+        assert_eq!(r2(r(0x1c, 4, 5, 0, 0, 0x00)), Instruction::MADD(4, 5));
+        assert_eq!(r2(r(0x1c, 4, 5, 0, 0, 0x04)), Instruction::MSUB(4, 5));
+        assert_eq!(r2(r(0x1f, 0, 5, 6, 0x02, 0x20)), Instruction::WSBH(5, 6));
+        assert_eq!(r2(r(0x1f, 0, 5, 6, 0x10, 0x20)), Instruction::SEB(5, 6));
+        assert_eq!(r2(r(0x1f, 0, 5, 6, 0x18, 0x20)), Instruction::SEH(5, 6));
+        assert_eq!(r2(r(0x1f, 0, 5, 6, 0x11, 0x20)), Instruction::Unknown(r(0x1f, 0, 5, 6, 0x11, 0x20)));
+        assert_eq!(r2(r(0x1f, 0, 5, 29, 0, 0x3b)), Instruction::RDHWR(5, 29));
+    }
+
+    #[test]
+    fn di_and_ei() {
+        assert_eq!(r2(r(0x10, 0x0b, 5, 12, 0, 0x00)), Instruction::DI(5));
+        assert_eq!(r2(r(0x10, 0x0b, 5, 12, 0, 0x20)), Instruction::EI(5));
+        assert_eq!(r2(r(0x10, 0x0b, 5, 13, 0, 0x20)), Instruction::Unknown(r(0x10, 0x0b, 5, 13, 0, 0x20)));
+    }
+
+    fn i(op: u32, rs: u32, rt: u32, imm: u32) -> u32 {
+        op << 26 | rs << 21 | rt << 16 | imm
+    }
+
+    fn r6(word: u32) -> Instruction {
+        decode_instruction(word, Isa::R6)
+    }
+
+    #[test]
+    fn release_6_splits_the_old_addi_and_daddi() {
+        // bovc/bnvc when rs >= rt, beqzalc/bnezalc when rs is 0, beqc/bnec otherwise
+        assert_eq!(r6(i(0x08, 5, 3, 0xffff)), Instruction::BOVC(5, 3, -1));
+        assert_eq!(r6(i(0x08, 0, 0, 0x0002)), Instruction::BOVC(0, 0, 2));
+        assert_eq!(r6(i(0x08, 0, 3, 0x0002)), Instruction::BEQZALC(3, 2));
+        assert_eq!(r6(i(0x08, 3, 5, 0x0002)), Instruction::BEQC(3, 5, 2));
+        assert_eq!(r6(i(0x18, 5, 5, 0xfffe)), Instruction::BNVC(5, 5, -2));
+        assert_eq!(r6(i(0x18, 0, 3, 0x0002)), Instruction::BNEZALC(3, 2));
+        assert_eq!(r6(i(0x18, 3, 5, 0x0002)), Instruction::BNEC(3, 5, 2));
+        assert_eq!(r2(i(0x08, 3, 5, 0x0002)), Instruction::ADDI(3, 5, 2));
+    }
+
+    #[test]
+    fn release_6_pop66_and_pop76() {
+        // jic/jialc when rs is 0, beqzc/bnezc with a 21-bit offset otherwise
+        assert_eq!(r6(i(0x36, 0, 4, 0x8000)), Instruction::JIC(4, -0x8000));
+        assert_eq!(r6(i(0x36, 4, 0x1f, 0xffff)), Instruction::BEQZC(4, -1));
+        assert_eq!(r6(i(0x36, 4, 0x0f, 0xffff)), Instruction::BEQZC(4, 0xfffff));
+        assert_eq!(r6(i(0x3e, 0, 4, 0x0010)), Instruction::JIALC(4, 0x10));
+        assert_eq!(r6(i(0x3e, 4, 0x10, 0x0000)), Instruction::BNEZC(4, -0x100000));
+    }
+
+    #[test]
+    fn release_6_pc_relative() {
+        // rt selects the instruction, the 19-bit forms keeping its low 3 bits
+        assert_eq!(r6(i(0x3b, 4, 0x07, 0xffff)), Instruction::ADDIUPC(4, -1));
+        assert_eq!(r6(i(0x3b, 4, 0x03, 0x0000)), Instruction::ADDIUPC(4, 0x30000));
+        assert_eq!(r6(i(0x3b, 4, 0x08, 0x0001)), Instruction::LWPC(4, 1));
+        assert_eq!(r6(i(0x3b, 4, 0x0c, 0x0000)), Instruction::LWPC(4, -0x40000));
+        assert_eq!(r6(i(0x3b, 4, 0x1e, 0x1234)), Instruction::AUIPC(4, 0x1234));
+        assert_eq!(r6(i(0x3b, 4, 0x1f, 0x1234)), Instruction::ALUIPC(4, 0x1234));
+        assert_eq!(r6(i(0x3b, 4, 0x10, 0x0000)), Instruction::Unknown(i(0x3b, 4, 0x10, 0x0000)));
+    }
+}
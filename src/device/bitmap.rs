@@ -0,0 +1,240 @@
+//! The MARS "Bitmap Display": a framebuffer of `0x00RRGGBB` words in guest
+//! memory, one word per unit of `unit_width` by `unit_height` pixels, row
+//! after row. Like in MARS it is not mapped on the bus, the frames are read
+//! from memory and written as PPM or PNG images.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use cpu::Cpu;
+use trace::{Observer, Record};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitmapConfig {
+    pub base: u32,
+    pub width: u32, // pixels
+    pub height: u32,
+    pub unit_width: u32,
+    pub unit_height: u32,
+}
+
+impl Default for BitmapConfig {
+    fn default() -> BitmapConfig {
+        BitmapConfig {
+            base: 0x10010000,
+            width: 512,
+            height: 256,
+            unit_width: 1,
+            unit_height: 1,
+        }
+    }
+}
+
+impl BitmapConfig {
+    /// Updates the configuration from `key=value` pairs separated by commas:
+    /// base (an address or one of global, gp, data, heap and mmio as in
+    /// MARS), width, height, unit-width and unit-height.
+    pub fn parse(&mut self, options: &str) -> Result<(), String> {
+        for option in options.split(',').filter(|option| !option.is_empty()) {
+            let mut parts = option.splitn(2, '=');
+            let key = parts.next().unwrap();
+            let value = parts.next().ok_or_else(|| format!("Missing value for {}.", key))?;
+            let number = || parse_number(value).ok_or_else(|| format!("Invalid value {} for {}.", value, key));
+
+            match key {
+                "base" => self.base = match value {
+                    "global" => 0x10000000,
+                    "gp" => 0x10008000,
+                    "data" => 0x10010000,
+                    "heap" => 0x10040000,
+                    "mmio" => 0xffff0000,
+                    _ => number()?,
+                },
+                "width" => self.width = number()?,
+                "height" => self.height = number()?,
+                "unit-width" => self.unit_width = number()?,
+                "unit-height" => self.unit_height = number()?,
+                _ => return Err(format!("Unknown bitmap option {}.", key)),
+            }
+        }
+
+        if self.width == 0 || self.height == 0 || self.unit_width == 0 || self.unit_height == 0 {
+            return Err("The bitmap and unit sizes must not be 0.".to_string());
+        }
+        if !self.width.is_multiple_of(self.unit_width) || !self.height.is_multiple_of(self.unit_height) {
+            return Err("The bitmap size must be a multiple of the unit size.".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn parse_number(s: &str) -> Option<u32> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        u32::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Ppm,
+    Png,
+}
+
+impl ImageFormat {
+    /// Format from the extension of a file name, PNG for `.png` and PPM otherwise.
+    pub fn from_path(path: &str) -> ImageFormat {
+        if path.to_lowercase().ends_with(".png") {
+            ImageFormat::Png
+        } else {
+            ImageFormat::Ppm
+        }
+    }
+}
+
+/// Frames of the display, written every `interval` instructions when there
+/// is one.
+#[derive(Debug, Clone)]
+pub struct BitmapDisplay {
+    pub config: BitmapConfig,
+    pub path: String,
+    pub interval: Option<u64>,
+    pub frames: u64,
+    instructions: u64,
+    failed: bool,
+}
+
+impl BitmapDisplay {
+    pub fn new(config: BitmapConfig, path: &str, interval: Option<u64>) -> BitmapDisplay {
+        BitmapDisplay {
+            config,
+            path: path.to_string(),
+            interval,
+            frames: 0,
+            instructions: 0,
+            failed: false,
+        }
+    }
+
+    /// RGB pixels of the current frame, row after row.
+    pub fn render(&self, cpu: &Cpu) -> Vec<u8> {
+        let config = &self.config;
+        let units_per_row = config.width / config.unit_width;
+        let mut pixels = Vec::with_capacity((config.width * config.height * 3) as usize);
+        for y in 0..config.height {
+            for x in 0..config.width {
+                let unit = (y / config.unit_height) * units_per_row + x / config.unit_width;
+                let color = cpu.peek_word(config.base.wrapping_add(unit * 4)).unwrap_or(0);
+                pixels.extend_from_slice(&[(color >> 16) as u8, (color >> 8) as u8, color as u8]);
+            }
+        }
+        pixels
+    }
+
+    /// Writes the current frame to `path`.
+    pub fn write(&self, cpu: &Cpu, path: &str) -> io::Result<()> {
+        let pixels = self.render(cpu);
+        let mut writer = BufWriter::new(File::create(path)?);
+        match ImageFormat::from_path(path) {
+            ImageFormat::Ppm => write_ppm(&mut writer, self.config.width, self.config.height, &pixels)?,
+            ImageFormat::Png => write_png(&mut writer, self.config.width, self.config.height, &pixels)?,
+        }
+        writer.flush()
+    }
+
+    /// Writes the next frame of the sequence, its number is added before
+    /// the extension of the path.
+    pub fn write_frame(&mut self, cpu: &Cpu) -> io::Result<()> {
+        let path = match self.path.rfind('.') {
+            Some(dot) if !self.path[dot..].contains('/') => {
+                format!("{}-{:04}{}", &self.path[..dot], self.frames, &self.path[dot..])
+            },
+            _ => format!("{}-{:04}", self.path, self.frames),
+        };
+        self.frames += 1;
+        self.write(cpu, &path)
+    }
+}
+
+impl Observer for BitmapDisplay {
+    fn observe(&mut self, _record: &Record, cpu: &Cpu) {
+        self.instructions += 1;
+        let due = self.interval.is_some_and(|interval| self.instructions.is_multiple_of(interval));
+        if self.failed || !due {
+            return;
+        }
+        if let Err(err) = self.write_frame(cpu) {
+            println!("Bitmap frames disabled: {}", err);
+            self.failed = true;
+        }
+    }
+}
+
+pub fn write_ppm<W: Write>(writer: &mut W, width: u32, height: u32, pixels: &[u8]) -> io::Result<()> {
+    write!(writer, "P6\n{} {}\n255\n", width, height)?;
+    writer.write_all(pixels)
+}
+
+/// PNG image of RGB pixels, compressed with stored deflate blocks only.
+pub fn write_png<W: Write>(writer: &mut W, width: u32, height: u32, pixels: &[u8]) -> io::Result<()> {
+    writer.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]); // 8 bits RGB, no interlacing
+    write_chunk(writer, b"IHDR", &header)?;
+
+    // scanlines start with their filter type, 0 for none
+    let row = width as usize * 3;
+    let mut raw = Vec::with_capacity((row + 1) * height as usize);
+    for line in pixels.chunks(row) {
+        raw.push(0);
+        raw.extend_from_slice(line);
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        zlib.push(blocks.peek().is_none() as u8);
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+    write_chunk(writer, b"IDAT", &zlib)?;
+
+    write_chunk(writer, b"IEND", &[])
+}
+
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    let crc = crc32(crc32(0xffffffff, kind), data);
+    writer.write_all(&(!crc).to_be_bytes())
+}
+
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
@@ -0,0 +1,208 @@
+//! The MARS "Keyboard and Display MMIO Simulator": a keyboard at 0xffff0000
+//! and a display at 0xffff0008, each with a control and a data register.
+//! Bit 0 of a control register is the ready bit and bit 1 enables the
+//! interrupt, raised while the device is ready. The keyboard reads the
+//! standard input of the host.
+
+use std::fmt;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+use device::{self, Device};
+
+pub const KEYBOARD_BASE: u32 = 0xffff0000;
+pub const DISPLAY_BASE: u32 = 0xffff0008;
+pub const KEYBOARD_IRQ: u32 = 0;
+pub const DISPLAY_IRQ: u32 = 1;
+
+const READY: u32 = 1;
+const INTERRUPT_ENABLE: u32 = 2;
+
+#[derive(Debug, Default)]
+pub struct Keyboard {
+    control: u32,
+    data: u8,
+    raw_terminal: bool,
+}
+
+impl Keyboard {
+    pub fn new() -> Keyboard {
+        Keyboard::default()
+    }
+
+    /// Keyboard putting the terminal in raw mode, to get the keys one by one
+    /// and without echo.
+    pub fn stdin() -> Keyboard {
+        let mut keyboard = Keyboard::new();
+        keyboard.raw_terminal = io::stdin().is_terminal() && stty(&["-icanon", "-echo", "min", "1"]);
+        keyboard
+    }
+}
+
+impl Drop for Keyboard {
+    fn drop(&mut self) {
+        if self.raw_terminal {
+            stty(&["icanon", "echo"]);
+        }
+    }
+}
+
+fn stty(args: &[&str]) -> bool {
+    Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+impl Device for Keyboard {
+    fn name(&self) -> &str {
+        "keyboard"
+    }
+
+    fn size(&self) -> u32 {
+        8
+    }
+
+    fn read(&mut self, offset: u32, _size: u8) -> u32 {
+        match offset {
+            0..=3 => self.control,
+            _ => {
+                // reading the data clears the ready bit
+                self.control &= !READY;
+                self.data as u32
+            },
+        }
+    }
+
+    fn peek(&self, offset: u32, _size: u8) -> u32 {
+        match offset {
+            0..=3 => self.control,
+            _ => self.data as u32,
+        }
+    }
+
+    fn write(&mut self, offset: u32, _size: u8, value: u32) {
+        if offset < 4 {
+            self.control = (self.control & READY) | (value & INTERRUPT_ENABLE);
+        }
+    }
+
+    fn wants_stdin(&self) -> bool {
+        self.control & READY == 0
+    }
+
+    fn receive_stdin(&mut self, byte: u8) {
+        self.data = byte;
+        self.control |= READY;
+    }
+
+    fn interrupt(&self) -> bool {
+        self.control & (READY | INTERRUPT_ENABLE) == READY | INTERRUPT_ENABLE
+    }
+
+    fn reset(&mut self) {
+        self.control = 0;
+    }
+
+    fn words(&self) -> Vec<u32> {
+        vec![self.control, self.data as u32]
+    }
+
+    fn set_words(&mut self, words: &[u32]) -> Result<(), String> {
+        match *words {
+            [control, data] if data <= 0xff => {
+                self.control = control;
+                self.data = data as u8;
+                Ok(())
+            },
+            _ => Err(device::invalid_state(self.name())),
+        }
+    }
+}
+
+/// Display printing the transmitted characters, ready again `delay`
+/// instructions after each one.
+#[derive(Debug)]
+pub struct Display<W: Write> {
+    output: W,
+    delay: u64,
+    control: u32,
+    busy: u64, // instructions before being ready
+}
+
+impl<W: Write> Display<W> {
+    pub fn new(output: W, delay: u64) -> Display<W> {
+        Display {
+            output,
+            delay,
+            control: READY,
+            busy: 0,
+        }
+    }
+}
+
+impl<W: Write + fmt::Debug> Device for Display<W> {
+    fn name(&self) -> &str {
+        "display"
+    }
+
+    fn size(&self) -> u32 {
+        8
+    }
+
+    fn read(&mut self, offset: u32, size: u8) -> u32 {
+        self.peek(offset, size)
+    }
+
+    fn peek(&self, offset: u32, _size: u8) -> u32 {
+        match offset {
+            0..=3 => self.control,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u32, _size: u8, value: u32) {
+        if offset < 4 {
+            self.control = (self.control & READY) | (value & INTERRUPT_ENABLE);
+        } else if self.control & READY != 0 {
+            let _ = self.output.write_all(&[value as u8]);
+            let _ = self.output.flush();
+            self.control &= !READY;
+            self.busy = self.delay;
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        if self.control & READY == 0 {
+            self.busy = self.busy.saturating_sub(cycles);
+            if self.busy == 0 {
+                self.control |= READY;
+            }
+        }
+    }
+
+    fn interrupt(&self) -> bool {
+        self.control & (READY | INTERRUPT_ENABLE) == READY | INTERRUPT_ENABLE
+    }
+
+    fn reset(&mut self) {
+        self.control = READY;
+        self.busy = 0;
+    }
+
+    fn words(&self) -> Vec<u32> {
+        vec![self.control, self.busy as u32, (self.busy >> 32) as u32]
+    }
+
+    fn set_words(&mut self, words: &[u32]) -> Result<(), String> {
+        match *words {
+            [control, busy_low, busy_high] => {
+                self.control = control;
+                self.busy = (busy_high as u64) << 32 | busy_low as u64;
+                Ok(())
+            },
+            _ => Err(device::invalid_state(self.name())),
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Memory-mapped devices and the bus routing their address ranges.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{BufReader, Read};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+pub mod bitmap;
+pub mod mars;
+pub mod timer;
+pub mod uart;
+
+/// Device registers mapped in memory. Accesses are given as an offset in the
+/// mapped range and a size of 1, 2 or 4 bytes, the value being in the low
+/// bits.
+pub trait Device: fmt::Debug {
+    fn name(&self) -> &str;
+
+    /// Size in bytes of the mapped range.
+    fn size(&self) -> u32;
+
+    fn read(&mut self, offset: u32, size: u8) -> u32;
+
+    /// Reads like `read` without its side effects, for the debugger and the
+    /// other inspections of the memory.
+    fn peek(&self, offset: u32, size: u8) -> u32;
+
+    fn write(&mut self, offset: u32, size: u8, value: u32);
+
+    /// Whether the device reads the standard input and can take a byte of it
+    /// now. The bytes go through the input of the cpu to be recorded.
+    fn wants_stdin(&self) -> bool {
+        false
+    }
+
+    /// Byte of the standard input, given when the device wants one.
+    fn receive_stdin(&mut self, _byte: u8) {}
+
+    /// Called after each instruction with the number of cycles elapsed.
+    fn tick(&mut self, _cycles: u64) {}
+
+    /// State of the interrupt line of the device.
+    fn interrupt(&self) -> bool {
+        false
+    }
+
+    /// Called when the cpu is reset.
+    fn reset(&mut self) {}
+
+    /// Registers and internal state of the device in a fixed order, for the
+    /// snapshots. The host side (files, sockets, terminals) is not included.
+    fn words(&self) -> Vec<u32> {
+        Vec::new()
+    }
+
+    /// Restores the state given by `words`.
+    fn set_words(&mut self, words: &[u32]) -> Result<(), String> {
+        if words.is_empty() { Ok(()) } else { Err(invalid_state(self.name())) }
+    }
+}
+
+pub fn invalid_state(name: &str) -> String {
+    format!("Invalid state for device {}.", name)
+}
+
+/// Reads `input` byte by byte in a thread, so that devices can poll it.
+pub fn spawn_reader<R: Read + Send + 'static>(input: R) -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for byte in BufReader::new(input).bytes() {
+            match byte {
+                Ok(byte) if sender.send(byte).is_ok() => {},
+                _ => break,
+            }
+        }
+    });
+    receiver
+}
+
+#[derive(Debug, Clone)]
+pub struct Mapping {
+    pub base: u32,
+    pub size: u32,
+    pub irq: Option<u32>,
+    pub device: Rc<RefCell<dyn Device>>,
+}
+
+/// Address ranges routed to devices instead of RAM.
+#[derive(Debug, Clone, Default)]
+pub struct Bus {
+    mappings: Vec<Mapping>,
+}
+
+impl Bus {
+    pub fn new() -> Bus {
+        Bus::default()
+    }
+
+    /// Maps a device at `base`, with its interrupt line connected to `irq`.
+    pub fn attach(&mut self, base: u32, device: Rc<RefCell<dyn Device>>, irq: Option<u32>) -> Result<(), String> {
+        let size = device.borrow().size();
+        let end = base as u64 + size as u64;
+        if size == 0 || end > 1 << 32 {
+            return Err(format!("Invalid range for device {}.", device.borrow().name()));
+        }
+        if let Some(other) = self.mappings.iter().find(|m| (base as u64) < m.base as u64 + m.size as u64 && (m.base as u64) < end) {
+            return Err(format!("Device {} overlaps device {} at {:#010x}.",
+                               device.borrow().name(), other.device.borrow().name(), other.base));
+        }
+        if irq.is_some_and(|irq| irq >= 8) {
+            return Err(format!("Invalid interrupt line for device {}.", device.borrow().name()));
+        }
+
+        self.mappings.push(Mapping { base, size, irq, device });
+        self.mappings.sort_by_key(|mapping| mapping.base);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Mapping> {
+        self.mappings.iter()
+    }
+
+    /// Device mapped at `addr` and the offset of `addr` in its range.
+    #[inline]
+    pub fn find(&self, addr: u32) -> Option<(&Rc<RefCell<dyn Device>>, u32)> {
+        if self.mappings.is_empty() {
+            return None;
+        }
+        self.mappings.iter()
+            .find(|mapping| mapping.base <= addr && addr - mapping.base < mapping.size)
+            .map(|mapping| (&mapping.device, addr - mapping.base))
+    }
+
+    /// Device waiting for a byte of the standard input.
+    pub fn stdin_reader(&self) -> Option<&Rc<RefCell<dyn Device>>> {
+        self.mappings.iter()
+            .map(|mapping| &mapping.device)
+            .find(|device| device.borrow().wants_stdin())
+    }
+
+    pub fn tick(&self, cycles: u64) {
+        for mapping in &self.mappings {
+            mapping.device.borrow_mut().tick(cycles);
+        }
+    }
+
+    pub fn reset(&self) {
+        for mapping in &self.mappings {
+            mapping.device.borrow_mut().reset();
+        }
+    }
+
+    /// Interrupt lines (0 to 7) asserted by the devices, as a bit mask.
+    pub fn interrupts(&self) -> u32 {
+        self.mappings.iter()
+            .filter(|mapping| mapping.device.borrow().interrupt())
+            .filter_map(|mapping| mapping.irq)
+            .fold(0, |lines, irq| lines | 1 << irq)
+    }
+}
@@ -0,0 +1,108 @@
+//! Countdown timer counting executed instructions.
+//!
+//! | offset | register                                                     |
+//! |--------|--------------------------------------------------------------|
+//! | 0x0    | control: bit 0 enable, bit 1 interrupt enable, bit 2 periodic |
+//! | 0x4    | reload value, loaded in the counter when it is written        |
+//! | 0x8    | counter, decremented after each instruction                   |
+//! | 0xc    | status: bit 0 expired, cleared by writing 1                   |
+//!
+//! When the counter reaches 0 the timer expires, then it is reloaded if it
+//! is periodic and stopped otherwise. The interrupt line is raised while the
+//! timer is expired with the interrupt enabled.
+
+use device::{self, Device};
+
+pub const TIMER_BASE: u32 = 0xffff0010;
+pub const TIMER_IRQ: u32 = 2;
+
+const ENABLE: u32 = 1;
+const INTERRUPT_ENABLE: u32 = 2;
+const PERIODIC: u32 = 4;
+const EXPIRED: u32 = 1;
+
+#[derive(Debug, Clone, Default)]
+pub struct Timer {
+    control: u32,
+    reload: u32,
+    counter: u32,
+    status: u32,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer::default()
+    }
+}
+
+impl Device for Timer {
+    fn name(&self) -> &str {
+        "timer"
+    }
+
+    fn size(&self) -> u32 {
+        16
+    }
+
+    fn read(&mut self, offset: u32, size: u8) -> u32 {
+        self.peek(offset, size)
+    }
+
+    fn peek(&self, offset: u32, _size: u8) -> u32 {
+        match offset / 4 {
+            0 => self.control,
+            1 => self.reload,
+            2 => self.counter,
+            _ => self.status,
+        }
+    }
+
+    fn write(&mut self, offset: u32, _size: u8, value: u32) {
+        match offset / 4 {
+            0 => self.control = value & (ENABLE | INTERRUPT_ENABLE | PERIODIC),
+            1 => {
+                self.reload = value;
+                self.counter = value;
+            },
+            2 => self.counter = value,
+            _ => self.status &= !(value & EXPIRED),
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        if self.control & ENABLE == 0 || self.counter == 0 {
+            return;
+        }
+        self.counter = self.counter.saturating_sub(cycles.min(u32::MAX as u64) as u32);
+        if self.counter == 0 {
+            self.status |= EXPIRED;
+            if self.control & PERIODIC != 0 {
+                self.counter = self.reload;
+            } else {
+                self.control &= !ENABLE;
+            }
+        }
+    }
+
+    fn interrupt(&self) -> bool {
+        self.control & INTERRUPT_ENABLE != 0 && self.status & EXPIRED != 0
+    }
+
+    fn reset(&mut self) {
+        *self = Timer::new();
+    }
+
+    fn words(&self) -> Vec<u32> {
+        vec![self.control, self.reload, self.counter, self.status]
+    }
+
+    fn set_words(&mut self, words: &[u32]) -> Result<(), String> {
+        match *words {
+            [control, reload, counter, status] => {
+                *self = Timer { control, reload, counter, status };
+                Ok(())
+            },
+            _ => Err(device::invalid_state(self.name())),
+        }
+    }
+}
@@ -0,0 +1,362 @@
+//! 16550-style UART. The registers are at consecutive addresses shifted
+//! left by `shift` bits, transmitted bytes are sent to the host at once and
+//! received ones wait in a 16 bytes FIFO (1 byte when the FIFOs are
+//! disabled). The baud rate and the line settings are kept but unused.
+
+use std::collections::VecDeque;
+#[cfg(unix)]
+use std::ffi::CStr;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::io::FromRawFd;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::Receiver;
+
+#[cfg(unix)]
+use libc;
+
+use device::{self, Device};
+
+pub const UART_BASE: u32 = 0xb80003f8;
+pub const UART_IRQ: u32 = 3;
+
+const FIFO_SIZE: usize = 16;
+
+const IER_RECEIVED: u32 = 0x01;
+const IER_TRANSMITTER_EMPTY: u32 = 0x02;
+const FCR_ENABLE: u32 = 0x01;
+const FCR_CLEAR_RECEIVER: u32 = 0x02;
+const LCR_DLAB: u32 = 0x80;
+const MCR_LOOPBACK: u32 = 0x10;
+const LSR_DATA_READY: u32 = 0x01;
+const LSR_OVERRUN: u32 = 0x02;
+const LSR_TRANSMITTER_EMPTY: u32 = 0x60; // holding register and shift register
+const MSR_CONNECTED: u32 = 0xb0; // clear to send, data set ready, carrier detect
+
+/// Host side of a UART.
+pub struct HostPort {
+    pub name: String,
+    input: Option<Receiver<u8>>,
+    stdin: bool, // the input is polled from the standard input by the cpu
+    output: Box<dyn Write>,
+}
+
+impl fmt::Debug for HostPort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HostPort {{ name: {:?} }}", self.name)
+    }
+}
+
+impl HostPort {
+    /// Parses a host port: stdio, file:PATH (output only), unix:PATH (a
+    /// listening socket to connect to) or pty (a new pseudo-terminal), the
+    /// last two on Unix only.
+    pub fn open(spec: &str) -> Result<HostPort, String> {
+        let mut parts = spec.splitn(2, ':');
+        match (parts.next().unwrap(), parts.next()) {
+            ("stdio", None) => Ok(HostPort::stdio()),
+            ("file", Some(path)) => HostPort::file(path).map_err(|err| format!("Can't create {}: {}", path, err)),
+            ("unix", Some(path)) => HostPort::unix(path).map_err(|err| format!("Can't connect to {}: {}", path, err)),
+            ("pty", None) => HostPort::pty().map_err(|err| format!("Can't create a pseudo-terminal: {}", err)),
+            _ => Err(format!("Invalid UART port {}.", spec)),
+        }
+    }
+
+    pub fn stdio() -> HostPort {
+        HostPort {
+            name: "stdio".to_string(),
+            input: None,
+            stdin: true,
+            output: Box::new(io::stdout()),
+        }
+    }
+
+    pub fn file(path: &str) -> io::Result<HostPort> {
+        Ok(HostPort {
+            name: path.to_string(),
+            input: None,
+            stdin: false,
+            output: Box::new(File::create(path)?),
+        })
+    }
+
+    #[cfg(unix)]
+    pub fn unix(path: &str) -> io::Result<HostPort> {
+        let stream = UnixStream::connect(path)?;
+        Ok(HostPort {
+            name: path.to_string(),
+            input: Some(device::spawn_reader(stream.try_clone()?)),
+            stdin: false,
+            output: Box::new(stream),
+        })
+    }
+
+    /// Opens a pseudo-terminal in raw mode, its name is the path of the
+    /// slave side to connect a terminal program to.
+    #[cfg(unix)]
+    pub fn pty() -> io::Result<HostPort> {
+        unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let master = File::from_raw_fd(fd);
+            if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut termios = ::std::mem::zeroed();
+            if libc::tcgetattr(fd, &mut termios) == 0 {
+                libc::cfmakeraw(&mut termios);
+                libc::tcsetattr(fd, libc::TCSANOW, &termios);
+            }
+            let name = pts_name(fd)?;
+
+            Ok(HostPort {
+                name,
+                input: Some(device::spawn_reader(master.try_clone()?)),
+                stdin: false,
+                output: Box::new(master),
+            })
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn unix(_path: &str) -> io::Result<HostPort> {
+        Err(unsupported())
+    }
+
+    #[cfg(not(unix))]
+    pub fn pty() -> io::Result<HostPort> {
+        Err(unsupported())
+    }
+
+    fn receive(&mut self) -> Option<u8> {
+        self.input.as_ref().and_then(|input| input.try_recv().ok())
+    }
+
+    fn send(&mut self, byte: u8) {
+        let _ = self.output.write_all(&[byte]);
+        let _ = self.output.flush();
+    }
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn pts_name(fd: libc::c_int) -> io::Result<String> {
+    let mut name = [0 as libc::c_char; 128];
+    if libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned())
+}
+
+// ptsname_r is not everywhere, the emulator being single-threaded ptsname is fine
+#[cfg(all(unix, not(target_os = "linux")))]
+unsafe fn pts_name(fd: libc::c_int) -> io::Result<String> {
+    let name = libc::ptsname(fd);
+    if name.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(CStr::from_ptr(name).to_string_lossy().into_owned())
+}
+
+#[cfg(not(unix))]
+fn unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "unsupported on this platform")
+}
+
+#[derive(Debug)]
+pub struct Uart {
+    port: HostPort,
+    shift: u32,
+    receiver: VecDeque<u8>,
+    ier: u32,
+    fcr: u32,
+    lcr: u32,
+    mcr: u32,
+    scr: u32,
+    divisor: u32,
+    overrun: bool,
+    transmitter_interrupt: bool, // transmitter empty, until IIR is read or THR written
+}
+
+impl Uart {
+    pub fn new(port: HostPort, shift: u32) -> Uart {
+        Uart {
+            port,
+            shift,
+            receiver: VecDeque::new(),
+            ier: 0,
+            fcr: 0,
+            lcr: 0,
+            mcr: 0,
+            scr: 0,
+            divisor: 0,
+            overrun: false,
+            transmitter_interrupt: false,
+        }
+    }
+
+    fn fifo_size(&self) -> usize {
+        if self.fcr & FCR_ENABLE != 0 { FIFO_SIZE } else { 1 }
+    }
+
+    fn push_received(&mut self, byte: u8) {
+        if self.receiver.len() < self.fifo_size() {
+            self.receiver.push_back(byte);
+        } else {
+            self.overrun = true;
+        }
+    }
+
+    fn interrupt_identification(&self) -> u32 {
+        let fifos = if self.fcr & FCR_ENABLE != 0 { 0xc0 } else { 0 };
+        let id = if self.ier & IER_RECEIVED != 0 && !self.receiver.is_empty() {
+            0x04
+        } else if self.ier & IER_TRANSMITTER_EMPTY != 0 && self.transmitter_interrupt {
+            0x02
+        } else {
+            0x01 // none pending
+        };
+        fifos | id
+    }
+}
+
+impl Device for Uart {
+    fn name(&self) -> &str {
+        "uart"
+    }
+
+    fn size(&self) -> u32 {
+        8 << self.shift
+    }
+
+    fn read(&mut self, offset: u32, size: u8) -> u32 {
+        let value = self.peek(offset, size);
+        match offset >> self.shift {
+            0 if self.lcr & LCR_DLAB == 0 => {
+                self.receiver.pop_front();
+            },
+            2 if value & 0x0f == 0x02 => self.transmitter_interrupt = false,
+            5 => self.overrun = false,
+            _ => {},
+        }
+        value
+    }
+
+    fn peek(&self, offset: u32, _size: u8) -> u32 {
+        let dlab = self.lcr & LCR_DLAB != 0;
+        match offset >> self.shift {
+            0 if dlab => self.divisor & 0xff,
+            0 => self.receiver.front().cloned().unwrap_or(0) as u32,
+            1 if dlab => self.divisor >> 8,
+            1 => self.ier,
+            2 => self.interrupt_identification(),
+            3 => self.lcr,
+            4 => self.mcr,
+            5 => {
+                LSR_TRANSMITTER_EMPTY |
+                    if self.receiver.is_empty() { 0 } else { LSR_DATA_READY } |
+                    if self.overrun { LSR_OVERRUN } else { 0 }
+            },
+            6 => MSR_CONNECTED,
+            _ => self.scr,
+        }
+    }
+
+    fn write(&mut self, offset: u32, _size: u8, value: u32) {
+        let dlab = self.lcr & LCR_DLAB != 0;
+        match offset >> self.shift {
+            0 if dlab => self.divisor = (self.divisor & 0xff00) | (value & 0xff),
+            0 => {
+                if self.mcr & MCR_LOOPBACK != 0 {
+                    self.push_received(value as u8);
+                } else {
+                    self.port.send(value as u8);
+                }
+                self.transmitter_interrupt = true;
+            },
+            1 if dlab => self.divisor = (self.divisor & 0xff) | (value & 0xff) << 8,
+            1 => {
+                // enabling the interrupt raises it at once, the transmitter being empty
+                if value & IER_TRANSMITTER_EMPTY != 0 && self.ier & IER_TRANSMITTER_EMPTY == 0 {
+                    self.transmitter_interrupt = true;
+                }
+                self.ier = value & 0x0f;
+            },
+            2 => {
+                self.fcr = value & 0xc9;
+                if value & FCR_CLEAR_RECEIVER != 0 || value & FCR_ENABLE == 0 {
+                    self.receiver.clear();
+                }
+            },
+            3 => self.lcr = value & 0xff,
+            4 => self.mcr = value & 0x1f,
+            5 | 6 => {},
+            _ => self.scr = value & 0xff,
+        }
+    }
+
+    fn tick(&mut self, _cycles: u64) {
+        if self.mcr & MCR_LOOPBACK != 0 {
+            return;
+        }
+        // bytes stay on the host side while the FIFO is full
+        while self.receiver.len() < self.fifo_size() {
+            match self.port.receive() {
+                Some(byte) => self.receiver.push_back(byte),
+                None => break,
+            }
+        }
+    }
+
+    fn wants_stdin(&self) -> bool {
+        self.port.stdin && self.mcr & MCR_LOOPBACK == 0 && self.receiver.len() < self.fifo_size()
+    }
+
+    fn receive_stdin(&mut self, byte: u8) {
+        self.receiver.push_back(byte);
+    }
+
+    fn interrupt(&self) -> bool {
+        self.interrupt_identification() & 0x01 == 0
+    }
+
+    fn reset(&mut self) {
+        let port = ::std::mem::replace(&mut self.port, HostPort {
+            name: String::new(),
+            input: None,
+            stdin: false,
+            output: Box::new(io::sink()),
+        });
+        *self = Uart::new(port, self.shift);
+    }
+
+    // the registers, then the bytes waiting in the receiver FIFO
+    fn words(&self) -> Vec<u32> {
+        let mut words = vec![
+            self.ier, self.fcr, self.lcr, self.mcr, self.scr, self.divisor,
+            self.overrun as u32, self.transmitter_interrupt as u32,
+        ];
+        words.extend(self.receiver.iter().map(|&byte| byte as u32));
+        words
+    }
+
+    fn set_words(&mut self, words: &[u32]) -> Result<(), String> {
+        if words.len() < 8 || words.len() > 8 + FIFO_SIZE || words[8..].iter().any(|&byte| byte > 0xff) {
+            return Err(device::invalid_state(self.name()));
+        }
+        self.ier = words[0];
+        self.fcr = words[1];
+        self.lcr = words[2];
+        self.mcr = words[3];
+        self.scr = words[4];
+        self.divisor = words[5];
+        self.overrun = words[6] != 0;
+        self.transmitter_interrupt = words[7] != 0;
+        self.receiver = words[8..].iter().map(|&byte| byte as u8).collect();
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+//! Pipeline diagrams of the executed instructions: the stage of each
+//! instruction at each cycle, as scheduled by the timing model, with the
+//! stalls and the forwarding paths.
+
+use std::io::{self, Write};
+
+use instruction::{Instruction, InstructionClass};
+use pipeline::Schedule;
+use trace::{register_name, Record};
+
+const STAGES: [&str; 5] = ["IF", "ID", "EX", "MEM", "WB"];
+const COLORS: [&str; 5] = ["#9ecae1", "#a1d99b", "#fdd0a2", "#dadaeb", "#fcbba1"];
+
+/// Register value sent to a stage before being written back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Forward {
+    pub reg: u32,
+    pub from_pc: u32,
+    pub from_row: Option<usize>, // None if the producer is outside the diagram
+    pub path: &'static str,
+    pub cycle: u64, // cycle where the consumer uses the value
+}
+
+#[derive(Debug, Clone)]
+pub struct Row {
+    pub count: u64, // index of the instruction in the execution
+    pub pc: u32,
+    pub instruction: String,
+    pub schedule: Schedule,
+    pub forwards: Vec<Forward>,
+}
+
+impl Row {
+    /// Stage of the instruction at `cycle`, and whether it is stalled there.
+    pub fn stage(&self, cycle: u64) -> Option<(usize, bool)> {
+        let s = &self.schedule;
+        let starts = [s.fetch, s.decode, s.execute, s.memory, s.writeback, s.end];
+        (0..5).find(|&stage| starts[stage] <= cycle && cycle < starts[stage + 1])
+            .map(|stage| (stage, cycle != starts[stage]))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Writer {
+    pc: u32,
+    row: Option<usize>,
+    schedule: Schedule,
+    load: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagram {
+    pub rows: Vec<Row>,
+    range: Option<(u32, u32)>,
+    first: u64,
+    limit: usize,
+    count: u64,
+    writers: [Option<Writer>; 32],
+}
+
+impl Diagram {
+    /// Diagram of at most `limit` instructions, from the `first` executed one
+    /// and only inside `range` (end excluded) if there is one.
+    pub fn new(range: Option<(u32, u32)>, first: u64, limit: usize) -> Diagram {
+        Diagram {
+            rows: Vec::new(),
+            range,
+            first,
+            limit,
+            count: 0,
+            writers: [None; 32],
+        }
+    }
+
+    /// Adds an instruction once the pipeline has scheduled it.
+    pub fn add(&mut self, record: &Record, inst: &Instruction, schedule: Schedule) {
+        let count = self.count;
+        self.count += 1;
+        let shown = count >= self.first && self.rows.len() < self.limit &&
+            self.range.is_none_or(|(start, end)| start <= record.pc && record.pc < end);
+
+        let row = if shown {
+            let in_decode = matches!(inst.class(), InstructionClass::Branch | InstructionClass::Jump);
+            let needed = if in_decode { schedule.decode } else { schedule.execute };
+            let mut forwards = Vec::new();
+            for reg in inst.sources() {
+                let writer = match self.writers[reg as usize] {
+                    _ if forwards.iter().any(|forward: &Forward| forward.reg == reg) => continue,
+                    Some(writer) if needed < writer.schedule.writeback => writer,
+                    _ => continue, // read from the register file
+                };
+                let from_memory = writer.load || needed > writer.schedule.memory;
+                forwards.push(Forward {
+                    reg,
+                    from_pc: writer.pc,
+                    from_row: writer.row,
+                    path: match (from_memory, in_decode) {
+                        (false, false) => "EX/MEM->EX",
+                        (true, false) => "MEM/WB->EX",
+                        (false, true) => "EX/MEM->ID",
+                        (true, true) => "MEM/WB->ID",
+                    },
+                    cycle: needed,
+                });
+            }
+            self.rows.push(Row {
+                count,
+                pc: record.pc,
+                instruction: inst.to_string(),
+                schedule,
+                forwards,
+            });
+            Some(self.rows.len() - 1)
+        } else {
+            None
+        };
+
+        if let Some(reg) = inst.destination() {
+            self.writers[reg as usize] = Some(Writer {
+                pc: record.pc,
+                row,
+                schedule,
+                load: inst.class() == InstructionClass::Load,
+            });
+        }
+    }
+
+    fn cycles(&self) -> (u64, u64) {
+        let first = self.rows.first().map_or(0, |row| row.schedule.fetch);
+        let last = self.rows.iter().map(|row| row.schedule.end).max().unwrap_or(0);
+        (first, last)
+    }
+
+    fn notes(&self, row: &Row) -> Vec<String> {
+        let mut notes = Vec::new();
+        if let Some(stall) = row.schedule.stall {
+            notes.push(format!("stall: {}", stall.name()));
+        }
+        for forward in &row.forwards {
+            notes.push(format!("{} {} from {:#010x}", register_name(forward.reg), forward.path, forward.from_pc));
+        }
+        notes
+    }
+
+    /// Table with a column per cycle, stalled cycles are shown as `--`.
+    pub fn write_text<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let (first, last) = self.cycles();
+        write!(writer, "{:>8} {:<10} {:<24}", "#", "pc", "instruction")?;
+        for cycle in first..last {
+            write!(writer, " {:>4}", cycle - first + 1)?;
+        }
+        writeln!(writer)?;
+
+        for row in &self.rows {
+            write!(writer, "{:>8} {:#010x} {:<24}", row.count, row.pc, row.instruction)?;
+            for cycle in first..last {
+                let cell = match row.stage(cycle) {
+                    Some((_, true)) => "--",
+                    Some((stage, false)) => STAGES[stage],
+                    None => "",
+                };
+                write!(writer, " {:>4}", cell)?;
+            }
+            let notes = self.notes(row);
+            if !notes.is_empty() {
+                write!(writer, "  {}", notes.join(", "))?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+
+    /// SVG picture with the stages colored, the stalls grey and the forwarding
+    /// paths drawn as arrows between instructions of the diagram.
+    pub fn write_svg<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        const LABEL: u64 = 300;
+        const WIDTH: u64 = 40;
+        const HEIGHT: u64 = 22;
+
+        let (first, last) = self.cycles();
+        let x = |cycle: u64| LABEL + (cycle - first) * WIDTH;
+        let y = |row: usize| HEIGHT + row as u64 * HEIGHT;
+        let width = x(last) + 10;
+        let height = y(self.rows.len()) + 10;
+
+        writeln!(writer, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+                          font-family=\"monospace\" font-size=\"11\">", width, height)?;
+        writeln!(writer, "<defs><marker id=\"arrow\" markerWidth=\"6\" markerHeight=\"6\" refX=\"5\" refY=\"3\" \
+                          orient=\"auto\"><path d=\"M0,0 L6,3 L0,6 z\" fill=\"#d62728\"/></marker></defs>")?;
+        for cycle in first..last {
+            writeln!(writer, "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+                     x(cycle) + WIDTH / 2, HEIGHT - 6, cycle - first + 1)?;
+        }
+
+        for (index, row) in self.rows.iter().enumerate() {
+            let title = format!("#{} {:#010x}: {}", row.count, row.pc, row.instruction);
+            let notes = self.notes(row);
+            writeln!(writer, "<text x=\"4\" y=\"{}\">{}<title>{}</title></text>",
+                     y(index) + 15, escape(&format!("{:#010x} {}", row.pc, row.instruction)),
+                     escape(&format!("{}\n{}", title, notes.join("\n"))))?;
+            for cycle in row.schedule.fetch..row.schedule.end {
+                let (stage, stalled) = row.stage(cycle).unwrap();
+                let (fill, text) = if stalled { ("#d9d9d9", "--") } else { (COLORS[stage], STAGES[stage]) };
+                writeln!(writer, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" stroke=\"white\"/>\
+                                  <text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+                         x(cycle), y(index), WIDTH, HEIGHT, fill, x(cycle) + WIDTH / 2, y(index) + 15, text)?;
+            }
+        }
+
+        for (index, row) in self.rows.iter().enumerate() {
+            for forward in &row.forwards {
+                if let Some(from) = forward.from_row {
+                    writeln!(writer, "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"#d62728\" \
+                                      stroke-width=\"1.5\" marker-end=\"url(#arrow)\"><title>{} {}</title></line>",
+                             x(forward.cycle) - 4, y(from) + HEIGHT / 2, x(forward.cycle) + 4, y(index) + 4,
+                             register_name(forward.reg), forward.path)?;
+                }
+            }
+        }
+        writeln!(writer, "</svg>")
+    }
+
+    /// HTML page with the SVG picture followed by the stalls and forwarding paths.
+    pub fn write_html<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "<!DOCTYPE html>")?;
+        writeln!(writer, "<html><head><meta charset=\"utf-8\"><title>Pipeline diagram</title></head><body>")?;
+        self.write_svg(writer)?;
+        writeln!(writer, "<ul style=\"font-family: monospace\">")?;
+        for row in &self.rows {
+            let notes = self.notes(row);
+            if !notes.is_empty() {
+                writeln!(writer, "<li>{:#010x} {}: {}</li>", row.pc, escape(&row.instruction), escape(&notes.join(", ")))?;
+            }
+        }
+        writeln!(writer, "</ul>")?;
+        writeln!(writer, "</body></html>")
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
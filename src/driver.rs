@@ -1,16 +1,37 @@
 extern crate clap;
-extern crate elf;
 extern crate regex;
 #[macro_use]
 extern crate lazy_static;
 extern crate lib_mips_emu;
 
-use clap::{Arg, App};
+use std::cell::RefCell;
+use std::fs::File;
+use std::io;
+use std::rc::Rc;
+
+use clap::{Arg, App, ArgMatches};
 
 mod debugger;
 
 use debugger::Debugger;
-use lib_mips_emu::cpu::Cpu;
+use lib_mips_emu::cache::{CacheConfig, CacheHierarchy};
+use lib_mips_emu::cpu::{Cpu, Signal};
+use lib_mips_emu::device::bitmap::{BitmapConfig, BitmapDisplay};
+use lib_mips_emu::device::mars;
+use lib_mips_emu::device::timer::{self, Timer};
+use lib_mips_emu::device::uart::{self, HostPort, Uart};
+use lib_mips_emu::diagram::Diagram;
+use lib_mips_emu::history::{self, History};
+use lib_mips_emu::loader::{self, Format, LoadOptions};
+use lib_mips_emu::mmu;
+use lib_mips_emu::input::Input;
+use lib_mips_emu::instruction::{self, InstructionClass, Isa};
+use lib_mips_emu::callgraph::CallGraph;
+use lib_mips_emu::coverage::Coverage;
+use lib_mips_emu::predictor::{self, BranchPredictor};
+use lib_mips_emu::pipeline::{Pipeline, TimingConfig};
+use lib_mips_emu::profile::Profiler;
+use lib_mips_emu::trace::{self, TraceWriter};
 
 fn main() {
     let matches = App::new("MIPS emulator")
@@ -24,22 +45,547 @@ fn main() {
              .help("Activate debugger.")
              .short("d")
              .long("debug"))
+        .arg(Arg::with_name("history")
+             .help("Records the execution history in the debugger, for the reverse commands.")
+             .long("history")
+             .requires("debug"))
+        .arg(Arg::with_name("format")
+             .help("Sets the format of the input file, guessed from its extension otherwise (.bin/.rom raw, .hex Intel HEX, .srec/.s19/.s28/.s37 S-records, ELF).")
+             .long("format")
+             .value_name("FORMAT")
+             .possible_values(&loader::FORMATS))
+        .arg(Arg::with_name("load-address")
+             .help("Sets the address of a raw binary, which starts there.")
+             .long("load-address")
+             .value_name("ADDRESS")
+             .default_value("0xbfc00000"))
+        .arg(Arg::with_name("add")
+             .help("Also loads this file next to the input file, as a raw binary at ADDRESS when given. Overlapping files are an error.")
+             .long("add")
+             .value_name("FILE[@ADDRESS]")
+             .multiple(true)
+             .number_of_values(1))
+        .arg(Arg::with_name("entry")
+             .help("Starts at the entry point of this loaded file, at this symbol or at this address, instead of the entry point of the input file.")
+             .long("entry")
+             .value_name("FILE|SYMBOL|ADDRESS")
+             .conflicts_with("bare-metal"))
+        .arg(Arg::with_name("bare-metal")
+             .help("Starts at the reset vector 0xbfc00000 in kernel mode, with the reset state of COP0.")
+             .long("bare-metal"))
+        .arg(Arg::with_name("record")
+             .help("Records every external input to this file.")
+             .long("record")
+             .value_name("FILE")
+             .conflicts_with("replay"))
+        .arg(Arg::with_name("replay")
+             .help("Replays the external inputs recorded in this file.")
+             .long("replay")
+             .value_name("FILE"))
+        .arg(Arg::with_name("trace")
+             .help("Writes a trace of the executed instructions to this file.")
+             .long("trace")
+             .value_name("FILE"))
+        .arg(Arg::with_name("trace-format")
+             .help("Format of the trace.")
+             .long("trace-format")
+             .value_name("FORMAT")
+             .possible_values(&["json", "binary"])
+             .default_value("json"))
+        .arg(Arg::with_name("trace-range")
+             .help("Only traces instructions in this address range (START:END, END excluded).")
+             .long("trace-range")
+             .value_name("RANGE")
+             .multiple(true)
+             .number_of_values(1))
+        .arg(Arg::with_name("trace-symbol")
+             .help("Only traces instructions of this symbol.")
+             .long("trace-symbol")
+             .value_name("SYMBOL")
+             .multiple(true)
+             .number_of_values(1))
+        .arg(Arg::with_name("trace-class")
+             .help("Only traces instructions of this class (alu, shift, load, store, branch, jump, muldiv, move, system).")
+             .long("trace-class")
+             .value_name("CLASS")
+             .multiple(true)
+             .number_of_values(1))
+        .arg(Arg::with_name("profile")
+             .help("Prints an execution profile at the end.")
+             .long("profile"))
+        .arg(Arg::with_name("profile-top")
+             .help("Number of entries of each profile table.")
+             .long("profile-top")
+             .value_name("N")
+             .default_value("10"))
+        .arg(Arg::with_name("annotate")
+             .help("Writes the disassembly of the executed code annotated with execution counts.")
+             .long("annotate")
+             .value_name("FILE"))
+        .arg(Arg::with_name("folded")
+             .help("Writes the profile as folded stacks, for flamegraph.pl or inferno.")
+             .long("folded")
+             .value_name("FILE"))
+        .arg(Arg::with_name("callgrind")
+             .help("Writes the profile in the callgrind format, for KCachegrind.")
+             .long("callgrind")
+             .value_name("FILE"))
+        .arg(Arg::with_name("coverage")
+             .help("Writes a coverage report per symbol and per address.")
+             .long("coverage")
+             .value_name("FILE"))
+        .arg(Arg::with_name("lcov")
+             .help("Writes the coverage as an lcov tracefile, the program needs line information.")
+             .long("lcov")
+             .value_name("FILE"))
+        .arg(Arg::with_name("timing")
+             .help("Prints the cycles of a 5-stage pipeline at the end.")
+             .long("timing"))
+        .arg(Arg::with_name("latency")
+             .help("Sets a latency of the timing model (fetch, load, store, mult, div or mispredict), as NAME=CYCLES.")
+             .long("latency")
+             .value_name("LATENCY")
+             .multiple(true)
+             .number_of_values(1))
+        .arg(Arg::with_name("caches")
+             .help("Simulates L1 instruction and data caches and prints their statistics at the end.")
+             .long("caches"))
+        .arg(Arg::with_name("cache")
+             .help("Configures a cache (l1i, l1d or l2) as LEVEL:OPTIONS, e.g. l2:size=64k,assoc=8,line=32,repl=lru,write=back,alloc=yes,latency=10.")
+             .long("cache")
+             .value_name("CACHE")
+             .multiple(true)
+             .number_of_values(1))
+        .arg(Arg::with_name("memory-latency")
+             .help("Sets the cycles of a memory access missing all the caches.")
+             .long("memory-latency")
+             .value_name("CYCLES")
+             .default_value("100"))
+        .arg(Arg::with_name("predictor")
+             .help("Simulates a branch predictor and prints its accuracy at the end.")
+             .long("predictor")
+             .value_name("PREDICTOR")
+             .possible_values(&predictor::PREDICTORS))
+        .arg(Arg::with_name("predictor-bits")
+             .help("Sets the number of entries of the predictor tables, as a power of two.")
+             .long("predictor-bits")
+             .value_name("BITS")
+             .default_value("10"))
+        .arg(Arg::with_name("diagram")
+             .help("Writes a pipeline diagram, as HTML or SVG for a .html or .svg file and as text otherwise.")
+             .long("diagram")
+             .value_name("FILE"))
+        .arg(Arg::with_name("diagram-range")
+             .help("Only shows the instructions between two addresses (end excluded) in the diagram, as START:END.")
+             .long("diagram-range")
+             .value_name("RANGE"))
+        .arg(Arg::with_name("diagram-start")
+             .help("Sets the number of executed instructions before the first one of the diagram.")
+             .long("diagram-start")
+             .value_name("COUNT")
+             .default_value("0"))
+        .arg(Arg::with_name("diagram-size")
+             .help("Sets the maximum number of instructions of the diagram.")
+             .long("diagram-size")
+             .value_name("COUNT")
+             .default_value("50"))
+        .arg(Arg::with_name("isa")
+             .help("Sets the revision of MIPS32 the instructions are decoded for. Release 6 raises a reserved instruction exception on the encodings it removed.")
+             .long("isa")
+             .value_name("ISA")
+             .possible_values(&instruction::ISAS)
+             .default_value("r2"))
+        .arg(Arg::with_name("mmu")
+             .help("Translates the addresses with a TLB, kseg0 and kseg1 being mapped to the start of the physical memory. The MMIO devices of kseg3 (--mmio, --timer) are not available then.")
+             .long("mmu")
+             .conflicts_with_all(&["mmio", "timer"]))
+        .arg(Arg::with_name("tlb-entries")
+             .help("Sets the number of TLB entries.")
+             .long("tlb-entries")
+             .value_name("COUNT")
+             .possible_values(&["16", "32", "48", "64"])
+             .default_value("32"))
+        .arg(Arg::with_name("mmio")
+             .help("Attaches the MARS keyboard and display MMIO simulator at 0xffff0000, on the terminal.")
+             .long("mmio")
+             .conflicts_with("debug"))
+        .arg(Arg::with_name("mmio-delay")
+             .help("Sets the instructions the MMIO display takes to print a character.")
+             .long("mmio-delay")
+             .value_name("COUNT")
+             .default_value("5"))
+        .arg(Arg::with_name("timer")
+             .help("Attaches a countdown timer at 0xffff0010, on interrupt line 2 (Cause IP2).")
+             .long("timer"))
+        .arg(Arg::with_name("uart")
+             .help("Attaches a 16550 UART on interrupt line 3 (Cause IP3), connected to stdio, file:PATH, unix:PATH (a listening socket) or pty.")
+             .long("uart")
+             .value_name("PORT"))
+        .arg(Arg::with_name("uart-base")
+             .help("Sets the address of the UART, COM1 of the Malta board by default. Addresses of kseg0 and kseg1 are made physical with --mmu.")
+             .long("uart-base")
+             .value_name("ADDRESS")
+             .default_value("0xb80003f8"))
+        .arg(Arg::with_name("uart-shift")
+             .help("Sets the UART register spacing, 1 << SHIFT bytes.")
+             .long("uart-shift")
+             .value_name("SHIFT")
+             .default_value("0"))
+        .arg(Arg::with_name("bitmap")
+             .help("Writes the bitmap display at the end, as a PNG image for a .png file and as PPM otherwise.")
+             .long("bitmap")
+             .value_name("FILE"))
+        .arg(Arg::with_name("bitmap-options")
+             .help("Configures the bitmap display, e.g. base=gp,width=256,height=256,unit-width=8,unit-height=8.")
+             .long("bitmap-options")
+             .value_name("OPTIONS"))
+        .arg(Arg::with_name("bitmap-every")
+             .help("Also writes a numbered frame of the bitmap display every COUNT instructions.")
+             .long("bitmap-every")
+             .value_name("COUNT")
+             .requires("bitmap"))
         .get_matches();
 
     
     let maybe_input_path = matches.value_of("INPUT");
     let mut cpu = Cpu::new();
 
-    if matches.is_present("debug") {
+    if let Some(path) = matches.value_of("record") {
+        cpu.input = Input::record(path).unwrap_or_else(|err| exit_with_error(&format!("Can't create the input log: {}", err)));
+    } else if let Some(path) = matches.value_of("replay") {
+        cpu.input = Input::replay(path).unwrap_or_else(|err| exit_with_error(&format!("Can't read the input log: {}", err)));
+    }
+
+    cpu.isa = matches.value_of("isa").and_then(Isa::from_name).unwrap_or_default();
+    if matches.is_present("mmu") {
+        let entries = matches.value_of("tlb-entries").unwrap().parse().unwrap();
+        cpu.enable_mmu(entries);
+    }
+
+    let load_options = LoadOptions {
+        format: matches.value_of("format").and_then(Format::from_name),
+        address: parse_number(matches.value_of("load-address").unwrap()).unwrap_or_else(|err| exit_with_error(&err)),
+        bare_metal: matches.is_present("bare-metal"),
+        entry: matches.value_of("entry").map(str::to_string),
+    };
+    let added_paths: Vec<&str> = matches.values_of("add").map_or_else(Vec::new, |paths| paths.collect());
+
+    if matches.is_present("mmio") {
+        let delay = parse_number(matches.value_of("mmio-delay").unwrap()).unwrap_or_else(|err| exit_with_error(&err));
+        let keyboard = Rc::new(RefCell::new(mars::Keyboard::stdin()));
+        let display = Rc::new(RefCell::new(mars::Display::new(io::stdout(), delay as u64)));
+        cpu.memory.bus.attach(mars::KEYBOARD_BASE, keyboard, Some(mars::KEYBOARD_IRQ)).unwrap();
+        cpu.memory.bus.attach(mars::DISPLAY_BASE, display, Some(mars::DISPLAY_IRQ)).unwrap();
+    }
+
+    if matches.is_present("timer") {
+        let timer = Rc::new(RefCell::new(Timer::new()));
+        cpu.memory.bus.attach(timer::TIMER_BASE, timer, Some(timer::TIMER_IRQ)).unwrap();
+    }
+
+    if let Some(port) = matches.value_of("uart") {
+        let mut base = parse_number(matches.value_of("uart-base").unwrap()).unwrap_or_else(|err| exit_with_error(&err));
+        if cpu.tlb.is_some() {
+            base = mmu::unmapped(base).unwrap_or(base);
+        }
+        let shift = parse_number(matches.value_of("uart-shift").unwrap()).unwrap_or_else(|err| exit_with_error(&err));
+        if shift > 4 {
+            exit_with_error("The UART shift must be at most 4.");
+        }
+        if port == "stdio" && (matches.is_present("debug") || matches.is_present("mmio")) {
+            exit_with_error(&format!("The UART can't be connected to stdio, the {} reads it.",
+                                     if matches.is_present("debug") { "debugger" } else { "MMIO keyboard" }));
+        }
+        let port = HostPort::open(port).unwrap_or_else(|err| exit_with_error(&err));
+        if port.name != "stdio" {
+            println!("UART connected to {}", port.name);
+        }
+        let uart = Rc::new(RefCell::new(Uart::new(port, shift)));
+        cpu.memory.bus.attach(base, uart, Some(uart::UART_IRQ)).unwrap_or_else(|err| exit_with_error(&err));
+    }
+
+    let mut bitmap_config = BitmapConfig::default();
+    if let Some(options) = matches.value_of("bitmap-options") {
+        bitmap_config.parse(options).unwrap_or_else(|err| exit_with_error(&err));
+    }
+    let bitmap = matches.value_of("bitmap").map(|path| {
+        let interval = matches.value_of("bitmap-every").map(|count| {
+            parse_number(count).ok().filter(|&count| count != 0).unwrap_or_else(|| exit_with_error("Invalid bitmap interval.")) as u64
+        });
+        let bitmap = Rc::new(RefCell::new(BitmapDisplay::new(bitmap_config, path, interval)));
+        if interval.is_some() {
+            cpu.observers.push(bitmap.clone());
+        }
+        bitmap
+    });
+
+    if let Some(path) = matches.value_of("trace") {
+        let writer = create_trace_writer(path, &matches).unwrap_or_else(|err| {
+            exit_with_error(&format!("Can't create the trace: {}", err))
+        });
+        cpu.observers.push(Rc::new(RefCell::new(writer)));
+    }
+
+    let profiler = if matches.is_present("profile") || matches.is_present("annotate") {
+        let profiler = Rc::new(RefCell::new(Profiler::new()));
+        cpu.observers.push(profiler.clone());
+        Some(profiler)
+    } else {
+        None
+    };
+
+    let call_graph = if ["profile", "folded", "callgrind"].iter().any(|&arg| matches.is_present(arg)) {
+        let call_graph = Rc::new(RefCell::new(CallGraph::new()));
+        cpu.observers.push(call_graph.clone());
+        Some(call_graph)
+    } else {
+        None
+    };
+
+    let coverage = if matches.is_present("coverage") || matches.is_present("lcov") {
+        let coverage = Rc::new(RefCell::new(Coverage::new()));
+        cpu.observers.push(coverage.clone());
+        Some(coverage)
+    } else {
+        None
+    };
+
+    let mut caches = if matches.is_present("caches") || matches.is_present("cache") {
+        Some(cache_hierarchy(&matches).unwrap_or_else(|err| exit_with_error(&err)))
+    } else {
+        None
+    };
+
+    let mut predictor = matches.value_of("predictor").map(|name| {
+        let bits = matches.value_of("predictor-bits").unwrap().parse().unwrap_or_else(|_| exit_with_error("Invalid predictor size."));
+        BranchPredictor::new(name, bits).unwrap_or_else(|err| exit_with_error(&err))
+    });
+
+    // with the timing model the caches give the memory latencies of the
+    // pipeline and the predictor its misprediction stalls
+    let pipeline = if matches.is_present("timing") || matches.is_present("diagram") {
+        let config = timing_config(&matches).unwrap_or_else(|err| exit_with_error(&err));
+        let mut pipeline = Pipeline::new(config);
+        if matches.is_present("diagram") {
+            pipeline.diagram = Some(diagram(&matches).unwrap_or_else(|err| exit_with_error(&err)));
+        }
+        pipeline.caches = caches.take();
+        pipeline.predictor = predictor.take();
+        let pipeline = Rc::new(RefCell::new(pipeline));
+        cpu.observers.push(pipeline.clone());
+        Some(pipeline)
+    } else {
+        None
+    };
+
+    let caches = caches.map(|caches| {
+        let caches = Rc::new(RefCell::new(caches));
+        cpu.observers.push(caches.clone());
+        caches
+    });
+    let predictor = predictor.map(|predictor| {
+        let predictor = Rc::new(RefCell::new(predictor));
+        cpu.observers.push(predictor.clone());
+        predictor
+    });
+
+    if matches.is_present("history") {
+        cpu.history = Some(History::new(history::DEFAULT_BUDGET, history::DEFAULT_CHECKPOINT_INTERVAL));
+    }
+
+    let cpu = if matches.is_present("debug") {
         let mut debugger = Debugger::new(cpu);
+        debugger.bitmap = bitmap_config;
+        debugger.load_options = load_options;
         if let Some(path) = maybe_input_path {
-            debugger.execute_command("load", vec![path]);
+            let mut paths = vec![path];
+            paths.extend(&added_paths);
+            debugger.execute_command("load", paths);
         }
         debugger.launch();
+        debugger.cpu
     } else {
         let path = matches.value_of("INPUT").unwrap();
-        let elf_file = elf::File::open_path(path).expect("Can't read elf.");
-        cpu.load_elf(elf_file).unwrap();
-        cpu.run(false, false);
+        let mut paths = vec![path];
+        paths.extend(&added_paths);
+        loader::load(&mut cpu, &paths, &load_options).unwrap_or_else(|err| exit_with_error(&err));
+        match cpu.run(false, false) {
+            Some(Signal::Exit) | None => {},
+            Some(signal) => eprintln!("{}", signal),
+        }
+        cpu
+    };
+
+    let top = matches.value_of("profile-top").unwrap().parse().unwrap_or_else(|_| exit_with_error("Invalid profile size."));
+    if let Some(profiler) = profiler {
+        let profiler = profiler.borrow();
+        if matches.is_present("profile") {
+            profiler.report(&mut io::stdout(), &cpu, top).expect("Can't print the profile.");
+        }
+        if let Some(path) = matches.value_of("annotate") {
+            let mut file = File::create(path).expect("Can't create the annotated disassembly.");
+            profiler.annotate(&mut file, &cpu).expect("Can't write the annotated disassembly.");
+        }
+    }
+
+    if let Some(call_graph) = call_graph {
+        let call_graph = call_graph.borrow();
+        if matches.is_present("profile") {
+            println!("\nCall graph:");
+            call_graph.report(&mut io::stdout(), &cpu.symbols, top).expect("Can't print the call graph.");
+        }
+        if let Some(path) = matches.value_of("folded") {
+            let mut file = File::create(path).expect("Can't create the folded stacks.");
+            call_graph.write_folded(&mut file, &cpu.symbols).expect("Can't write the folded stacks.");
+        }
+        if let Some(path) = matches.value_of("callgrind") {
+            let command = matches.value_of("INPUT").unwrap_or("");
+            let mut file = File::create(path).expect("Can't create the callgrind profile.");
+            call_graph.write_callgrind(&mut file, &cpu.symbols, command).expect("Can't write the callgrind profile.");
+        }
     }
+
+    if let Some(coverage) = coverage {
+        let coverage = coverage.borrow();
+        if let Some(path) = matches.value_of("coverage") {
+            let mut file = File::create(path).expect("Can't create the coverage report.");
+            coverage.write_report(&mut file, &cpu).expect("Can't write the coverage report.");
+        }
+        if let Some(path) = matches.value_of("lcov") {
+            if cpu.lines.is_empty() {
+                println!("No line information in the program, use --coverage instead of --lcov.");
+            } else {
+                let test_name = matches.value_of("INPUT").unwrap_or("");
+                let mut file = File::create(path).expect("Can't create the lcov tracefile.");
+                coverage.write_lcov(&mut file, &cpu, test_name).expect("Can't write the lcov tracefile.");
+            }
+        }
+    }
+
+    if let Some(bitmap) = bitmap {
+        let bitmap = bitmap.borrow();
+        bitmap.write(&cpu, &bitmap.path).expect("Can't write the bitmap display.");
+    }
+
+    if let Some(caches) = caches {
+        println!("\nCaches:");
+        caches.borrow().report(&mut io::stdout(), top).expect("Can't print the caches.");
+    }
+
+    if let Some(predictor) = predictor {
+        println!("\nBranch prediction:");
+        predictor.borrow().report(&mut io::stdout(), &cpu, top).expect("Can't print the branch prediction.");
+    }
+
+    if let Some(pipeline) = pipeline {
+        let pipeline = pipeline.borrow();
+        if let Some(ref caches) = pipeline.caches {
+            println!("\nCaches:");
+            caches.report(&mut io::stdout(), top).expect("Can't print the caches.");
+        }
+        if let Some(ref predictor) = pipeline.predictor {
+            println!("\nBranch prediction:");
+            predictor.report(&mut io::stdout(), &cpu, top).expect("Can't print the branch prediction.");
+        }
+        if matches.is_present("timing") {
+            println!("\nTiming:");
+            pipeline.report(&mut io::stdout()).expect("Can't print the timing.");
+        }
+        if let (Some(diagram), Some(path)) = (pipeline.diagram.as_ref(), matches.value_of("diagram")) {
+            let mut file = File::create(path).expect("Can't create the pipeline diagram.");
+            let result = if path.ends_with(".html") {
+                diagram.write_html(&mut file)
+            } else if path.ends_with(".svg") {
+                diagram.write_svg(&mut file)
+            } else {
+                diagram.write_text(&mut file)
+            };
+            result.expect("Can't write the pipeline diagram.");
+        }
+    }
+}
+
+fn create_trace_writer(path: &str, matches: &ArgMatches) -> Result<TraceWriter, String> {
+    let format = trace::Format::from_name(matches.value_of("trace-format").unwrap()).unwrap();
+    let mut filter = trace::Filter::default();
+
+    for range in matches.values_of("trace-range").into_iter().flatten() {
+        let mut bounds = range.splitn(2, ':');
+        let start = parse_number(bounds.next().unwrap())?;
+        let end = bounds.next()
+            .ok_or_else(|| format!("Invalid range {}.", range))
+            .and_then(parse_number)?;
+        filter.ranges.push((start, end));
+    }
+    for symbol in matches.values_of("trace-symbol").into_iter().flatten() {
+        filter.symbols.push(symbol.to_string());
+    }
+    for class in matches.values_of("trace-class").into_iter().flatten() {
+        let class = InstructionClass::from_name(class)
+            .ok_or_else(|| format!("Unknown instruction class {}.", class))?;
+        filter.classes.push(class);
+    }
+
+    TraceWriter::create(path, format, filter)
+}
+
+fn timing_config(matches: &ArgMatches) -> Result<TimingConfig, String> {
+    let mut config = TimingConfig::default();
+    for latency in matches.values_of("latency").into_iter().flatten() {
+        let mut parts = latency.splitn(2, '=');
+        let name = parts.next().unwrap();
+        let cycles = parts.next()
+            .ok_or_else(|| format!("Invalid latency {}.", latency))
+            .and_then(parse_number)?;
+        config.set(name, cycles)?;
+    }
+    Ok(config)
+}
+
+fn diagram(matches: &ArgMatches) -> Result<Diagram, String> {
+    let range = match matches.value_of("diagram-range") {
+        Some(range) => {
+            let mut bounds = range.splitn(2, ':');
+            let start = parse_number(bounds.next().unwrap())?;
+            let end = bounds.next()
+                .ok_or_else(|| format!("Invalid range {}.", range))
+                .and_then(parse_number)?;
+            Some((start, end))
+        },
+        None => None,
+    };
+    let start = parse_number(matches.value_of("diagram-start").unwrap())?;
+    let size = parse_number(matches.value_of("diagram-size").unwrap())?;
+    Ok(Diagram::new(range, start as u64, size as usize))
+}
+
+fn cache_hierarchy(matches: &ArgMatches) -> Result<CacheHierarchy, String> {
+    let (mut l1i, mut l1d, mut l2) = (CacheConfig::l1(), CacheConfig::l1(), None);
+    for cache in matches.values_of("cache").into_iter().flatten() {
+        let mut parts = cache.splitn(2, ':');
+        let level = parts.next().unwrap();
+        let options = parts.next().unwrap_or("");
+        match level {
+            "l1i" => l1i.parse(options)?,
+            "l1d" => l1d.parse(options)?,
+            "l2" => l2.get_or_insert_with(CacheConfig::l2).parse(options)?,
+            _ => return Err(format!("Unknown cache {}.", level)),
+        }
+    }
+    let memory_latency = parse_number(matches.value_of("memory-latency").unwrap())?;
+    Ok(CacheHierarchy::new(l1i, l1d, l2, memory_latency))
+}
+
+/// Reports an invalid command line the way clap does, without a panic.
+fn exit_with_error(message: &str) -> ! {
+    clap::Error::with_description(message, clap::ErrorKind::InvalidValue).exit()
+}
+
+fn parse_number(s: &str) -> Result<u32, String> {
+    let result = if s.starts_with("0x") || s.starts_with("0X") {
+        u32::from_str_radix(&s[2..], 16)
+    } else {
+        s.parse()
+    };
+    result.map_err(|_| format!("Can't parse {}.", s))
 }
@@ -1,11 +1,22 @@
 use utils;
-use instruction::Instruction;
+use instruction::{Instruction, InstructionClass, Isa};
 use cpu::{Cpu, Signal, PCOperation};
+use cop0::{self, Exception};
+use memory::AccessKind;
+use mmu::TlbEntry;
 use syscall;
 
 pub fn apply_instruction(inst: &Instruction, cpu: &mut Cpu) -> Result<(), Signal> {
     let (pcop, maybe_signal) = match apply_instruction_inner(inst, cpu) {
         Ok(pcop) => (pcop, Ok(())),
+        Err(Signal::Exception(exception)) if !cpu.has_exception_handler() => {
+            return Err(cpu.unhandled_exception(exception));
+        },
+        Err(Signal::Exception(exception)) => {
+            // the instruction is abandoned, the next one is the handler
+            cpu.take_exception(exception);
+            return Err(Signal::Exception(exception));
+        },
         Err(signal) => (PCOperation::Offset(4), Err(signal)),
     };
     cpu.move_pc(pcop);
@@ -20,6 +31,23 @@ macro_rules! check_address_aligned_word {
     }
 }
 
+macro_rules! check_coprocessor_usable {
+    ($cpu:expr) => {
+        if !$cpu.cop0.coprocessor_usable() {
+            return Err(Signal::Exception(Exception::new(cop0::EXC_CPU)))
+        }
+    }
+}
+
+macro_rules! tlb {
+    ($cpu:expr) => {
+        match $cpu.tlb {
+            Some(ref mut tlb) => tlb,
+            None => return Err(Signal::Trap("TLB instruction without MMU.".to_string())),
+        }
+    }
+}
+
 macro_rules! check_address_aligned_half_word {
     ($addr:expr) => {
         if ($addr & 0b1) != 0 {
@@ -30,7 +58,17 @@ macro_rules! check_address_aligned_half_word {
 
 fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperation, Signal> {
     let pc = cpu.pc;
+    // Release 6 reserves the delay and forbidden slots to the other instructions
+    if cpu.isa == Isa::R6 && cpu.in_branch_slot() &&
+        matches!(inst.class(), InstructionClass::Branch | InstructionClass::Jump) {
+        return Err(reserved_instruction(cpu, inst));
+    }
+
     match *inst {
+        Instruction::Unknown(_) if cpu.isa == Isa::R6 => {
+            // including the encodings removed by Release 6
+            Err(reserved_instruction(cpu, inst))
+        },
         Instruction::Unknown(inst) => {
             panic!("Unknown Instruction (pc= {:#x}). {:032b}", pc, inst);
         },
@@ -63,12 +101,27 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
             cpu.set_register(rt, utils::i2u(result));
             Ok(PCOperation::Offset(4))
         },
+        Instruction::ADDIUPC(rs, offset) => {
+            cpu.set_register(rs, pc.wrapping_add((offset << 2) as u32));
+            Ok(PCOperation::Offset(4))
+        },
         Instruction::ADDU(rs, rt, rd) => {
             let rs_value = cpu.get_register(rs);
             let rt_value = cpu.get_register(rt);
             cpu.set_register(rd, rs_value.wrapping_add(rt_value));
             Ok(PCOperation::Offset(4))
         },
+        Instruction::ALIGN(rs, rt, rd, bp) => {
+            let rs_value = cpu.get_register(rs) as u64;
+            let rt_value = cpu.get_register(rt) as u64;
+            // rt shifted left by bp bytes, filled with the high bytes of rs
+            cpu.set_register(rd, ((rt_value << 32 | rs_value) >> (32 - 8 * bp)) as u32);
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::ALUIPC(rs, imm) => {
+            cpu.set_register(rs, pc.wrapping_add(imm << 16) & !0xffff);
+            Ok(PCOperation::Offset(4))
+        },
         Instruction::AND(rs, rt, rd) => {
             let rs_value = cpu.get_register(rs);
             let rt_value = cpu.get_register(rt);
@@ -80,6 +133,61 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
             cpu.set_register(rt, rs_value & imm);
             Ok(PCOperation::Offset(4))
         },
+        Instruction::AUI(rs, rt, imm) => {
+            let rs_value = cpu.get_register(rs);
+            cpu.set_register(rt, rs_value.wrapping_add(imm << 16));
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::AUIPC(rs, imm) => {
+            cpu.set_register(rs, pc.wrapping_add(imm << 16));
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::BALC(offset) => {
+            cpu.set_register(31, pc + 4);
+            Ok(compact_branch(cpu, true, offset))
+        },
+        Instruction::BC(offset) => {
+            Ok(compact_branch(cpu, true, offset))
+        },
+        Instruction::BEQC(rs, rt, offset) | Instruction::BNEC(rs, rt, offset) |
+        Instruction::BGEC(rs, rt, offset) | Instruction::BLTC(rs, rt, offset) |
+        Instruction::BGEUC(rs, rt, offset) | Instruction::BLTUC(rs, rt, offset) |
+        Instruction::BOVC(rs, rt, offset) | Instruction::BNVC(rs, rt, offset) => {
+            let rs_value = cpu.get_register(rs);
+            let rt_value = cpu.get_register(rt);
+            let taken = match *inst {
+                Instruction::BEQC(..) => rs_value == rt_value,
+                Instruction::BNEC(..) => rs_value != rt_value,
+                Instruction::BGEC(..) => utils::u2i(rs_value) >= utils::u2i(rt_value),
+                Instruction::BLTC(..) => utils::u2i(rs_value) < utils::u2i(rt_value),
+                Instruction::BGEUC(..) => rs_value >= rt_value,
+                Instruction::BLTUC(..) => rs_value < rt_value,
+                Instruction::BOVC(..) => utils::u2i(rs_value).checked_add(utils::u2i(rt_value)).is_none(),
+                _ => utils::u2i(rs_value).checked_add(utils::u2i(rt_value)).is_some(),
+            };
+            Ok(compact_branch(cpu, taken, offset))
+        },
+        Instruction::BEQZC(reg, offset) | Instruction::BNEZC(reg, offset) |
+        Instruction::BGEZC(reg, offset) | Instruction::BGTZC(reg, offset) |
+        Instruction::BLEZC(reg, offset) | Instruction::BLTZC(reg, offset) |
+        Instruction::BEQZALC(reg, offset) | Instruction::BNEZALC(reg, offset) |
+        Instruction::BGEZALC(reg, offset) | Instruction::BGTZALC(reg, offset) |
+        Instruction::BLEZALC(reg, offset) | Instruction::BLTZALC(reg, offset) => {
+            let value = utils::u2i(cpu.get_register(reg));
+            let taken = match *inst {
+                Instruction::BEQZC(..) | Instruction::BEQZALC(..) => value == 0,
+                Instruction::BNEZC(..) | Instruction::BNEZALC(..) => value != 0,
+                Instruction::BGEZC(..) | Instruction::BGEZALC(..) => value >= 0,
+                Instruction::BGTZC(..) | Instruction::BGTZALC(..) => value > 0,
+                Instruction::BLEZC(..) | Instruction::BLEZALC(..) => value <= 0,
+                _ => value < 0,
+            };
+            // the compact calls link whether they are taken or not
+            if inst.destination() == Some(31) {
+                cpu.set_register(31, pc + 4);
+            }
+            Ok(compact_branch(cpu, taken, offset))
+        },
         Instruction::BEQ(rs, rt, offset) => {
             let rs_value = cpu.get_register(rs);
             let rt_value = cpu.get_register(rt);
@@ -90,6 +198,10 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
                 Ok(PCOperation::Offset(4))
             }
         },
+        Instruction::BEQL(rs, rt, offset) => {
+            let taken = cpu.get_register(rs) == cpu.get_register(rt);
+            Ok(branch_likely(cpu, taken, offset))
+        },
         Instruction::BGEZ(rs, offset) => {
             let rs_value = utils::u2i(cpu.get_register(rs));
             
@@ -109,6 +221,15 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
                 Ok(PCOperation::Offset(4))
             }
         },
+        Instruction::BGEZALL(rs, offset) => {
+            cpu.set_register(31, pc + 8);
+            let taken = utils::u2i(cpu.get_register(rs)) >= 0;
+            Ok(branch_likely(cpu, taken, offset))
+        },
+        Instruction::BGEZL(rs, offset) => {
+            let taken = utils::u2i(cpu.get_register(rs)) >= 0;
+            Ok(branch_likely(cpu, taken, offset))
+        },
         Instruction::BGTZ(rs, offset) => {
             let rs_value = utils::u2i(cpu.get_register(rs));
             
@@ -118,6 +239,15 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
                 Ok(PCOperation::Offset(4))
             }
         },
+        Instruction::BGTZL(rs, offset) => {
+            let taken = utils::u2i(cpu.get_register(rs)) > 0;
+            Ok(branch_likely(cpu, taken, offset))
+        },
+        Instruction::BITSWAP(rt, rd) => {
+            let rt_value = cpu.get_register(rt);
+            cpu.set_register(rd, rt_value.reverse_bits().swap_bytes());
+            Ok(PCOperation::Offset(4))
+        },
         Instruction::BLEZ(rs, offset) => {
             let rs_value = utils::u2i(cpu.get_register(rs));
 
@@ -128,6 +258,10 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
             }
 
         },
+        Instruction::BLEZL(rs, offset) => {
+            let taken = utils::u2i(cpu.get_register(rs)) <= 0;
+            Ok(branch_likely(cpu, taken, offset))
+        },
         Instruction::BLTZ(rs, offset) => {
             let rs_value = utils::u2i(cpu.get_register(rs));
 
@@ -147,6 +281,15 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
                 Ok(PCOperation::Offset(4))
             }
         },
+        Instruction::BLTZALL(rs, offset) => {
+            cpu.set_register(31, pc + 8);
+            let taken = utils::u2i(cpu.get_register(rs)) < 0;
+            Ok(branch_likely(cpu, taken, offset))
+        },
+        Instruction::BLTZL(rs, offset) => {
+            let taken = utils::u2i(cpu.get_register(rs)) < 0;
+            Ok(branch_likely(cpu, taken, offset))
+        },
         Instruction::BNE(rs, rt, offset) => {
             let rs_value = cpu.get_register(rs);
             let rt_value = cpu.get_register(rt);
@@ -157,9 +300,38 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
                 Ok(PCOperation::Offset(4))
             }
         },
+        Instruction::BNEL(rs, rt, offset) => {
+            let taken = cpu.get_register(rs) != cpu.get_register(rt);
+            Ok(branch_likely(cpu, taken, offset))
+        },
         Instruction::BREAK => {
             Err(Signal::Breakpoint(pc))
         },
+        Instruction::CACHE(..) => {
+            // there are no caches to maintain
+            check_coprocessor_usable!(cpu);
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::CLO(rs, rd) => {
+            let rs_value = cpu.get_register(rs);
+            cpu.set_register(rd, rs_value.leading_ones());
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::CLZ(rs, rd) => {
+            let rs_value = cpu.get_register(rs);
+            cpu.set_register(rd, rs_value.leading_zeros());
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::DI(rt) | Instruction::EI(rt) => {
+            check_coprocessor_usable!(cpu);
+            let status = cpu.cop0.status;
+            cpu.set_register(rt, status);
+            cpu.cop0.status = match *inst {
+                Instruction::DI(_) => status & !cop0::STATUS_IE,
+                _ => status | cop0::STATUS_IE,
+            };
+            Ok(PCOperation::Offset(4))
+        },
         Instruction::DIV(rs, rt) => {
             let rs_value = utils::u2i(cpu.get_register(rs));
             let rt_value = utils::u2i(cpu.get_register(rt));
@@ -185,6 +357,47 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
 
             Ok(PCOperation::Offset(4))
         },
+        // the result of a division by zero is unpredictable
+        Instruction::DIVR6(rs, rt, rd) | Instruction::MOD(rs, rt, rd) => {
+            let rs_value = utils::u2i(cpu.get_register(rs));
+            let rt_value = utils::u2i(cpu.get_register(rt));
+            let result = match *inst {
+                _ if rt_value == 0 => 0,
+                Instruction::DIVR6(..) => rs_value.wrapping_div(rt_value),
+                _ => rs_value.wrapping_rem(rt_value),
+            };
+            cpu.set_register(rd, utils::i2u(result));
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::DIVUR6(rs, rt, rd) | Instruction::MODU(rs, rt, rd) => {
+            let rs_value = cpu.get_register(rs);
+            let rt_value = cpu.get_register(rt);
+            let result = match *inst {
+                Instruction::DIVUR6(..) => rs_value.checked_div(rt_value),
+                _ => rs_value.checked_rem(rt_value),
+            };
+            cpu.set_register(rd, result.unwrap_or(0));
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::ERET => {
+            check_coprocessor_usable!(cpu);
+            // no delay slot, the next instruction is the one at EPC
+            cpu.npc = cpu.cop0.exception_return();
+            cpu.link = None;
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::EXT(rs, rt, pos, size) => {
+            let rs_value = cpu.get_register(rs);
+            cpu.set_register(rt, (rs_value >> pos) & low_bits(size));
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::INS(rs, rt, pos, size) => {
+            let rs_value = cpu.get_register(rs);
+            let rt_value = cpu.get_register(rt);
+            let mask = low_bits(size) << pos;
+            cpu.set_register(rt, (rt_value & !mask) | ((rs_value << pos) & mask));
+            Ok(PCOperation::Offset(4))
+        },
         Instruction::J(instr_index) => {
             Ok(PCOperation::JumpCompute(instr_index))
         },
@@ -197,12 +410,24 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
             let addr = cpu.get_register(rs);
             Ok(PCOperation::JumpReal(addr))
         },
+        Instruction::JIALC(rt, offset) => {
+            let addr = utils::offset_addr(cpu.get_register(rt), offset);
+            cpu.set_register(31, pc + 4);
+            // no delay slot, the next instruction is the target
+            cpu.npc = addr;
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::JIC(rt, offset) => {
+            cpu.npc = utils::offset_addr(cpu.get_register(rt), offset);
+            Ok(PCOperation::Offset(4))
+        },
         Instruction::JR(rs) => {
             let addr = cpu.get_register(rs);
             Ok(PCOperation::JumpReal(addr))
         },
         Instruction::LB(base, rt, offset) => {
             let addr = utils::offset_addr(cpu.get_register(base), offset);
+            let addr = cpu.translate(addr, AccessKind::Read)?;
 
             let byte = cpu.memory.get_byte(addr) as i8;
             cpu.set_register(rt, utils::i2u(byte as i32));
@@ -210,6 +435,7 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
         },
         Instruction::LBU(base, rt, offset) => {
             let addr = utils::offset_addr(cpu.get_register(base), offset);
+            let addr = cpu.translate(addr, AccessKind::Read)?;
 
             let byte = cpu.memory.get_byte(addr);
             cpu.set_register(rt, byte as u32);
@@ -218,6 +444,7 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
         Instruction::LH(base, rt, offset) => {
             let addr = utils::offset_addr(cpu.get_register(base), offset);
             check_address_aligned_half_word!(addr);
+            let addr = cpu.translate(addr, AccessKind::Read)?;
 
             let half = cpu.memory.get_half_word(addr) as i16;
             cpu.set_register(rt, utils::i2u(half as i32));
@@ -226,11 +453,28 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
         Instruction::LHU(base, rt, offset) => {
             let addr = utils::offset_addr(cpu.get_register(base), offset);
             check_address_aligned_half_word!(addr);
+            let addr = cpu.translate(addr, AccessKind::Read)?;
 
             let half = cpu.memory.get_half_word(addr);
             cpu.set_register(rt, half as u32);
             Ok(PCOperation::Offset(4))
         },
+        Instruction::LL(base, rt, offset) => {
+            let addr = utils::offset_addr(cpu.get_register(base), offset);
+            check_address_aligned_word!(addr);
+            let addr = cpu.translate(addr, AccessKind::Read)?;
+
+            let word = cpu.memory.get_word(addr);
+            cpu.set_register(rt, word);
+            cpu.set_link(addr);
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::LSA(rs, rt, rd, shift) => {
+            let rs_value = cpu.get_register(rs);
+            let rt_value = cpu.get_register(rt);
+            cpu.set_register(rd, (rs_value << shift).wrapping_add(rt_value));
+            Ok(PCOperation::Offset(4))
+        },
         Instruction::LUI(rt, imm) => {
             cpu.set_register(rt, imm << 16);
             Ok(PCOperation::Offset(4))
@@ -239,6 +483,7 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
             let addr = utils::offset_addr(cpu.get_register(base), offset);
 
             check_address_aligned_word!(addr);
+            let addr = cpu.translate(addr, AccessKind::Read)?;
             
             let word = cpu.memory.get_word(addr);
             cpu.set_register(rt, word);
@@ -247,6 +492,7 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
         Instruction::LWL(base, rt, offset) => {
             let rt_value = cpu.get_register(rt);
             let addr = utils::offset_addr(cpu.get_register(base), offset);
+            let addr = cpu.translate(addr, AccessKind::Read)?;
 
             let unaligned_offset = addr & 0b11;
 
@@ -264,6 +510,7 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
         Instruction::LWR(base, rt, offset) => {
             let rt_value = cpu.get_register(rt);
             let addr = utils::offset_addr(cpu.get_register(base), offset);
+            let addr = cpu.translate(addr, AccessKind::Read)?;
 
             let unaligned_offset = addr & 0b11;
 
@@ -278,6 +525,46 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
             cpu.set_register(rt, result);
             Ok(PCOperation::Offset(4))
         },
+        Instruction::LWPC(rs, offset) => {
+            let addr = pc.wrapping_add((offset << 2) as u32);
+            let addr = cpu.translate(addr, AccessKind::Read)?;
+
+            let word = cpu.memory.get_word(addr);
+            cpu.set_register(rs, word);
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::MADD(rs, rt) | Instruction::MSUB(rs, rt) => {
+            let rs_value = utils::u2i(cpu.get_register(rs)) as i64;
+            let rt_value = utils::u2i(cpu.get_register(rt)) as i64;
+            let hilo = ((cpu.hi as u64) << 32 | cpu.lo as u64) as i64;
+
+            let result = match *inst {
+                Instruction::MADD(..) => hilo.wrapping_add(rs_value * rt_value),
+                _ => hilo.wrapping_sub(rs_value * rt_value),
+            };
+            cpu.lo = result as u32;
+            cpu.hi = (result >> 32) as u32;
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::MADDU(rs, rt) | Instruction::MSUBU(rs, rt) => {
+            let rs_value = cpu.get_register(rs) as u64;
+            let rt_value = cpu.get_register(rt) as u64;
+            let hilo = (cpu.hi as u64) << 32 | cpu.lo as u64;
+
+            let result = match *inst {
+                Instruction::MADDU(..) => hilo.wrapping_add(rs_value * rt_value),
+                _ => hilo.wrapping_sub(rs_value * rt_value),
+            };
+            cpu.lo = result as u32;
+            cpu.hi = (result >> 32) as u32;
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::MFC0(rt, rd, sel) => {
+            check_coprocessor_usable!(cpu);
+            let value = cpu.cop0.read(rd, sel);
+            cpu.set_register(rt, value);
+            Ok(PCOperation::Offset(4))
+        },
         Instruction::MFHI(rd) => {
             let value = cpu.hi;
             cpu.set_register(rd, value);
@@ -288,6 +575,12 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
             cpu.set_register(rd, value);
             Ok(PCOperation::Offset(4))
         },
+        Instruction::MTC0(rt, rd, sel) => {
+            check_coprocessor_usable!(cpu);
+            let value = cpu.get_register(rt);
+            cpu.cop0.write(rd, sel, value);
+            Ok(PCOperation::Offset(4))
+        },
         Instruction::MTHI(rs) => {
             cpu.hi = cpu.get_register(rs);
             Ok(PCOperation::Offset(4))
@@ -321,6 +614,18 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
             cpu.set_register(rd, result);
             Ok(PCOperation::Offset(4))
         },
+        Instruction::MUH(rs, rt, rd) => {
+            let rs_value = utils::u2i(cpu.get_register(rs)) as i64;
+            let rt_value = utils::u2i(cpu.get_register(rt)) as i64;
+            cpu.set_register(rd, ((rs_value * rt_value) >> 32) as u32);
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::MUHU(rs, rt, rd) => {
+            let rs_value = cpu.get_register(rs) as u64;
+            let rt_value = cpu.get_register(rt) as u64;
+            cpu.set_register(rd, ((rs_value * rt_value) >> 32) as u32);
+            Ok(PCOperation::Offset(4))
+        },
         Instruction::MULT(rs, rt) => {
             let rs_value = utils::u2i(cpu.get_register(rs)) as i64;
             let rt_value = utils::u2i(cpu.get_register(rt)) as i64;
@@ -339,6 +644,12 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
             cpu.hi = (result >> 32) as u32;
             Ok(PCOperation::Offset(4))
         },
+        Instruction::MULU(rs, rt, rd) => {
+            let rs_value = cpu.get_register(rs);
+            let rt_value = cpu.get_register(rt);
+            cpu.set_register(rd, rs_value.wrapping_mul(rt_value));
+            Ok(PCOperation::Offset(4))
+        },
         Instruction::NOR(rs, rt, rd) => {
             let rs_value = cpu.get_register(rs);
             let rt_value = cpu.get_register(rt);
@@ -356,13 +667,74 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
             cpu.set_register(rt, rs_value | imm);
             Ok(PCOperation::Offset(4))
         },
+        Instruction::RDHWR(rt, rd) => {
+            if !cpu.cop0.hardware_register_enabled(rd) {
+                return Err(reserved_instruction(cpu, inst));
+            }
+            let value = match rd {
+                0 => 0, // CPUNum
+                1 => 0, // SYNCI_Step, no caches to synchronize
+                2 => cpu.cop0.count, // CC
+                3 => 1, // CCRes, Count increments every instruction
+                29 => cpu.cop0.user_local, // ULR
+                _ => return Err(reserved_instruction(cpu, inst)),
+            };
+            cpu.set_register(rt, value);
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::ROTR(rt, rd, shift) => {
+            let rt_value = cpu.get_register(rt);
+            cpu.set_register(rd, rt_value.rotate_right(shift));
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::ROTRV(rs, rt, rd) => {
+            let rt_value = cpu.get_register(rt);
+            let shift = (cpu.get_register(rs) << 27) >> 27;
+            cpu.set_register(rd, rt_value.rotate_right(shift));
+            Ok(PCOperation::Offset(4))
+        },
         Instruction::SB(base, rt, offset) => {
             let word = cpu.get_register(rt);
             let byte = word as u8;
 
             let addr = utils::offset_addr(cpu.get_register(base), offset);
 
-            cpu.memory.set_byte(addr, byte);
+            cpu.store_byte(addr, byte)?;
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::SC(base, rt, offset) => {
+            let addr = utils::offset_addr(cpu.get_register(base), offset);
+            check_address_aligned_word!(addr);
+            let addr = cpu.translate(addr, AccessKind::Write)?;
+
+            // the store only happens if nothing touched the line since `ll`
+            if cpu.take_link(addr) {
+                let word = cpu.get_register(rt);
+                cpu.memory.set_word(addr, word);
+                cpu.set_register(rt, 1);
+            } else {
+                cpu.set_register(rt, 0);
+            }
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::SEB(rt, rd) => {
+            let byte = cpu.get_register(rt) as i8;
+            cpu.set_register(rd, utils::i2u(byte as i32));
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::SEH(rt, rd) => {
+            let half = cpu.get_register(rt) as i16;
+            cpu.set_register(rd, utils::i2u(half as i32));
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::SELEQZ(rs, rt, rd) | Instruction::SELNEZ(rs, rt, rd) => {
+            let rs_value = cpu.get_register(rs);
+            let rt_value = cpu.get_register(rt);
+            let select = match *inst {
+                Instruction::SELEQZ(..) => rt_value == 0,
+                _ => rt_value != 0,
+            };
+            cpu.set_register(rd, if select { rs_value } else { 0 });
             Ok(PCOperation::Offset(4))
         },
         Instruction::SH(base, rt, offset) => {
@@ -371,7 +743,7 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
 
             let addr = utils::offset_addr(cpu.get_register(base), offset);
 
-            cpu.memory.set_half_word(addr, half);
+            cpu.store_half_word(addr, half)?;
             Ok(PCOperation::Offset(4))
         },
         Instruction::SLL(rt, rd, shift) => {
@@ -459,13 +831,14 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
             check_address_aligned_word!(addr);
 
             let word = cpu.get_register(rt);
-            cpu.memory.set_word(addr, word);
+            cpu.store_word(addr, word)?;
 
             Ok(PCOperation::Offset(4))
         },
         Instruction::SWL(base, rt, offset) => {
             let rt_value = cpu.get_register(rt);
             let addr = utils::offset_addr(cpu.get_register(base), offset);
+            let addr = cpu.translate(addr, AccessKind::Write)?;
             
             let unaligned_offset = addr & 0b11;
             let mem_part = if unaligned_offset != 3 {
@@ -477,11 +850,13 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
             let reg_part = rt_value >> (8 * (3 - unaligned_offset));
 
             cpu.memory.set_word(addr - unaligned_offset, mem_part | reg_part);
+            cpu.monitor_store(addr);
             Ok(PCOperation::Offset(4))
         },
         Instruction::SWR(base, rt, offset) => {
             let rt_value = cpu.get_register(rt);
             let addr = utils::offset_addr(cpu.get_register(base), offset);
+            let addr = cpu.translate(addr, AccessKind::Write)?;
 
             let unaligned_offset = addr & 0b11;
             let mem_part = if unaligned_offset != 0 {
@@ -493,17 +868,82 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
             let reg_part = rt_value << 8 * unaligned_offset;
             
             cpu.memory.set_word(addr - unaligned_offset, mem_part | reg_part);
+            cpu.monitor_store(addr);
+            Ok(PCOperation::Offset(4))
+        },
+        // the memory is sequentially consistent and the hazards are always cleared
+        Instruction::SYNC(_) | Instruction::PREF(..) | Instruction::SSNOP | Instruction::EHB => {
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::SYNCI(base, offset) => {
+            // there are no caches to synchronize, only the translation can fail
+            let addr = utils::offset_addr(cpu.get_register(base), offset);
+            cpu.translate(addr, AccessKind::Read)?;
             Ok(PCOperation::Offset(4))
         },
         Instruction::SYSCALL => syscall::call_syscall(cpu),
-        Instruction::TEQ(rs, rt) => {
+        Instruction::TEQ(rs, rt) | Instruction::TGE(rs, rt) | Instruction::TGEU(rs, rt) |
+        Instruction::TLT(rs, rt) | Instruction::TLTU(rs, rt) | Instruction::TNE(rs, rt) => {
             let rs_value = cpu.get_register(rs);
             let rt_value = cpu.get_register(rt);
-            if rs_value == rt_value {
-                Err(Signal::Trap("TEQ trap".to_string()))
-            } else {
-                Ok(PCOperation::Offset(4))
-            }
+            let (trap, name) = match *inst {
+                Instruction::TEQ(..) => (rs_value == rt_value, "TEQ"),
+                Instruction::TGE(..) => (utils::u2i(rs_value) >= utils::u2i(rt_value), "TGE"),
+                Instruction::TGEU(..) => (rs_value >= rt_value, "TGEU"),
+                Instruction::TLT(..) => (utils::u2i(rs_value) < utils::u2i(rt_value), "TLT"),
+                Instruction::TLTU(..) => (rs_value < rt_value, "TLTU"),
+                _ => (rs_value != rt_value, "TNE"),
+            };
+            trap_if(cpu, trap, name)
+        },
+        Instruction::TEQI(rs, imm) | Instruction::TGEI(rs, imm) | Instruction::TGEIU(rs, imm) |
+        Instruction::TLTI(rs, imm) | Instruction::TLTIU(rs, imm) | Instruction::TNEI(rs, imm) => {
+            let rs_value = cpu.get_register(rs);
+            // the unsigned forms compare with the sign-extended immediate
+            let (trap, name) = match *inst {
+                Instruction::TEQI(..) => (rs_value == imm as u32, "TEQI"),
+                Instruction::TGEI(..) => (utils::u2i(rs_value) >= imm, "TGEI"),
+                Instruction::TGEIU(..) => (rs_value >= imm as u32, "TGEIU"),
+                Instruction::TLTI(..) => (utils::u2i(rs_value) < imm, "TLTI"),
+                Instruction::TLTIU(..) => (rs_value < imm as u32, "TLTIU"),
+                _ => (rs_value != imm as u32, "TNEI"),
+            };
+            trap_if(cpu, trap, name)
+        },
+        Instruction::TLBP => {
+            check_coprocessor_usable!(cpu);
+            let entry_hi = cpu.cop0.entry_hi;
+            let index = tlb!(cpu).probe(entry_hi, entry_hi & cop0::ENTRY_HI_ASID);
+            cpu.cop0.index = match index {
+                Some(index) => index as u32,
+                None => cop0::INDEX_PROBE_FAILURE,
+            };
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::TLBR => {
+            check_coprocessor_usable!(cpu);
+            let index = cpu.cop0.index;
+            let entry = tlb!(cpu).read(index);
+            cpu.cop0.page_mask = entry.page_mask;
+            cpu.cop0.entry_hi = entry.entry_hi;
+            cpu.cop0.entry_lo0 = entry.entry_lo0;
+            cpu.cop0.entry_lo1 = entry.entry_lo1;
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::TLBWI | Instruction::TLBWR => {
+            check_coprocessor_usable!(cpu);
+            let index = match *inst {
+                Instruction::TLBWI => cpu.cop0.index,
+                _ => cpu.cop0.random,
+            };
+            let entry = TlbEntry::from_cop0(&cpu.cop0);
+            tlb!(cpu).write(index, entry);
+            Ok(PCOperation::Offset(4))
+        },
+        Instruction::WSBH(rt, rd) => {
+            let rt_value = cpu.get_register(rt);
+            cpu.set_register(rd, (rt_value & 0x00ff00ff) << 8 | (rt_value >> 8) & 0x00ff00ff);
+            Ok(PCOperation::Offset(4))
         },
         Instruction::XOR(rs, rt, rd) => {
             let rs_value = cpu.get_register(rs);
@@ -518,3 +958,187 @@ fn apply_instruction_inner(inst: &Instruction, cpu: &mut Cpu) -> Result<PCOperat
         },
     }
 }
+
+/// Mask of the `size` low bits, `size` being 1 to 32.
+fn low_bits(size: u32) -> u32 {
+    0xFFFFFFFFu32 >> (32 - size)
+}
+
+/// A branch likely executes its delay slot only when it is taken, the next
+/// instruction being the one after the delay slot otherwise.
+fn branch_likely(cpu: &mut Cpu, taken: bool, offset: i32) -> PCOperation {
+    if taken {
+        PCOperation::Offset(offset << 2)
+    } else {
+        cpu.npc = cpu.npc.wrapping_add(4);
+        PCOperation::Offset(4)
+    }
+}
+
+/// A compact branch has no delay slot, the next instruction is the target
+/// when it is taken.
+fn compact_branch(cpu: &mut Cpu, taken: bool, offset: i32) -> PCOperation {
+    if taken {
+        cpu.npc = utils::offset_addr(cpu.npc, offset << 2);
+    }
+    PCOperation::Offset(4)
+}
+
+/// The Reserved Instruction exception, or a stop when the guest has no
+/// handler for it.
+fn reserved_instruction(cpu: &Cpu, inst: &Instruction) -> Signal {
+    if cpu.has_exception_handler() {
+        return Signal::Exception(Exception::new(cop0::EXC_RI));
+    }
+    match *inst {
+        Instruction::Unknown(word) => Signal::Trap(format!("reserved instruction {:#010x} (pc={:#x})", word, cpu.pc)),
+        _ => Signal::Trap(format!("reserved instruction {} (pc={:#x})", inst, cpu.pc)),
+    }
+}
+
+/// The Trap exception when `trap`, or a stop when the guest has no handler
+/// for it.
+fn trap_if(cpu: &Cpu, trap: bool, name: &str) -> Result<PCOperation, Signal> {
+    if !trap {
+        Ok(PCOperation::Offset(4))
+    } else if cpu.has_exception_handler() {
+        Err(Signal::Exception(Exception::new(cop0::EXC_TR)))
+    } else {
+        Err(Signal::Trap(format!("{} trap", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn execute(cpu: &mut Cpu, inst: Instruction) -> Result<(), Signal> {
+        apply_instruction(&inst, cpu)
+    }
+
+    fn result(inst: Instruction, rs_value: u32, rt_value: u32) -> u32 {
+        let mut cpu = Cpu::new();
+        cpu.set_register(4, rs_value);
+        cpu.set_register(5, rt_value);
+        execute(&mut cpu, inst).unwrap();
+        cpu.get_register(6)
+    }
+
+    #[test]
+    fn ext_and_ins_reach_bit_31() {
+        assert_eq!(result(Instruction::EXT(4, 6, 28, 4), 0xf000_0000, 0), 0xf);
+        assert_eq!(result(Instruction::EXT(4, 6, 0, 32), 0x1234_5678, 0), 0x1234_5678);
+
+        let mut cpu = Cpu::new();
+        cpu.set_register(4, 0x0000_000a);
+        cpu.set_register(5, 0x1234_5678);
+        execute(&mut cpu, Instruction::INS(4, 5, 28, 4)).unwrap();
+        assert_eq!(cpu.get_register(5), 0xa234_5678);
+        execute(&mut cpu, Instruction::INS(4, 5, 0, 32)).unwrap();
+        assert_eq!(cpu.get_register(5), 0x0000_000a);
+    }
+
+    #[test]
+    fn rotr_by_zero() {
+        assert_eq!(result(Instruction::ROTR(5, 6, 0), 0, 0x8000_0001), 0x8000_0001);
+        assert_eq!(result(Instruction::ROTR(5, 6, 1), 0, 0x8000_0001), 0xc000_0000);
+        assert_eq!(result(Instruction::ROTRV(4, 5, 6), 32, 0x8000_0001), 0x8000_0001);
+    }
+
+    #[test]
+    fn clz_and_clo() {
+        assert_eq!(result(Instruction::CLZ(4, 6), 0, 0), 32);
+        assert_eq!(result(Instruction::CLZ(4, 6), !0, 0), 0);
+        assert_eq!(result(Instruction::CLO(4, 6), 0, 0), 0);
+        assert_eq!(result(Instruction::CLO(4, 6), !0, 0), 32);
+    }
+
+    #[test]
+    fn madd_and_msub_carry_between_lo_and_hi() {
+        let mut cpu = Cpu::new();
+        cpu.set_register(4, 1);
+        cpu.set_register(5, 1);
+        cpu.lo = 0xffff_ffff;
+        execute(&mut cpu, Instruction::MADD(4, 5)).unwrap();
+        assert_eq!((cpu.hi, cpu.lo), (1, 0));
+        execute(&mut cpu, Instruction::MSUB(4, 5)).unwrap();
+        assert_eq!((cpu.hi, cpu.lo), (0, 0xffff_ffff));
+
+        cpu.lo = 0;
+        execute(&mut cpu, Instruction::MSUB(4, 5)).unwrap();
+        assert_eq!((cpu.hi, cpu.lo), (0xffff_ffff, 0xffff_ffff));
+        cpu.set_register(5, 0xffff_ffff); // -1
+        execute(&mut cpu, Instruction::MADD(4, 5)).unwrap();
+        assert_eq!((cpu.hi, cpu.lo), (0xffff_ffff, 0xffff_fffe));
+    }
+
+    #[test]
+    fn byte_and_half_word_moves() {
+        assert_eq!(result(Instruction::WSBH(5, 6), 0, 0x1122_3344), 0x2211_4433);
+        assert_eq!(result(Instruction::SEB(5, 6), 0, 0x0000_0180), 0xffff_ff80);
+        assert_eq!(result(Instruction::SEB(5, 6), 0, 0xffff_ff7f), 0x0000_007f);
+        assert_eq!(result(Instruction::SEH(5, 6), 0, 0x0001_8000), 0xffff_8000);
+        assert_eq!(result(Instruction::SEH(5, 6), 0, 0xffff_7fff), 0x0000_7fff);
+    }
+
+    #[test]
+    fn di_and_ei_return_the_previous_status() {
+        let mut cpu = Cpu::new();
+        cpu.cop0.status = cop0::STATUS_IE | cop0::STATUS_IM;
+        execute(&mut cpu, Instruction::DI(6)).unwrap();
+        assert_eq!(cpu.get_register(6), cop0::STATUS_IE | cop0::STATUS_IM);
+        assert_eq!(cpu.cop0.status, cop0::STATUS_IM);
+        execute(&mut cpu, Instruction::EI(6)).unwrap();
+        assert_eq!(cpu.get_register(6), cop0::STATUS_IM);
+        assert_eq!(cpu.cop0.status, cop0::STATUS_IE | cop0::STATUS_IM);
+    }
+
+    #[test]
+    fn rdhwr_needs_hwrena_in_user_mode() {
+        let mut cpu = Cpu::new();
+        cpu.bare_metal = true;
+        cpu.cop0.user_local = 0x1234;
+        cpu.cop0.status = cop0::STATUS_USER;
+        match execute(&mut cpu, Instruction::RDHWR(6, 29)) {
+            Err(Signal::Exception(_)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!((cpu.cop0.cause >> 2) & 0x1f, cop0::EXC_RI);
+        assert_eq!(cpu.get_register(6), 0);
+
+        cpu.cop0.status = cop0::STATUS_USER;
+        cpu.cop0.hwrena = 1 << 29;
+        execute(&mut cpu, Instruction::RDHWR(6, 29)).unwrap();
+        assert_eq!(cpu.get_register(6), 0x1234);
+    }
+
+    #[test]
+    fn rdhwr_stops_without_exception_handler() {
+        let mut cpu = Cpu::new();
+        cpu.cop0.status = cop0::STATUS_USER;
+        match execute(&mut cpu, Instruction::RDHWR(6, 2)) {
+            Err(Signal::Trap(_)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn release_6_branch_in_a_slot_is_reserved() {
+        let mut cpu = Cpu::new();
+        cpu.isa = Isa::R6;
+        cpu.memory.set_word(0, 0x1000_0004); // beq $0, $0, 4
+        cpu.memory.set_word(4, 0xc800_0004); // bc 4, in the delay slot
+        assert_eq!(cpu.run(true, false), None);
+        match cpu.run(true, false) {
+            Some(Signal::Trap(_)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+
+        cpu.bare_metal = true;
+        cpu.pc = 0;
+        cpu.npc = 4;
+        assert_eq!(cpu.run(true, false), None);
+        assert_eq!(cpu.run(true, false), None);
+        assert_eq!((cpu.cop0.cause >> 2) & 0x1f, cop0::EXC_RI);
+    }
+}
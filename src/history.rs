@@ -0,0 +1,254 @@
+use std::collections::VecDeque;
+
+use cop0::Cop0;
+use memory::Memory;
+use mmu::{Tlb, TlbEntry};
+
+pub const DEFAULT_BUDGET: usize = 1_000_000;
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 100_000;
+
+/// Undo log of the last executed instructions.
+///
+/// At most `budget` steps are kept, the oldest ones are dropped first. Every
+/// `checkpoint_interval` instructions a full copy of the machine is taken so
+/// that going back a long way does not require undoing every single step.
+#[derive(Debug, Clone)]
+pub struct History {
+    budget: usize,
+    checkpoint_interval: u64,
+    first_count: u64,
+    steps: VecDeque<Step>,
+    checkpoints: VecDeque<Checkpoint>,
+}
+
+/// State overwritten by the execution of one instruction.
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub pc: u32,
+    pub npc: u32,
+    pub hi: u32,
+    pub lo: u32,
+    pub cop0: Cop0,
+    pub delay_slot: bool,
+    pub forbidden_slot: bool,
+    pub link: Option<u32>,
+    pub tlb: Option<(usize, TlbEntry)>, // index, previous value
+    pub registers: Vec<(u32, u32)>, // index, previous value
+    pub memory: Vec<(u32, u8)>, // address, previous value
+}
+
+/// Full machine state before the execution of the `count`-th instruction.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub count: u64,
+    pub registers: [u32; 31],
+    pub hi: u32,
+    pub lo: u32,
+    pub pc: u32,
+    pub npc: u32,
+    pub cop0: Cop0,
+    pub delay_slot: bool,
+    pub forbidden_slot: bool,
+    pub link: Option<u32>,
+    pub tlb: Option<Tlb>,
+    pub memory: Memory,
+}
+
+impl History {
+    pub fn new(budget: usize, checkpoint_interval: u64) -> History {
+        History {
+            budget,
+            checkpoint_interval: checkpoint_interval.max(1),
+            first_count: 0,
+            steps: VecDeque::new(),
+            checkpoints: VecDeque::new(),
+        }
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    pub fn checkpoint_interval(&self) -> u64 {
+        self.checkpoint_interval
+    }
+
+    /// Number of steps that can be undone.
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn checkpoint_count(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.steps.clear();
+        self.checkpoints.clear();
+    }
+
+    /// Whether a checkpoint should be taken before executing the `count`-th instruction.
+    pub fn wants_checkpoint(&self, count: u64) -> bool {
+        count.is_multiple_of(self.checkpoint_interval)
+            && self.checkpoints.back().is_none_or(|cp| cp.count < count)
+    }
+
+    pub fn push_checkpoint(&mut self, checkpoint: Checkpoint) {
+        self.checkpoints.push_back(checkpoint);
+    }
+
+    /// Records the undo step of the `count`-th instruction.
+    pub fn push(&mut self, count: u64, step: Step) {
+        if self.end_count() != count {
+            // the machine moved without us, older steps can't be undone anymore
+            self.steps.clear();
+            self.checkpoints.retain(|cp| cp.count == count);
+            self.first_count = count;
+        }
+
+        self.steps.push_back(step);
+        while self.steps.len() > self.budget {
+            self.steps.pop_front();
+            self.first_count += 1;
+        }
+
+        let first_count = self.first_count;
+        while self.checkpoints.front().is_some_and(|cp| cp.count < first_count) {
+            self.checkpoints.pop_front();
+        }
+    }
+
+    /// Removes the most recent step.
+    pub fn pop(&mut self) -> Option<Step> {
+        let step = self.steps.pop_back()?;
+
+        let end_count = self.end_count();
+        while self.checkpoints.back().is_some_and(|cp| cp.count > end_count) {
+            self.checkpoints.pop_back();
+        }
+        Some(step)
+    }
+
+    /// Returns the oldest checkpoint taken at or after `target` and drops
+    /// every step and checkpoint that happened after it.
+    pub fn rewind_to_checkpoint(&mut self, target: u64) -> Option<Checkpoint> {
+        let end_count = self.end_count();
+        let index = self.checkpoints
+            .iter()
+            .position(|cp| cp.count >= target && cp.count < end_count)?;
+
+        self.checkpoints.truncate(index + 1);
+        let checkpoint = self.checkpoints[index].clone();
+        self.steps.truncate((checkpoint.count - self.first_count) as usize);
+        Some(checkpoint)
+    }
+
+    fn end_count(&self) -> u64 {
+        self.first_count + self.steps.len() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(pc: u32) -> Step {
+        Step {
+            pc,
+            npc: pc + 4,
+            hi: 0,
+            lo: 0,
+            cop0: Cop0::new(),
+            delay_slot: false,
+            forbidden_slot: false,
+            link: None,
+            tlb: None,
+            registers: Vec::new(),
+            memory: Vec::new(),
+        }
+    }
+
+    fn checkpoint(count: u64) -> Checkpoint {
+        Checkpoint {
+            count,
+            registers: [0; 31],
+            hi: 0,
+            lo: 0,
+            pc: count as u32 * 4,
+            npc: count as u32 * 4 + 4,
+            cop0: Cop0::new(),
+            delay_slot: false,
+            forbidden_slot: false,
+            link: None,
+            tlb: None,
+            memory: Memory::new(),
+        }
+    }
+
+    // executes the instructions from `start` to `end` excluded, as the cpu does
+    fn record(history: &mut History, start: u64, end: u64) {
+        for count in start..end {
+            if history.wants_checkpoint(count) {
+                history.push_checkpoint(checkpoint(count));
+            }
+            history.push(count, step(count as u32 * 4));
+        }
+    }
+
+    #[test]
+    fn push_keeps_the_budget() {
+        let mut history = History::new(5, 4);
+        record(&mut history, 0, 10);
+
+        assert_eq!(history.len(), 5);
+        // the checkpoints at 0 and 4 are older than the oldest step
+        assert_eq!(history.checkpoint_count(), 1);
+        assert_eq!(history.pop().unwrap().pc, 36);
+    }
+
+    #[test]
+    fn push_after_a_gap_drops_the_old_steps() {
+        let mut history = History::new(100, 4);
+        record(&mut history, 0, 6);
+        record(&mut history, 8, 10);
+
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.checkpoint_count(), 1);
+        assert_eq!(history.pop().unwrap().pc, 36);
+        assert_eq!(history.pop().unwrap().pc, 32);
+        assert!(history.pop().is_none());
+    }
+
+    #[test]
+    fn pop_drops_the_later_checkpoints() {
+        let mut history = History::new(100, 4);
+        record(&mut history, 0, 5);
+        assert_eq!(history.checkpoint_count(), 2);
+
+        assert_eq!(history.pop().unwrap().pc, 16);
+        assert_eq!(history.checkpoint_count(), 2);
+        assert_eq!(history.pop().unwrap().pc, 12);
+        assert_eq!(history.checkpoint_count(), 1);
+        assert_eq!(history.len(), 3);
+    }
+
+    #[test]
+    fn rewind_to_checkpoint_truncates_the_history() {
+        let mut history = History::new(100, 4);
+        record(&mut history, 0, 10);
+
+        let checkpoint = history.rewind_to_checkpoint(3).unwrap();
+        assert_eq!(checkpoint.count, 4);
+        assert_eq!(history.len(), 4);
+        assert_eq!(history.checkpoint_count(), 2);
+        assert_eq!(history.pop().unwrap().pc, 12);
+
+        // no checkpoint after the end of the history
+        assert!(history.rewind_to_checkpoint(5).is_none());
+        assert_eq!(history.len(), 3);
+    }
+}
@@ -0,0 +1,354 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::mpsc::Receiver;
+
+use device;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HEADER: &str = "# mips_emu input log v1";
+
+/// Non-deterministic value handed to the guest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Stdin(Vec<u8>),
+    Time(u64), // milliseconds since the epoch
+    Random(u32),
+    Poll(u8), // byte of stdin polled by a device
+}
+
+#[derive(Debug, Clone)]
+enum Mode {
+    Live,
+    Record(Rc<RefCell<BufWriter<File>>>), // shared by the clones of the CPU
+    Replay,
+}
+
+/// Source of every external input of the guest.
+///
+/// Events are keyed by the instruction count of the syscall that asked for
+/// them, or after which a device polled stdin. While recording, each new
+/// event is appended to the log file; while replaying, events only come
+/// from the log. In both modes an instruction executed again (after a
+/// reverse step for instance) gets the same event.
+#[derive(Debug, Clone)]
+pub struct Input {
+    mode: Mode,
+    events: BTreeMap<u64, Event>,
+    polls: BTreeMap<u64, u8>,
+    stdin: Rc<RefCell<Option<Receiver<u8>>>>, // read by a thread once a device polls it
+    rng_state: u64,
+}
+
+impl Input {
+    pub fn live() -> Input {
+        Input {
+            mode: Mode::Live,
+            events: BTreeMap::new(),
+            polls: BTreeMap::new(),
+            stdin: Rc::new(RefCell::new(None)),
+            rng_state: 0,
+        }
+    }
+
+    pub fn record<P: AsRef<Path>>(path: P) -> Result<Input, String> {
+        let file = File::create(path).map_err(|err| err.to_string())?;
+        let mut log = BufWriter::new(file);
+        writeln!(log, "{}", HEADER)
+            .and_then(|_| log.flush())
+            .map_err(|err| err.to_string())?;
+
+        Ok(Input {
+            mode: Mode::Record(Rc::new(RefCell::new(log))),
+            ..Input::live()
+        })
+    }
+
+    pub fn replay<P: AsRef<Path>>(path: P) -> Result<Input, String> {
+        let file = File::open(path).map_err(|err| err.to_string())?;
+        let mut input = Input {
+            mode: Mode::Replay,
+            ..Input::live()
+        };
+
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|err| err.to_string())?;
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            let (count, event) = parse_event(&line)
+                .ok_or_else(|| format!("Invalid input log entry line {}.", i + 1))?;
+            input.insert(count, event);
+        }
+        Ok(input)
+    }
+
+    /// Live input giving `events` again for their instructions, the next
+    /// random numbers following `rng_state` (0 to seed from the clock).
+    pub fn from_events(events: Vec<(u64, Event)>, rng_state: u64) -> Input {
+        let mut input = Input::live();
+        for (count, event) in events {
+            input.insert(count, event);
+        }
+        input.rng_state = rng_state;
+        input
+    }
+
+    /// Every event given so far with its instruction count, for the
+    /// snapshots.
+    pub fn events(&self) -> Vec<(u64, Event)> {
+        let mut events: Vec<(u64, Event)> = self.events
+            .iter()
+            .map(|(&count, event)| (count, event.clone()))
+            .chain(self.polls.iter().map(|(&count, &byte)| (count, Event::Poll(byte))))
+            .collect();
+        events.sort_by_key(|&(count, _)| count);
+        events
+    }
+
+    pub fn rng_state(&self) -> u64 {
+        self.rng_state
+    }
+
+    /// Goes on recording to the log of `session`, or replaying it for the
+    /// instructions that have no event yet.
+    pub fn continue_log(&mut self, session: &Input) {
+        self.mode = session.mode.clone();
+        self.stdin = session.stdin.clone();
+        if let Mode::Replay = self.mode {
+            for (count, event) in session.events() {
+                if !self.has_event(count, &event) {
+                    self.insert(count, event);
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, count: u64, event: Event) {
+        if let Event::Poll(byte) = event {
+            self.polls.insert(count, byte);
+        } else {
+            self.events.insert(count, event);
+        }
+    }
+
+    fn has_event(&self, count: u64, event: &Event) -> bool {
+        match *event {
+            Event::Poll(_) => self.polls.contains_key(&count),
+            _ => self.events.contains_key(&count),
+        }
+    }
+
+    /// Reads a line from stdin, the newline included.
+    pub fn read_line(&mut self, count: u64) -> Result<Vec<u8>, String> {
+        let event = self.event(count, |input| {
+            input.read_stdin(usize::MAX, b'\n').map(Event::Stdin)
+        })?;
+
+        match event {
+            Event::Stdin(bytes) => Ok(bytes),
+            _ => Err(mismatch(count, "stdin")),
+        }
+    }
+
+    /// Reads at most `max` bytes from stdin, stopping after `stop`.
+    pub fn read_until(&mut self, count: u64, max: usize, stop: u8) -> Result<Vec<u8>, String> {
+        let event = self.event(count, |input| input.read_stdin(max, stop).map(Event::Stdin))?;
+
+        match event {
+            Event::Stdin(bytes) => Ok(bytes),
+            _ => Err(mismatch(count, "stdin")),
+        }
+    }
+
+    /// Milliseconds elapsed since the epoch.
+    pub fn time(&mut self, count: u64) -> Result<u64, String> {
+        let event = self.event(count, |_| Ok(Event::Time(now().as_millis() as u64)))?;
+
+        match event {
+            Event::Time(time) => Ok(time),
+            _ => Err(mismatch(count, "time")),
+        }
+    }
+
+    pub fn random(&mut self, count: u64) -> Result<u32, String> {
+        let event = self.event(count, |input| Ok(Event::Random(input.next_random())))?;
+
+        match event {
+            Event::Random(value) => Ok(value),
+            _ => Err(mismatch(count, "random")),
+        }
+    }
+
+    /// Byte of stdin for a device polling it after instruction `count`,
+    /// without waiting for one.
+    pub fn poll_stdin(&mut self, count: u64) -> Result<Option<u8>, String> {
+        if let Some(&byte) = self.polls.get(&count) {
+            return Ok(Some(byte));
+        }
+        if let Mode::Replay = self.mode {
+            return Ok(None);
+        }
+
+        let byte = self.stdin
+            .borrow_mut()
+            .get_or_insert_with(|| device::spawn_reader(io::stdin()))
+            .try_recv()
+            .ok();
+        if let Some(byte) = byte {
+            self.append(count, &Event::Poll(byte))?;
+            self.polls.insert(count, byte);
+        }
+        Ok(byte)
+    }
+
+    // once a device has polled stdin, its thread is the only reader
+    fn read_stdin(&self, max: usize, stop: u8) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        if let Some(ref receiver) = *self.stdin.borrow() {
+            while bytes.len() < max {
+                match receiver.recv() {
+                    Ok(byte) => {
+                        bytes.push(byte);
+                        if byte == stop {
+                            break;
+                        }
+                    },
+                    Err(_) => break,
+                }
+            }
+            return Ok(bytes);
+        }
+
+        let stdin = io::stdin();
+        for byte in stdin.lock().bytes().take(max) {
+            let byte = byte.map_err(|err| err.to_string())?;
+            bytes.push(byte);
+            if byte == stop {
+                break;
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn event<F>(&mut self, count: u64, live: F) -> Result<Event, String>
+        where F: FnOnce(&mut Input) -> Result<Event, String>
+    {
+        if let Some(event) = self.events.get(&count) {
+            return Ok(event.clone());
+        }
+
+        match self.mode {
+            Mode::Live => live(self),
+            Mode::Replay => Err(format!("No recorded input for instruction {}.", count)),
+            Mode::Record(_) => {
+                let event = live(self)?;
+                self.append(count, &event)?;
+                self.events.insert(count, event.clone());
+                Ok(event)
+            },
+        }
+    }
+
+    // an instruction recorded again from another timeline (after a restart
+    // for instance) appends a new line, the last one wins on replay
+    fn append(&self, count: u64, event: &Event) -> Result<(), String> {
+        let log = match self.mode {
+            Mode::Record(ref log) => log,
+            _ => return Ok(()),
+        };
+
+        let entry = match *event {
+            Event::Stdin(ref bytes) => {
+                let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                format!("{} stdin {}", count, hex.concat())
+            },
+            Event::Time(time) => format!("{} time {}", count, time),
+            Event::Random(value) => format!("{} random {}", count, value),
+            Event::Poll(byte) => format!("{} poll {:02x}", count, byte),
+        };
+
+        let mut log = log.borrow_mut();
+        writeln!(log, "{}", entry)
+            .and_then(|_| log.flush())
+            .map_err(|err| err.to_string())
+    }
+
+    // xorshift64*, seeded from the clock on first use
+    fn next_random(&mut self) -> u32 {
+        if self.rng_state == 0 {
+            self.rng_state = (now().as_nanos() as u64) | 1;
+        }
+        self.rng_state ^= self.rng_state >> 12;
+        self.rng_state ^= self.rng_state << 25;
+        self.rng_state ^= self.rng_state >> 27;
+        (self.rng_state.wrapping_mul(0x2545F4914F6CDD1D) >> 32) as u32
+    }
+}
+
+fn now() -> ::std::time::Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+fn mismatch(count: u64, expected: &str) -> String {
+    format!("Recorded input for instruction {} is not {}.", count, expected)
+}
+
+fn parse_event(line: &str) -> Option<(u64, Event)> {
+    let mut parts = line.split_whitespace();
+    let count = parts.next()?.parse().ok()?;
+    let kind = parts.next()?;
+    let data = parts.next().unwrap_or("");
+
+    let event = match kind {
+        "stdin" => {
+            if !data.len().is_multiple_of(2) {
+                return None;
+            }
+            let mut bytes = Vec::new();
+            for i in (0..data.len()).step_by(2) {
+                bytes.push(u8::from_str_radix(data.get(i..i + 2)?, 16).ok()?);
+            }
+            Event::Stdin(bytes)
+        },
+        "time" => Event::Time(data.parse().ok()?),
+        "random" => Event::Random(data.parse().ok()?),
+        "poll" => Event::Poll(u8::from_str_radix(data, 16).ok()?),
+        _ => return None,
+    };
+    Some((count, event))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn replay_gives_the_recorded_events() {
+        let path = env::temp_dir().join(format!("mips_emu_input_{}.log", process::id()));
+
+        let mut input = Input::record(&path).unwrap();
+        let time = input.time(3).unwrap();
+        let random = input.random(5).unwrap();
+        assert_eq!(input.random(5).unwrap(), random);
+        input.append(7, &Event::Stdin(b"hi\n".to_vec())).unwrap();
+        input.append(9, &Event::Poll(b'x')).unwrap();
+        drop(input);
+
+        let mut input = Input::replay(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(input.time(3), Ok(time));
+        assert_eq!(input.random(5), Ok(random));
+        assert_eq!(input.read_line(7), Ok(b"hi\n".to_vec()));
+        assert_eq!(input.poll_stdin(9), Ok(Some(b'x')));
+        assert_eq!(input.poll_stdin(10), Ok(None));
+        assert_eq!(input.random(3), Err("Recorded input for instruction 3 is not random.".to_string()));
+        assert_eq!(input.time(4), Err("No recorded input for instruction 4.".to_string()));
+    }
+}
@@ -2,51 +2,123 @@ use cpu::{Cpu, Signal};
 use decoder;
 use executer;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Instruction {
     Unknown(u32),
     ADD(u32, u32, u32), // rs, rt, rd
     ADDI(u32, u32, i32), // rs, rt, imm
     ADDIU(u32, u32, i32), // rs, rt, imm
+    ADDIUPC(u32, i32), // rs, offset
     ADDU(u32, u32, u32), // rs, rt, rd
+    ALIGN(u32, u32, u32, u32), // rs, rt, rd, bp
+    ALUIPC(u32, u32), // rs, imm
     AND(u32, u32, u32), // rs, rt, rd
     ANDI(u32, u32, u32), // rs, rt, imm
+    AUI(u32, u32, u32), // rs, rt, imm
+    AUIPC(u32, u32), // rs, imm
+    BALC(i32), // offset
+    BC(i32), // offset
     BEQ(u32, u32, i32), // rs, rt, offset
+    BEQC(u32, u32, i32), // rs, rt, offset
+    BEQL(u32, u32, i32), // rs, rt, offset
+    BEQZALC(u32, i32), // rt, offset
+    BEQZC(u32, i32), // rs, offset
+    BGEC(u32, u32, i32), // rs, rt, offset
+    BGEUC(u32, u32, i32), // rs, rt, offset
     BGEZ(u32, i32), // rs, offset
     BGEZAL(u32, i32), // rs, offset
+    BGEZALC(u32, i32), // rt, offset
+    BGEZALL(u32, i32), // rs, offset
+    BGEZC(u32, i32), // rt, offset
+    BGEZL(u32, i32), // rs, offset
     BGTZ(u32, i32), // rs, offset
+    BGTZALC(u32, i32), // rt, offset
+    BGTZC(u32, i32), // rt, offset
+    BGTZL(u32, i32), // rs, offset
+    BITSWAP(u32, u32), // rt, rd
     BLEZ(u32, i32), // rs, offset
+    BLEZALC(u32, i32), // rt, offset
+    BLEZC(u32, i32), // rt, offset
+    BLEZL(u32, i32), // rs, offset
+    BLTC(u32, u32, i32), // rs, rt, offset
+    BLTUC(u32, u32, i32), // rs, rt, offset
     BLTZ(u32, i32), // rs, offset
     BLTZAL(u32, i32), // rs, offset
+    BLTZALC(u32, i32), // rt, offset
+    BLTZALL(u32, i32), // rs, offset
+    BLTZC(u32, i32), // rt, offset
+    BLTZL(u32, i32), // rs, offset
     BNE(u32, u32, i32), // rs, rt, offset
+    BNEC(u32, u32, i32), // rs, rt, offset
+    BNEL(u32, u32, i32), // rs, rt, offset
+    BNEZALC(u32, i32), // rt, offset
+    BNEZC(u32, i32), // rs, offset
+    BNVC(u32, u32, i32), // rs, rt, offset
+    BOVC(u32, u32, i32), // rs, rt, offset
     BREAK,
+    CACHE(u32, u32, i32), // base, op, offset
+    CLO(u32, u32), // rs, rd
+    CLZ(u32, u32), // rs, rd
+    DI(u32), // rt
     DIV(u32, u32), // rs, rt
+    DIVR6(u32, u32, u32), // rs, rt, rd
     DIVU(u32, u32), // rs, rt
+    DIVUR6(u32, u32, u32), // rs, rt, rd
+    EHB,
+    EI(u32), // rt
+    ERET,
+    EXT(u32, u32, u32, u32), // rs, rt, pos, size
+    INS(u32, u32, u32, u32), // rs, rt, pos, size
     J(u32), // instr_index
     JAL(u32), // instr_index
+    JIALC(u32, i32), // rt, offset
+    JIC(u32, i32), // rt, offset
     JALR(u32, u32), // rs, rd
     JR(u32), // rs
     LB(u32, u32, i32), // base, rt, offset
     LBU(u32, u32, i32), // base, rt, offset
     LH(u32, u32, i32), // base, rt, offset
     LHU(u32, u32, i32), // base, rt, offset
+    LL(u32, u32, i32), // base, rt, offset
+    LSA(u32, u32, u32, u32), // rs, rt, rd, shift
     LUI(u32, u32), // rt, imm
     LW(u32, u32, i32), // base, rt, offset
     LWL(u32, u32, i32), // base, rt, offset
+    LWPC(u32, i32), // rs, offset
     LWR(u32, u32, i32), // base, rt, offset
+    MADD(u32, u32), // rs, rt
+    MADDU(u32, u32), // rs, rt
+    MFC0(u32, u32, u32), // rt, rd, sel
     MFHI(u32), // rd
     MFLO(u32), // rd
+    MOD(u32, u32, u32), // rs, rt, rd
+    MODU(u32, u32, u32), // rs, rt, rd
     MOVN(u32, u32, u32), // rs, rt, rd
     MOVZ(u32, u32, u32), // rs, rt, rd
+    MSUB(u32, u32), // rs, rt
+    MSUBU(u32, u32), // rs, rt
+    MTC0(u32, u32, u32), // rt, rd, sel
     MTHI(u32), // rs
     MTLO(u32), // rs
+    MUH(u32, u32, u32), // rs, rt, rd
+    MUHU(u32, u32, u32), // rs, rt, rd
     MUL(u32, u32, u32), // rs, rt, rd
     MULT(u32, u32), // rs, rt
     MULTU(u32, u32), // rs, rt
+    MULU(u32, u32, u32), // rs, rt, rd
     NOR(u32, u32, u32), // rs, rt, rd
     OR(u32, u32, u32), // rs, rt, rd
     ORI(u32, u32, u32), // rs, rt, imm
+    PREF(u32, u32, i32), // base, hint, offset
+    RDHWR(u32, u32), // rt, rd
+    ROTR(u32, u32, u32), // rt, rd, shift
+    ROTRV(u32, u32, u32), // rs, rt, rd
     SB(u32, u32, i32), // base, rt, offset
+    SC(u32, u32, i32), // base, rt, offset
+    SEB(u32, u32), // rt, rd
+    SEH(u32, u32), // rt, rd
+    SELEQZ(u32, u32, u32), // rs, rt, rd
+    SELNEZ(u32, u32, u32), // rs, rt, rd
     SH(u32, u32, i32), // base, rt, offset
     SLL(u32, u32, u32), // rt, rd, shift
     SLLV(u32, u32, u32), // rs, rt, rd
@@ -58,24 +130,301 @@ pub enum Instruction {
     SRAV(u32, u32, u32), // rs, rt, rd
     SRL(u32, u32, u32), // rt, rd, shift
     SRLV(u32, u32, u32), // rs, rt, rd
+    SSNOP,
     SUB(u32, u32, u32), // rs, rt, rd
     SUBU(u32, u32, u32), // rs, rt, rd
     SW(u32, u32, i32), // base, rt, offset
     SWL(u32, u32, i32), // base, rt, offset
     SWR(u32, u32, i32), // base, rt, offset
+    SYNC(u32), // stype
+    SYNCI(u32, i32), // base, offset
     SYSCALL,
     TEQ(u32, u32), // rs, rt
+    TEQI(u32, i32), // rs, imm
+    TGE(u32, u32), // rs, rt
+    TGEI(u32, i32), // rs, imm
+    TGEIU(u32, i32), // rs, imm
+    TGEU(u32, u32), // rs, rt
+    TLBP,
+    TLBR,
+    TLBWI,
+    TLBWR,
+    TLT(u32, u32), // rs, rt
+    TLTI(u32, i32), // rs, imm
+    TLTIU(u32, i32), // rs, imm
+    TLTU(u32, u32), // rs, rt
+    TNE(u32, u32), // rs, rt
+    TNEI(u32, i32), // rs, imm
+    WSBH(u32, u32), // rt, rd
     XOR(u32, u32, u32), // rs, rt, rd
     XORI(u32, u32, u32), // rs, rt, imm
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum InstructionClass {
+    Alu,
+    Shift,
+    Load,
+    Store,
+    Branch,
+    Jump,
+    MulDiv,
+    Move,
+    System,
+    Unknown,
+}
+
+impl InstructionClass {
+    pub const ALL: [InstructionClass; 10] = [
+        InstructionClass::Alu,
+        InstructionClass::Shift,
+        InstructionClass::Load,
+        InstructionClass::Store,
+        InstructionClass::Branch,
+        InstructionClass::Jump,
+        InstructionClass::MulDiv,
+        InstructionClass::Move,
+        InstructionClass::System,
+        InstructionClass::Unknown,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            InstructionClass::Alu => "alu",
+            InstructionClass::Shift => "shift",
+            InstructionClass::Load => "load",
+            InstructionClass::Store => "store",
+            InstructionClass::Branch => "branch",
+            InstructionClass::Jump => "jump",
+            InstructionClass::MulDiv => "muldiv",
+            InstructionClass::Move => "move",
+            InstructionClass::System => "system",
+            InstructionClass::Unknown => "unknown",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<InstructionClass> {
+        InstructionClass::ALL.iter().cloned().find(|class| class.name() == name)
+    }
+}
+
+pub const ISAS: [&str; 2] = ["r2", "r6"];
+
+/// Revision of MIPS32 the instructions are decoded for. Release 6 removes
+/// and re-encodes many of the older instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Isa {
+    #[default]
+    R2,
+    R6,
+}
+
+impl Isa {
+    pub fn from_name(name: &str) -> Option<Isa> {
+        match name {
+            "r2" => Some(Isa::R2),
+            "r6" => Some(Isa::R6),
+            _ => None,
+        }
+    }
+}
+
 impl Instruction {
+    pub fn class(&self) -> InstructionClass {
+        match *self {
+            Instruction::Unknown(_) => InstructionClass::Unknown,
+            Instruction::ADD(..) | Instruction::ADDI(..) | Instruction::ADDIU(..) |
+            Instruction::ADDU(..) | Instruction::AND(..) | Instruction::ANDI(..) |
+            Instruction::LUI(..) | Instruction::NOR(..) | Instruction::OR(..) |
+            Instruction::ORI(..) | Instruction::SLT(..) | Instruction::SLTU(..) |
+            Instruction::SLTI(..) | Instruction::SLTIU(..) | Instruction::SUB(..) |
+            Instruction::SUBU(..) | Instruction::XOR(..) | Instruction::XORI(..) |
+            Instruction::CLO(..) | Instruction::CLZ(..) | Instruction::EXT(..) |
+            Instruction::INS(..) | Instruction::SEB(..) | Instruction::SEH(..) |
+            Instruction::WSBH(..) | Instruction::ADDIUPC(..) | Instruction::ALIGN(..) |
+            Instruction::ALUIPC(..) | Instruction::AUI(..) | Instruction::AUIPC(..) |
+            Instruction::BITSWAP(..) | Instruction::LSA(..) | Instruction::SELEQZ(..) |
+            Instruction::SELNEZ(..) => InstructionClass::Alu,
+            Instruction::SLL(..) | Instruction::SLLV(..) | Instruction::SRA(..) |
+            Instruction::SRAV(..) | Instruction::SRL(..) | Instruction::SRLV(..) |
+            Instruction::ROTR(..) | Instruction::ROTRV(..) => InstructionClass::Shift,
+            Instruction::LB(..) | Instruction::LBU(..) | Instruction::LH(..) |
+            Instruction::LHU(..) | Instruction::LW(..) | Instruction::LWL(..) |
+            Instruction::LWR(..) | Instruction::LL(..) | Instruction::LWPC(..) => InstructionClass::Load,
+            Instruction::SB(..) | Instruction::SH(..) | Instruction::SW(..) |
+            Instruction::SWL(..) | Instruction::SWR(..) | Instruction::SC(..) => InstructionClass::Store,
+            Instruction::BEQ(..) | Instruction::BGEZ(..) | Instruction::BGEZAL(..) |
+            Instruction::BGTZ(..) | Instruction::BLEZ(..) | Instruction::BLTZ(..) |
+            Instruction::BLTZAL(..) | Instruction::BNE(..) | Instruction::BEQL(..) |
+            Instruction::BGEZALL(..) | Instruction::BGEZL(..) | Instruction::BGTZL(..) |
+            Instruction::BLEZL(..) | Instruction::BLTZALL(..) | Instruction::BLTZL(..) |
+            Instruction::BNEL(..) | Instruction::BALC(..) | Instruction::BC(..) |
+            Instruction::BEQC(..) | Instruction::BEQZALC(..) | Instruction::BEQZC(..) |
+            Instruction::BGEC(..) | Instruction::BGEUC(..) | Instruction::BGEZALC(..) |
+            Instruction::BGEZC(..) | Instruction::BGTZALC(..) | Instruction::BGTZC(..) |
+            Instruction::BLEZALC(..) | Instruction::BLEZC(..) | Instruction::BLTC(..) |
+            Instruction::BLTUC(..) | Instruction::BLTZALC(..) | Instruction::BLTZC(..) |
+            Instruction::BNEC(..) | Instruction::BNEZALC(..) | Instruction::BNEZC(..) |
+            Instruction::BNVC(..) | Instruction::BOVC(..) => InstructionClass::Branch,
+            Instruction::J(..) | Instruction::JAL(..) | Instruction::JALR(..) |
+            Instruction::JR(..) | Instruction::JIALC(..) | Instruction::JIC(..) => InstructionClass::Jump,
+            Instruction::DIV(..) | Instruction::DIVU(..) | Instruction::MUL(..) |
+            Instruction::MULT(..) | Instruction::MULTU(..) | Instruction::MADD(..) |
+            Instruction::MADDU(..) | Instruction::MSUB(..) | Instruction::MSUBU(..) |
+            Instruction::DIVR6(..) | Instruction::DIVUR6(..) | Instruction::MOD(..) |
+            Instruction::MODU(..) | Instruction::MUH(..) | Instruction::MUHU(..) |
+            Instruction::MULU(..) => InstructionClass::MulDiv,
+            Instruction::MFHI(..) | Instruction::MFLO(..) | Instruction::MTHI(..) |
+            Instruction::MTLO(..) | Instruction::MOVN(..) | Instruction::MOVZ(..) |
+            Instruction::MFC0(..) | Instruction::MTC0(..) | Instruction::RDHWR(..) => InstructionClass::Move,
+            Instruction::BREAK | Instruction::SYSCALL | Instruction::TEQ(..) |
+            Instruction::ERET | Instruction::TLBP | Instruction::TLBR |
+            Instruction::TLBWI | Instruction::TLBWR | Instruction::DI(..) |
+            Instruction::EI(..) | Instruction::SYNCI(..) | Instruction::TEQI(..) |
+            Instruction::TGE(..) | Instruction::TGEI(..) | Instruction::TGEIU(..) |
+            Instruction::TGEU(..) | Instruction::TLT(..) | Instruction::TLTI(..) |
+            Instruction::TLTIU(..) | Instruction::TLTU(..) | Instruction::TNE(..) |
+            Instruction::TNEI(..) | Instruction::SYNC(..) | Instruction::PREF(..) |
+            Instruction::CACHE(..) | Instruction::SSNOP | Instruction::EHB => InstructionClass::System,
+        }
+    }
+
+    /// General registers read by the instruction.
+    pub fn sources(&self) -> Vec<u32> {
+        use self::Instruction::*;
+        let regs = match *self {
+            ADD(rs, rt, _) | ADDU(rs, rt, _) | AND(rs, rt, _) | BEQ(rs, rt, _) |
+            BNE(rs, rt, _) | DIV(rs, rt) | DIVU(rs, rt) | MUL(rs, rt, _) |
+            MULT(rs, rt) | MULTU(rs, rt) | NOR(rs, rt, _) | OR(rs, rt, _) |
+            SLLV(rs, rt, _) | SLT(rs, rt, _) | SLTU(rs, rt, _) | SRAV(rs, rt, _) |
+            SRLV(rs, rt, _) | SUB(rs, rt, _) | SUBU(rs, rt, _) | TEQ(rs, rt) |
+            XOR(rs, rt, _) | MOVN(rs, rt, _) | MOVZ(rs, rt, _) | ROTRV(rs, rt, _) |
+            MADD(rs, rt) | MADDU(rs, rt) | MSUB(rs, rt) | MSUBU(rs, rt) |
+            BEQL(rs, rt, _) | BNEL(rs, rt, _) | TGE(rs, rt) | TGEU(rs, rt) |
+            TLT(rs, rt) | TLTU(rs, rt) | TNE(rs, rt) | ALIGN(rs, rt, _, _) |
+            BEQC(rs, rt, _) | BGEC(rs, rt, _) | BGEUC(rs, rt, _) | BLTC(rs, rt, _) |
+            BLTUC(rs, rt, _) | BNEC(rs, rt, _) | BNVC(rs, rt, _) | BOVC(rs, rt, _) |
+            DIVR6(rs, rt, _) | DIVUR6(rs, rt, _) | LSA(rs, rt, _, _) | MOD(rs, rt, _) |
+            MODU(rs, rt, _) | MUH(rs, rt, _) | MUHU(rs, rt, _) | MULU(rs, rt, _) |
+            SELEQZ(rs, rt, _) | SELNEZ(rs, rt, _) => vec![rs, rt],
+            ADDI(rs, _, _) | ADDIU(rs, _, _) | ANDI(rs, _, _) | ORI(rs, _, _) |
+            SLTI(rs, _, _) | SLTIU(rs, _, _) | XORI(rs, _, _) |
+            BGEZ(rs, _) | BGEZAL(rs, _) | BGTZ(rs, _) | BLEZ(rs, _) | BLTZ(rs, _) |
+            BLTZAL(rs, _) | JALR(rs, _) | JR(rs) | MTHI(rs) | MTLO(rs) |
+            CLO(rs, _) | CLZ(rs, _) | EXT(rs, _, _, _) | BGEZALL(rs, _) |
+            BGEZL(rs, _) | BGTZL(rs, _) | BLEZL(rs, _) | BLTZALL(rs, _) | BLTZL(rs, _) |
+            TEQI(rs, _) | TGEI(rs, _) | TGEIU(rs, _) | TLTI(rs, _) | TLTIU(rs, _) |
+            TNEI(rs, _) | AUI(rs, _, _) | BEQZC(rs, _) | BNEZC(rs, _) => vec![rs],
+            // the inserted bit field is merged with the previous register value
+            INS(rs, rt, _, _) => vec![rs, rt],
+            LB(base, _, _) | LBU(base, _, _) | LH(base, _, _) | LHU(base, _, _) |
+            LW(base, _, _) | LL(base, _, _) | SYNCI(base, _) | PREF(base, _, _) | CACHE(base, _, _) => vec![base],
+            // the unaligned loads merge with the previous register value
+            LWL(base, rt, _) | LWR(base, rt, _) => vec![base, rt],
+            SB(base, rt, _) | SH(base, rt, _) | SW(base, rt, _) | SWL(base, rt, _) |
+            SWR(base, rt, _) | SC(base, rt, _) => vec![base, rt],
+            SLL(rt, _, _) | SRA(rt, _, _) | SRL(rt, _, _) | MTC0(rt, _, _) |
+            ROTR(rt, _, _) | SEB(rt, _) | SEH(rt, _) | WSBH(rt, _) | BITSWAP(rt, _) |
+            BEQZALC(rt, _) | BGEZALC(rt, _) | BGEZC(rt, _) | BGTZALC(rt, _) |
+            BGTZC(rt, _) | BLEZALC(rt, _) | BLEZC(rt, _) | BLTZALC(rt, _) |
+            BLTZC(rt, _) | BNEZALC(rt, _) | JIALC(rt, _) | JIC(rt, _) => vec![rt],
+            SYSCALL => vec![2, 4, 5],
+            Unknown(_) | BREAK | J(_) | JAL(_) | LUI(..) | MFHI(_) | MFLO(_) |
+            MFC0(..) | ERET | TLBP | TLBR | TLBWI | TLBWR | DI(_) | EI(_) |
+            RDHWR(..) | SYNC(_) | SSNOP | EHB | ADDIUPC(..) | ALUIPC(..) |
+            AUIPC(..) | BALC(_) | BC(_) | LWPC(..) => vec![],
+        };
+        regs.into_iter().filter(|&reg| reg != 0).collect()
+    }
+
+    /// General register written by the instruction.
+    pub fn destination(&self) -> Option<u32> {
+        use self::Instruction::*;
+        let reg = match *self {
+            ADD(_, _, rd) | ADDU(_, _, rd) | AND(_, _, rd) | MUL(_, _, rd) |
+            NOR(_, _, rd) | OR(_, _, rd) | SLLV(_, _, rd) | SLT(_, _, rd) |
+            SLTU(_, _, rd) | SRAV(_, _, rd) | SRLV(_, _, rd) | SUB(_, _, rd) |
+            SUBU(_, _, rd) | XOR(_, _, rd) | MOVN(_, _, rd) | MOVZ(_, _, rd) |
+            SLL(_, rd, _) | SRA(_, rd, _) | SRL(_, rd, _) | JALR(_, rd) |
+            MFHI(rd) | MFLO(rd) | CLO(_, rd) | CLZ(_, rd) | ROTR(_, rd, _) |
+            ROTRV(_, _, rd) | SEB(_, rd) | SEH(_, rd) | WSBH(_, rd) |
+            ALIGN(_, _, rd, _) | BITSWAP(_, rd) | DIVR6(_, _, rd) | DIVUR6(_, _, rd) |
+            LSA(_, _, rd, _) | MOD(_, _, rd) | MODU(_, _, rd) | MUH(_, _, rd) |
+            MUHU(_, _, rd) | MULU(_, _, rd) | SELEQZ(_, _, rd) | SELNEZ(_, _, rd) => rd,
+            ADDI(_, rt, _) | ADDIU(_, rt, _) | ANDI(_, rt, _) | ORI(_, rt, _) |
+            SLTI(_, rt, _) | SLTIU(_, rt, _) | XORI(_, rt, _) | LUI(rt, _) |
+            LB(_, rt, _) | LBU(_, rt, _) | LH(_, rt, _) | LHU(_, rt, _) |
+            LW(_, rt, _) | LWL(_, rt, _) | LWR(_, rt, _) | MFC0(rt, _, _) |
+            LL(_, rt, _) | SC(_, rt, _) |
+            EXT(_, rt, _, _) | INS(_, rt, _, _) | DI(rt) | EI(rt) | RDHWR(rt, _) |
+            AUI(_, rt, _) => rt,
+            ADDIUPC(rs, _) | ALUIPC(rs, _) | AUIPC(rs, _) | LWPC(rs, _) => rs,
+            BGEZAL(..) | BLTZAL(..) | BGEZALL(..) | BLTZALL(..) | JAL(_) |
+            BALC(_) | BEQZALC(..) | BGEZALC(..) | BGTZALC(..) | BLEZALC(..) |
+            BLTZALC(..) | BNEZALC(..) | JIALC(..) => 31,
+            SYSCALL => 2,
+            _ => 0,
+        };
+        if reg == 0 { None } else { Some(reg) }
+    }
+
+    /// Target of a conditional branch located at `pc`.
+    pub fn branch_target(&self, pc: u32) -> Option<u32> {
+        match *self {
+            Instruction::BEQ(_, _, offset) | Instruction::BNE(_, _, offset) |
+            Instruction::BGEZ(_, offset) | Instruction::BGEZAL(_, offset) |
+            Instruction::BGTZ(_, offset) | Instruction::BLEZ(_, offset) |
+            Instruction::BLTZ(_, offset) | Instruction::BLTZAL(_, offset) |
+            Instruction::BEQL(_, _, offset) | Instruction::BNEL(_, _, offset) |
+            Instruction::BGEZL(_, offset) | Instruction::BGEZALL(_, offset) |
+            Instruction::BGTZL(_, offset) | Instruction::BLEZL(_, offset) |
+            Instruction::BLTZL(_, offset) | Instruction::BLTZALL(_, offset) |
+            Instruction::BALC(offset) | Instruction::BC(offset) |
+            Instruction::BEQC(_, _, offset) | Instruction::BEQZALC(_, offset) |
+            Instruction::BEQZC(_, offset) | Instruction::BGEC(_, _, offset) |
+            Instruction::BGEUC(_, _, offset) | Instruction::BGEZALC(_, offset) |
+            Instruction::BGEZC(_, offset) | Instruction::BGTZALC(_, offset) |
+            Instruction::BGTZC(_, offset) | Instruction::BLEZALC(_, offset) |
+            Instruction::BLEZC(_, offset) | Instruction::BLTC(_, _, offset) |
+            Instruction::BLTUC(_, _, offset) | Instruction::BLTZALC(_, offset) |
+            Instruction::BLTZC(_, offset) | Instruction::BNEC(_, _, offset) |
+            Instruction::BNEZALC(_, offset) | Instruction::BNEZC(_, offset) |
+            Instruction::BNVC(_, _, offset) | Instruction::BOVC(_, _, offset) => {
+                Some(pc.wrapping_add(4).wrapping_add((offset << 2) as u32))
+            },
+            _ => None,
+        }
+    }
+
+    /// Whether this is a branch whose direction depends on registers (`b`,
+    /// `bal`, `bc` and `balc` are always taken).
+    pub fn is_conditional_branch(&self) -> bool {
+        match *self {
+            Instruction::BC(_) | Instruction::BALC(_) => false,
+            Instruction::BEQ(rs, rt, _) | Instruction::BEQL(rs, rt, _) => rs != rt,
+            Instruction::BGEZ(rs, _) | Instruction::BGEZAL(rs, _) |
+            Instruction::BGEZL(rs, _) | Instruction::BGEZALL(rs, _) => rs != 0,
+            _ => self.branch_target(0).is_some(),
+        }
+    }
+
+    /// Whether this is a Release 6 compact branch or jump, which has no
+    /// delay slot. The conditional ones have a forbidden slot instead: the
+    /// next instruction when they are not taken cannot be a branch or a jump.
+    pub fn is_compact(&self) -> bool {
+        use self::Instruction::*;
+        matches!(*self, BALC(_) | BC(_) | BEQC(..) | BEQZALC(..) | BEQZC(..) | BGEC(..) |
+                 BGEUC(..) | BGEZALC(..) | BGEZC(..) | BGTZALC(..) | BGTZC(..) | BLEZALC(..) |
+                 BLEZC(..) | BLTC(..) | BLTUC(..) | BLTZALC(..) | BLTZC(..) | BNEC(..) |
+                 BNEZALC(..) | BNEZC(..) | BNVC(..) | BOVC(..) | JIALC(..) | JIC(..))
+    }
+
     pub fn apply(&self, cpu: &mut Cpu) -> Result<(), Signal> {
         executer::apply_instruction(self, cpu)
     }
 
-    pub fn from_word(word: u32) -> Instruction {
-        decoder::decode_instruction(word)
+    pub fn from_word(word: u32, isa: Isa) -> Instruction {
+        decoder::decode_instruction(word, isa)
     }
 }
 
@@ -87,63 +436,159 @@ impl fmt::Display for Instruction {
             Instruction::ADD(rs, rt, rd) => write!(f, "add ${}, ${}, ${}", rd, rs, rt),
             Instruction::ADDI(rs, rt, imm) => write!(f, "addi ${}, ${}, {}", rt, rs, imm),
             Instruction::ADDIU(rs, rt, imm) => write!(f, "addiu ${}, ${}, {}", rt, rs, imm),
+            Instruction::ADDIUPC(rs, offset) => write!(f, "addiupc ${}, {:#x}", rs, offset),
             Instruction::ADDU(rs, rt, rd) => write!(f, "addu ${}, ${}, ${}", rd, rs, rt),
+            Instruction::ALIGN(rs, rt, rd, bp) => write!(f, "align ${}, ${}, ${}, {}", rd, rs, rt, bp),
+            Instruction::ALUIPC(rs, imm) => write!(f, "aluipc ${}, {}", rs, imm),
             Instruction::AND(rs, rt, rd) => write!(f, "and ${}, ${}, ${}", rd, rs, rt),
             Instruction::ANDI(rs, rt, imm) => write!(f, "andi ${}, ${}, {}", rt, rs, imm),
+            Instruction::AUI(rs, rt, imm) => write!(f, "aui ${}, ${}, {}", rt, rs, imm),
+            Instruction::AUIPC(rs, imm) => write!(f, "auipc ${}, {}", rs, imm),
+            Instruction::BALC(offset) => write!(f, "balc {:#x}", offset),
+            Instruction::BC(offset) => write!(f, "bc {:#x}", offset),
             Instruction::BEQ(rs, rt, offset) => write!(f, "beq ${}, ${}, {:#x}", rs, rt, offset),
+            Instruction::BEQC(rs, rt, offset) => write!(f, "beqc ${}, ${}, {:#x}", rs, rt, offset),
+            Instruction::BEQL(rs, rt, offset) => write!(f, "beql ${}, ${}, {:#x}", rs, rt, offset),
+            Instruction::BEQZALC(rt, offset) => write!(f, "beqzalc ${}, {:#x}", rt, offset),
+            Instruction::BEQZC(rs, offset) => write!(f, "beqzc ${}, {:#x}", rs, offset),
+            Instruction::BGEC(rs, rt, offset) => write!(f, "bgec ${}, ${}, {:#x}", rs, rt, offset),
+            Instruction::BGEUC(rs, rt, offset) => write!(f, "bgeuc ${}, ${}, {:#x}", rs, rt, offset),
             Instruction::BGEZ(rs, offset) => write!(f, "bgez ${}, {:#x}", rs, offset),
             Instruction::BGEZAL(rs, offset) => write!(f, "bgezal ${}, {:#x}", rs, offset),
+            Instruction::BGEZALC(rt, offset) => write!(f, "bgezalc ${}, {:#x}", rt, offset),
+            Instruction::BGEZALL(rs, offset) => write!(f, "bgezall ${}, {:#x}", rs, offset),
+            Instruction::BGEZC(rt, offset) => write!(f, "bgezc ${}, {:#x}", rt, offset),
+            Instruction::BGEZL(rs, offset) => write!(f, "bgezl ${}, {:#x}", rs, offset),
             Instruction::BGTZ(rs, offset) => write!(f, "bgtz ${}, {:#x}", rs, offset),
+            Instruction::BGTZALC(rt, offset) => write!(f, "bgtzalc ${}, {:#x}", rt, offset),
+            Instruction::BGTZC(rt, offset) => write!(f, "bgtzc ${}, {:#x}", rt, offset),
+            Instruction::BGTZL(rs, offset) => write!(f, "bgtzl ${}, {:#x}", rs, offset),
+            Instruction::BITSWAP(rt, rd) => write!(f, "bitswap ${}, ${}", rd, rt),
             Instruction::BLEZ(rs, offset) => write!(f, "blez ${}, {:#x}", rs, offset),
+            Instruction::BLEZALC(rt, offset) => write!(f, "blezalc ${}, {:#x}", rt, offset),
+            Instruction::BLEZC(rt, offset) => write!(f, "blezc ${}, {:#x}", rt, offset),
+            Instruction::BLEZL(rs, offset) => write!(f, "blezl ${}, {:#x}", rs, offset),
+            Instruction::BLTC(rs, rt, offset) => write!(f, "bltc ${}, ${}, {:#x}", rs, rt, offset),
+            Instruction::BLTUC(rs, rt, offset) => write!(f, "bltuc ${}, ${}, {:#x}", rs, rt, offset),
             Instruction::BLTZ(rs, offset) => write!(f, "bltz ${}, {:#x}", rs, offset),
             Instruction::BLTZAL(rs, offset) => write!(f, "bltzal ${}, {:#x}", rs, offset),
+            Instruction::BLTZALC(rt, offset) => write!(f, "bltzalc ${}, {:#x}", rt, offset),
+            Instruction::BLTZALL(rs, offset) => write!(f, "bltzall ${}, {:#x}", rs, offset),
+            Instruction::BLTZC(rt, offset) => write!(f, "bltzc ${}, {:#x}", rt, offset),
+            Instruction::BLTZL(rs, offset) => write!(f, "bltzl ${}, {:#x}", rs, offset),
             Instruction::BNE(rs, rt, offset) => write!(f, "bne ${}, ${}, {:#x}", rs, rt, offset),
+            Instruction::BNEC(rs, rt, offset) => write!(f, "bnec ${}, ${}, {:#x}", rs, rt, offset),
+            Instruction::BNEL(rs, rt, offset) => write!(f, "bnel ${}, ${}, {:#x}", rs, rt, offset),
+            Instruction::BNEZALC(rt, offset) => write!(f, "bnezalc ${}, {:#x}", rt, offset),
+            Instruction::BNEZC(rs, offset) => write!(f, "bnezc ${}, {:#x}", rs, offset),
+            Instruction::BNVC(rs, rt, offset) => write!(f, "bnvc ${}, ${}, {:#x}", rs, rt, offset),
+            Instruction::BOVC(rs, rt, offset) => write!(f, "bovc ${}, ${}, {:#x}", rs, rt, offset),
             Instruction::BREAK => write!(f, "break"),
+            Instruction::CACHE(base, op, offset) => write!(f, "cache {:#x}, {}(${})", op, offset, base),
+            Instruction::CLO(rs, rd) => write!(f, "clo ${}, ${}", rd, rs),
+            Instruction::CLZ(rs, rd) => write!(f, "clz ${}, ${}", rd, rs),
+            Instruction::DI(0) => write!(f, "di"),
+            Instruction::DI(rt) => write!(f, "di ${}", rt),
             Instruction::DIV(rs, rt) => write!(f, "div ${}, ${}", rs, rt),
+            Instruction::DIVR6(rs, rt, rd) => write!(f, "div ${}, ${}, ${}", rd, rs, rt),
             Instruction::DIVU(rs, rt) => write!(f, "divu ${}, ${}", rs, rt),
+            Instruction::DIVUR6(rs, rt, rd) => write!(f, "divu ${}, ${}, ${}", rd, rs, rt),
+            Instruction::EHB => write!(f, "ehb"),
+            Instruction::EI(0) => write!(f, "ei"),
+            Instruction::EI(rt) => write!(f, "ei ${}", rt),
+            Instruction::ERET => write!(f, "eret"),
+            Instruction::EXT(rs, rt, pos, size) => write!(f, "ext ${}, ${}, {}, {}", rt, rs, pos, size),
+            Instruction::INS(rs, rt, pos, size) => write!(f, "ins ${}, ${}, {}, {}", rt, rs, pos, size),
             Instruction::J(instr_index) => write!(f, "j {:#x}", instr_index),
             Instruction::JAL(instr_index) => write!(f, "jal {:#x}", instr_index),
+            Instruction::JIALC(rt, offset) => write!(f, "jialc ${}, {}", rt, offset),
+            Instruction::JIC(rt, offset) => write!(f, "jic ${}, {}", rt, offset),
             Instruction::JALR(rs, rd) => write!(f, "jalr ${}, ${}", rd, rs),
             Instruction::JR(rs) => write!(f, "jr ${}", rs),
             Instruction::LB(base, rt, offset) => write!(f, "lb ${}, {}(${})", rt, offset, base),
             Instruction::LBU(base, rt, offset) => write!(f, "lbu ${}, {}(${})", rt, offset, base),
             Instruction::LH(base, rt, offset) => write!(f, "lh ${}, {}(${})", rt, offset, base),
             Instruction::LHU(base, rt, offset) => write!(f, "lhu ${}, {}(${})", rt, offset, base),
+            Instruction::LL(base, rt, offset) => write!(f, "ll ${}, {}(${})", rt, offset, base),
+            Instruction::LSA(rs, rt, rd, shift) => write!(f, "lsa ${}, ${}, ${}, {}", rd, rs, rt, shift),
             Instruction::LUI(rt, imm) => write!(f, "lui ${}, {}", rt, imm),
             Instruction::LW(base, rt, offset) => write!(f, "lw ${}, {}(${})", rt, offset, base),
             Instruction::LWL(base, rt, offset) => write!(f, "lwl ${}, {}(${})", rt, offset, base),
+            Instruction::LWPC(rs, offset) => write!(f, "lwpc ${}, {:#x}", rs, offset),
             Instruction::LWR(base, rt, offset) => write!(f, "lwr ${}, {}(${})", rt, offset, base),
+            Instruction::MADD(rs, rt) => write!(f, "madd ${}, ${}", rs, rt),
+            Instruction::MADDU(rs, rt) => write!(f, "maddu ${}, ${}", rs, rt),
+            Instruction::MFC0(rt, rd, 0) => write!(f, "mfc0 ${}, ${}", rt, rd),
+            Instruction::MFC0(rt, rd, sel) => write!(f, "mfc0 ${}, ${}, {}", rt, rd, sel),
             Instruction::MFHI(rd) => write!(f, "mfhi ${}", rd),
             Instruction::MFLO(rd) => write!(f, "mflo ${}", rd),
+            Instruction::MOD(rs, rt, rd) => write!(f, "mod ${}, ${}, ${}", rd, rs, rt),
+            Instruction::MODU(rs, rt, rd) => write!(f, "modu ${}, ${}, ${}", rd, rs, rt),
+            Instruction::MTC0(rt, rd, 0) => write!(f, "mtc0 ${}, ${}", rt, rd),
+            Instruction::MTC0(rt, rd, sel) => write!(f, "mtc0 ${}, ${}, {}", rt, rd, sel),
+            Instruction::MSUB(rs, rt) => write!(f, "msub ${}, ${}", rs, rt),
+            Instruction::MSUBU(rs, rt) => write!(f, "msubu ${}, ${}", rs, rt),
             Instruction::MTHI(rs) => write!(f, "mthi ${}", rs),
             Instruction::MTLO(rs) => write!(f, "mtlo ${}", rs),
+            Instruction::MUH(rs, rt, rd) => write!(f, "muh ${}, ${}, ${}", rd, rs, rt),
+            Instruction::MUHU(rs, rt, rd) => write!(f, "muhu ${}, ${}, ${}", rd, rs, rt),
             Instruction::MOVN(rs, rt, rd) => write!(f, "movn ${}, ${}, ${}", rd, rs, rt),
             Instruction::MOVZ(rs, rt, rd) => write!(f, "movz ${}, ${}, ${}", rd, rs, rt),
             Instruction::MUL(rs, rt, rd) => write!(f, "mul ${}, ${}, ${}", rd, rs, rt),
             Instruction::MULT(rs, rt) => write!(f, "mult ${}, ${}", rs, rt),
             Instruction::MULTU(rs, rt) => write!(f, "multu ${}, ${}", rs, rt),
+            Instruction::MULU(rs, rt, rd) => write!(f, "mulu ${}, ${}, ${}", rd, rs, rt),
             Instruction::NOR(rs, rt, rd) => write!(f, "nor ${}, ${}, ${}", rd, rs, rt),
             Instruction::OR(rs, rt, rd) => write!(f, "or ${}, ${}, ${}", rd, rs, rt),
             Instruction::ORI(rs, rt, imm) => write!(f, "ori ${}, ${}, {}", rt, rs, imm),
+            Instruction::PREF(base, hint, offset) => write!(f, "pref {}, {}(${})", hint, offset, base),
+            Instruction::RDHWR(rt, rd) => write!(f, "rdhwr ${}, ${}", rt, rd),
+            Instruction::ROTR(rt, rd, shift) => write!(f, "rotr ${}, ${}, {}", rd, rt, shift),
+            Instruction::ROTRV(rs, rt, rd) => write!(f, "rotrv ${}, ${}, ${}", rd, rt, rs),
             Instruction::SB(base, rt, offset) => write!(f, "sb ${}, {}(${})", rt, offset, base),
+            Instruction::SC(base, rt, offset) => write!(f, "sc ${}, {}(${})", rt, offset, base),
+            Instruction::SEB(rt, rd) => write!(f, "seb ${}, ${}", rd, rt),
+            Instruction::SEH(rt, rd) => write!(f, "seh ${}, ${}", rd, rt),
+            Instruction::SELEQZ(rs, rt, rd) => write!(f, "seleqz ${}, ${}, ${}", rd, rs, rt),
+            Instruction::SELNEZ(rs, rt, rd) => write!(f, "selnez ${}, ${}, ${}", rd, rs, rt),
             Instruction::SH(base, rt, offset) => write!(f, "sh ${}, {}(${})", rt, offset, base),
             Instruction::SLL(rt, rd, shift) => write!(f, "sll ${}, ${}, {}", rd, rt, shift),
             Instruction::SLLV(rs, rt, rd) => write!(f, "sllv ${}, ${}, ${}", rd, rt, rs),
             Instruction::SLT(rs, rt, rd) => write!(f, "slt ${}, ${}, ${}", rd, rs, rt),
-            Instruction::SLTI(rs, rt, imm) => write!(f, "slti ${}, ${}, {}", rs, rt, imm),
-            Instruction::SLTIU(rs, rt, imm) => write!(f, "sltiu ${}, ${}, {}", rs, rt, imm),
+            Instruction::SLTI(rs, rt, imm) => write!(f, "slti ${}, ${}, {}", rt, rs, imm),
+            Instruction::SLTIU(rs, rt, imm) => write!(f, "sltiu ${}, ${}, {}", rt, rs, imm),
             Instruction::SLTU(rs, rt, rd) => write!(f, "sltu ${}, ${}, ${}", rd, rs, rt),
             Instruction::SRA(rt, rd, shift) => write!(f, "sra ${}, ${}, {}", rd, rt, shift),
             Instruction::SRAV(rs, rt, rd) => write!(f, "srav ${}, ${}, ${}", rd, rt, rs),
             Instruction::SRL(rt, rd, shift) => write!(f, "srl ${}, ${}, {}", rd, rt, shift),
             Instruction::SRLV(rs, rt, rd) => write!(f, "srlv ${}, ${}, ${}", rd, rt, rs),
+            Instruction::SSNOP => write!(f, "ssnop"),
             Instruction::SUB(rs, rt, rd) => write!(f, "sub ${}, ${}, ${}", rd, rs, rt),
             Instruction::SUBU(rs, rt, rd) => write!(f, "subu ${}, ${}, ${}", rd, rs, rt),
             Instruction::SW(base, rt, offset) => write!(f, "sw ${}, {}(${})", rt, offset, base),
             Instruction::SWL(base, rt, offset) => write!(f, "swl ${}, {}(${})", rt, offset, base),
             Instruction::SWR(base, rt, offset) => write!(f, "swr ${}, {}(${})", rt, offset, base),
+            Instruction::SYNC(0) => write!(f, "sync"),
+            Instruction::SYNC(stype) => write!(f, "sync {}", stype),
+            Instruction::SYNCI(base, offset) => write!(f, "synci {}(${})", offset, base),
             Instruction::SYSCALL => write!(f, "syscall"),
             Instruction::TEQ(rs, rt) => write!(f, "teq ${}, ${}", rs, rt),
+            Instruction::TEQI(rs, imm) => write!(f, "teqi ${}, {}", rs, imm),
+            Instruction::TGE(rs, rt) => write!(f, "tge ${}, ${}", rs, rt),
+            Instruction::TGEI(rs, imm) => write!(f, "tgei ${}, {}", rs, imm),
+            Instruction::TGEIU(rs, imm) => write!(f, "tgeiu ${}, {}", rs, imm),
+            Instruction::TGEU(rs, rt) => write!(f, "tgeu ${}, ${}", rs, rt),
+            Instruction::TLBP => write!(f, "tlbp"),
+            Instruction::TLBR => write!(f, "tlbr"),
+            Instruction::TLBWI => write!(f, "tlbwi"),
+            Instruction::TLBWR => write!(f, "tlbwr"),
+            Instruction::TLT(rs, rt) => write!(f, "tlt ${}, ${}", rs, rt),
+            Instruction::TLTI(rs, imm) => write!(f, "tlti ${}, {}", rs, imm),
+            Instruction::TLTIU(rs, imm) => write!(f, "tltiu ${}, {}", rs, imm),
+            Instruction::TLTU(rs, rt) => write!(f, "tltu ${}, ${}", rs, rt),
+            Instruction::TNE(rs, rt) => write!(f, "tne ${}, ${}", rs, rt),
+            Instruction::TNEI(rs, imm) => write!(f, "tnei ${}, {}", rs, imm),
+            Instruction::WSBH(rt, rd) => write!(f, "wsbh ${}, ${}", rd, rt),
             Instruction::XOR(rs, rt, rd) => write!(f, "xor ${}, ${}, ${}", rd, rs, rt),
             Instruction::XORI(rs, rt, imm) => write!(f, "xori ${}, ${}, {}", rt, rs, imm),
         }
@@ -1,12 +1,31 @@
 extern crate elf;
+#[cfg(unix)]
+extern crate libc;
 extern crate regex;
 #[macro_use]
 extern crate lazy_static;
 
 mod utils;
 pub mod memory;
+pub mod device;
 pub mod cpu;
+pub mod cop0;
+pub mod mmu;
 pub mod instruction;
+pub mod history;
+pub mod snapshot;
+pub mod loader;
+pub mod input;
+pub mod symbols;
+pub mod debug_line;
+pub mod trace;
+pub mod profile;
+pub mod callgraph;
+pub mod coverage;
+pub mod pipeline;
+pub mod cache;
+pub mod predictor;
+pub mod diagram;
 mod decoder;
 mod executer;
 mod syscall;
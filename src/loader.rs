@@ -0,0 +1,452 @@
+//! Loading of program files: ELF executables, raw binaries, Intel HEX and
+//! Motorola S-records.
+//!
+//! Raw binaries are loaded at a given address and start there. HEX and
+//! S-record files give their own addresses and may give the entry point
+//! (start linear address and S7/S8/S9 records), the first loaded address
+//! being the entry otherwise. In bare-metal mode the program starts at the
+//! reset vector whatever its entry point.
+//!
+//! Several files can be loaded in the same memory, e.g. a bootloader and a
+//! kernel or a program and its data, as long as they do not overlap. The
+//! first one gives the entry point unless another one is chosen.
+
+use std::fmt;
+use std::fs;
+use std::io;
+
+use elf;
+
+use cop0;
+use cpu::Cpu;
+use debug_line::LineTable;
+use symbols::SymbolTable;
+
+pub const FORMATS: [&str; 4] = ["elf", "raw", "ihex", "srec"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Elf,
+    Raw,
+    IntelHex,
+    SRecord,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "elf" => Some(Format::Elf),
+            "raw" => Some(Format::Raw),
+            "ihex" => Some(Format::IntelHex),
+            "srec" => Some(Format::SRecord),
+            _ => None,
+        }
+    }
+
+    /// Format from the extension of a file name, ELF when it is not known.
+    pub fn from_path(path: &str) -> Format {
+        let extension = path.rsplit('.').next().unwrap_or("").to_lowercase();
+        match extension.as_str() {
+            "bin" | "rom" => Format::Raw,
+            "hex" | "ihex" | "ihx" => Format::IntelHex,
+            "srec" | "s19" | "s28" | "s37" | "mot" => Format::SRecord,
+            _ => Format::Elf,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadOptions {
+    pub format: Option<Format>, // of the first file, from the file name when None
+    pub address: u32, // of raw binaries
+    pub bare_metal: bool,
+    pub entry: Option<String>, // file, symbol or address, the first file when None
+}
+
+impl Default for LoadOptions {
+    fn default() -> LoadOptions {
+        LoadOptions {
+            format: None,
+            address: cop0::RESET_VECTOR,
+            bare_metal: false,
+            entry: None,
+        }
+    }
+}
+
+/// Bytes to load at consecutive addresses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub addr: u32,
+    pub data: Vec<u8>,
+}
+
+/// Bytes of memory holding a loaded file, `last` being included.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub name: String,
+    pub start: u32,
+    pub last: u32,
+}
+
+impl Region {
+    pub fn overlaps(&self, other: &Region) -> bool {
+        self.start <= other.last && other.start <= self.last
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#010x}-{:#010x}", self.start, self.last)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Image {
+    pub segments: Vec<Segment>,
+    pub entry: Option<u32>,
+}
+
+impl Image {
+    pub fn raw(data: Vec<u8>, addr: u32) -> Image {
+        Image {
+            segments: vec![Segment { addr, data }],
+            entry: Some(addr),
+        }
+    }
+
+    /// Loaded sections and entry point of a MIPS executable.
+    pub fn from_elf(file: &elf::File) -> Result<Image, String> {
+        if file.ehdr.elftype.0 != 2 {
+            return Err("File is not executable.".to_string());
+        }
+        if file.ehdr.machine.0 != 8 {
+            return Err("File is not MIPS.".to_string());
+        }
+
+        let mut image = Image::default();
+        for section in &file.sections {
+            // only the sections in the program's memory, .bss being read as zeros
+            if section.shdr.flags.0 & elf::types::SHF_ALLOC.0 != 0 && !section.data.is_empty() {
+                image.segments.push(Segment { addr: section.shdr.addr as u32, data: section.data.clone() });
+            }
+        }
+        image.entry = Some((file.ehdr.entry as u32 / 4) * 4);
+        Ok(image)
+    }
+
+    /// Where the image starts: its entry point or its first segment.
+    pub fn start(&self) -> Option<u32> {
+        self.entry.or_else(|| self.segments.first().map(|segment| segment.addr))
+    }
+
+    pub fn parse_intel_hex(text: &str) -> Result<Image, String> {
+        let mut image = Image::default();
+        let mut base = 0u32;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |msg: &str| format!("Line {}: {}", i + 1, msg);
+            if !line.starts_with(':') {
+                return Err(error("missing start code."));
+            }
+            let bytes = parse_record_bytes(&line[1..]).ok_or_else(|| error("invalid hexadecimal."))?;
+            if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+                return Err(error("invalid record length."));
+            }
+            if bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0 {
+                return Err(error("invalid checksum."));
+            }
+
+            let offset = (bytes[1] as u32) << 8 | bytes[2] as u32;
+            let data = &bytes[4..bytes.len() - 1];
+            let value = data.iter().fold(0u32, |value, &byte| value << 8 | byte as u32);
+            match (bytes[3], data.len()) {
+                (0x00, _) => image.push(base.wrapping_add(offset), data),
+                (0x01, _) => break,
+                (0x02, 2) => base = value << 4, // extended segment address
+                (0x03, 4) => image.entry = Some(((value >> 16) << 4) + (value & 0xffff)), // CS:IP
+                (0x04, 2) => base = value << 16, // extended linear address
+                (0x05, 4) => image.entry = Some(value),
+                _ => return Err(error("invalid record.")),
+            }
+        }
+        Ok(image)
+    }
+
+    pub fn parse_srecord(text: &str) -> Result<Image, String> {
+        let mut image = Image::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |msg: &str| format!("Line {}: {}", i + 1, msg);
+            if !line.starts_with('S') || line.len() < 2 {
+                return Err(error("missing start code."));
+            }
+            let kind = line.as_bytes()[1];
+            let bytes = parse_record_bytes(&line[2..]).ok_or_else(|| error("invalid hexadecimal."))?;
+            if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+                return Err(error("invalid record length."));
+            }
+            if bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0xff {
+                return Err(error("invalid checksum."));
+            }
+
+            let address_size = match kind {
+                b'0' | b'1' | b'5' | b'9' => 2,
+                b'2' | b'6' | b'8' => 3,
+                b'3' | b'7' => 4,
+                _ => return Err(error("invalid record type.")),
+            };
+            if bytes.len() < address_size + 2 {
+                return Err(error("invalid record length."));
+            }
+            let addr = bytes[1..=address_size].iter().fold(0u32, |addr, &byte| addr << 8 | byte as u32);
+            let data = &bytes[address_size + 1..bytes.len() - 1];
+            match kind {
+                b'1' | b'2' | b'3' => image.push(addr, data),
+                b'7' | b'8' | b'9' => image.entry = Some(addr),
+                _ => {}, // header and record counts
+            }
+        }
+        Ok(image)
+    }
+
+    /// Adds bytes at `addr`, extending the last segment when they follow it.
+    fn push(&mut self, addr: u32, data: &[u8]) {
+        if let Some(segment) = self.segments.last_mut() {
+            if segment.addr.wrapping_add(segment.data.len() as u32) == addr {
+                segment.data.extend_from_slice(data);
+                return;
+            }
+        }
+        self.segments.push(Segment { addr, data: data.to_vec() });
+    }
+}
+
+fn parse_record_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// A file read in memory, with its symbols and lines when it is an ELF.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub image: Image,
+    pub symbols: SymbolTable,
+    pub lines: LineTable,
+}
+
+/// Reads the file at `path`, a raw binary being loaded at `address`.
+pub fn read(path: &str, format: Format, address: u32) -> Result<Program, String> {
+    let read_error = |err: io::Error| format!("Can't read {}: {}", path, err);
+    let image = match format {
+        Format::Elf => {
+            let file = elf::File::open_path(path).map_err(|err| format!("Can't read {}: {:?}", path, err))?;
+            let image = Image::from_elf(&file).map_err(|err| format!("{}: {}", path, err))?;
+            // line information is optional, a malformed one is ignored
+            return Ok(Program {
+                image,
+                symbols: SymbolTable::from_elf(&file),
+                lines: LineTable::from_elf(&file).unwrap_or_default(),
+            });
+        },
+        Format::Raw => Image::raw(fs::read(path).map_err(read_error)?, address),
+        Format::IntelHex | Format::SRecord => {
+            let text = fs::read_to_string(path).map_err(read_error)?;
+            let image = match format {
+                Format::IntelHex => Image::parse_intel_hex(&text),
+                _ => Image::parse_srecord(&text),
+            };
+            image.map_err(|err| format!("{}: {}", path, err))?
+        },
+    };
+    Ok(Program { image, symbols: SymbolTable::new(), lines: LineTable::new() })
+}
+
+/// Splits `FILE@ADDRESS`, a raw binary to load at a hexadecimal or decimal
+/// address, from a file name.
+fn parse_file(spec: &str) -> Result<(&str, Option<u32>), String> {
+    let (path, address) = match spec.rfind('@') {
+        Some(index) => (&spec[..index], &spec[index + 1..]),
+        None => return Ok((spec, None)),
+    };
+    match parse_address(address) {
+        Some(address) => Ok((path, Some(address))),
+        None => Err(format!("Invalid load address {}.", address)),
+    }
+}
+
+fn parse_address(s: &str) -> Option<u32> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        u32::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+/// Loads the files at `paths` in `cpu`, which is reset. The first one is
+/// the program, the others are loaded next to it and can be raw binaries
+/// at a given address, as `FILE@ADDRESS`.
+pub fn load(cpu: &mut Cpu, paths: &[&str], options: &LoadOptions) -> Result<(), String> {
+    let mut entries = Vec::new();
+    for (i, spec) in paths.iter().enumerate() {
+        let (path, address) = if i == 0 { (*spec, None) } else { parse_file(spec)? };
+        let format = match address {
+            Some(_) => Format::Raw,
+            None if i == 0 => options.format.unwrap_or_else(|| Format::from_path(path)),
+            None => Format::from_path(path),
+        };
+        let program = read(path, format, address.unwrap_or(options.address))?;
+
+        if i == 0 {
+            cpu.load_image(&program.image, path)?;
+        } else {
+            cpu.add_image(&program.image, path)?;
+        }
+        cpu.symbols.extend(program.symbols);
+        cpu.lines.extend(program.lines);
+        entries.push((path, program.image.start()));
+    }
+
+    cpu.bare_metal = options.bare_metal;
+    if options.bare_metal {
+        cpu.boot();
+        return Ok(());
+    }
+    if let Some(ref name) = options.entry {
+        let entry = match entries.iter().find(|&&(path, _)| path == name) {
+            Some(&(path, entry)) => entry.ok_or_else(|| format!("{} has no entry point.", path))?,
+            None => cpu.symbols.find(name).map(|symbol| symbol.addr)
+                .or_else(|| parse_address(name))
+                .ok_or_else(|| format!("Unknown entry point {}.", name))?,
+        };
+        cpu.jump(entry & !0b11);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use elf::types::*;
+
+    fn section(name: &str, shtype: SectionType, flags: SectionFlag, addr: u64, data: Vec<u8>) -> elf::Section {
+        elf::Section {
+            shdr: SectionHeader {
+                name: name.to_string(),
+                shtype,
+                flags,
+                addr,
+                offset: 0,
+                size: data.len() as u64,
+                link: 0,
+                info: 0,
+                addralign: 0,
+                entsize: 0,
+            },
+            data,
+        }
+    }
+
+    #[test]
+    fn elf_loads_only_allocated_sections() {
+        let mut ehdr = FileHeader::new();
+        ehdr.elftype = ET_EXEC;
+        ehdr.machine = EM_MIPS;
+        ehdr.entry = 0x0040_0002;
+        let file = elf::File {
+            ehdr,
+            phdrs: Vec::new(),
+            sections: vec![
+                section("", SHT_NULL, SectionFlag(0), 0, Vec::new()),
+                section(".text", SHT_PROGBITS, SectionFlag(SHF_ALLOC.0 | SHF_EXECINSTR.0), 0x0040_0000, vec![1, 2, 3, 4]),
+                section(".bss", SHT_NOBITS, SectionFlag(SHF_WRITE.0 | SHF_ALLOC.0), 0x1001_0000, vec![0; 8]),
+                section(".comment", SHT_PROGBITS, SectionFlag(0), 0, b"GCC".to_vec()),
+                section(".debug_line", SHT_PROGBITS, SectionFlag(0), 0, vec![5; 16]),
+            ],
+        };
+
+        let image = Image::from_elf(&file).unwrap();
+        assert_eq!(image.segments, vec![
+            Segment { addr: 0x0040_0000, data: vec![1, 2, 3, 4] },
+            Segment { addr: 0x1001_0000, data: vec![0; 8] },
+        ]);
+        assert_eq!(image.entry, Some(0x0040_0000));
+    }
+
+    #[test]
+    fn intel_hex_records() {
+        let text = ":020000040040BA\n\
+                    :040000002402000ACC\n\
+                    :040004000000000CEC\n\
+                    :02010000ABCD85\n\
+                    :0400000500400000B7\n\
+                    :00000001FF\n\
+                    :02010000ABCD85\n";
+        let image = Image::parse_intel_hex(text).unwrap();
+        assert_eq!(image.segments, vec![
+            Segment { addr: 0x0040_0000, data: vec![0x24, 0x02, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x0c] },
+            Segment { addr: 0x0040_0100, data: vec![0xab, 0xcd] },
+        ]);
+        assert_eq!(image.entry, Some(0x0040_0000));
+
+        // extended segment address and CS:IP start address
+        let image = Image::parse_intel_hex(":020000021000EC\n:0100100001EE\n:0400000312340005AE\n").unwrap();
+        assert_eq!(image.segments, vec![Segment { addr: 0x0001_0010, data: vec![0x01] }]);
+        assert_eq!(image.entry, Some(0x0001_2345));
+    }
+
+    #[test]
+    fn intel_hex_errors() {
+        let error = |text| Image::parse_intel_hex(text).unwrap_err();
+        assert_eq!(error(":040000002402000ACD"), "Line 1: invalid checksum.");
+        assert_eq!(error("\n040000002402000ACC"), "Line 2: missing start code.");
+        assert_eq!(error(":050000002402000ACC"), "Line 1: invalid record length.");
+        assert_eq!(error(":0400000Z2402000ACC"), "Line 1: invalid hexadecimal.");
+        assert_eq!(error(":00000006FA"), "Line 1: invalid record.");
+        assert_eq!(error(":0100000400FB"), "Line 1: invalid record.");
+    }
+
+    #[test]
+    fn srecord_records() {
+        let text = "S00700007465737438\n\
+                    S309004000002402000A86\n\
+                    S309004000040000000CA6\n\
+                    S5030002FA\n\
+                    S70500400000BA\n";
+        let image = Image::parse_srecord(text).unwrap();
+        assert_eq!(image.segments, vec![
+            Segment { addr: 0x0040_0000, data: vec![0x24, 0x02, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x0c] },
+        ]);
+        assert_eq!(image.entry, Some(0x0040_0000));
+
+        // 16 and 24-bit addresses
+        let image = Image::parse_srecord("S1041000FFEC\nS205012000EEEB\nS9031000EC\n").unwrap();
+        assert_eq!(image.segments, vec![
+            Segment { addr: 0x1000, data: vec![0xff] },
+            Segment { addr: 0x0001_2000, data: vec![0xee] },
+        ]);
+        assert_eq!(image.entry, Some(0x1000));
+    }
+
+    #[test]
+    fn srecord_errors() {
+        let error = |text| Image::parse_srecord(text).unwrap_err();
+        assert_eq!(error("S309004000002402000A87"), "Line 1: invalid checksum.");
+        assert_eq!(error("S5030002FA\n:0100100001EE"), "Line 2: missing start code.");
+        assert_eq!(error("S309004000002402000A"), "Line 1: invalid record length.");
+        assert_eq!(error("S4030000FC"), "Line 1: invalid record type.");
+        assert_eq!(error("S7020000FD"), "Line 1: invalid record length.");
+    }
+}
+
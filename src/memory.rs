@@ -3,22 +3,62 @@ use std::fmt;
 use std::clone::Clone;
 use std::cell::RefCell;
 
+use device::Bus;
+
 #[derive(Debug, Clone)]
 pub struct Memory {
     blocks: BTreeMap<usize, Block>,
-    cache: RefCell<Option<Cache>>
+    cache: RefCell<Option<Cache>>,
+    journal: Option<Vec<(u32, u8)>>,
+    accesses: RefCell<Option<Vec<MemoryAccess>>>,
+    /// Devices mapped over the memory, their writes are not journaled.
+    pub bus: Bus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub addr: u32,
+    pub size: u8,
+    pub value: u32,
 }
 
 impl Memory {
     pub fn new() -> Memory {
         Memory {
             blocks: BTreeMap::new(),
-            cache: RefCell::new(None)
+            cache: RefCell::new(None),
+            journal: None,
+            accesses: RefCell::new(None),
+            bus: Bus::new(),
         }
     }
 
-    #[inline]
     pub fn get_byte(&self, index: u32) -> u8 {
+        let byte = match self.bus.find(index) {
+            Some((device, offset)) => device.borrow_mut().read(offset, 1) as u8,
+            None => self.read_byte(index),
+        };
+        self.log_access(AccessKind::Read, index, 1, byte as u32);
+        byte
+    }
+
+    pub fn set_byte(&mut self, index: u32, byte: u8) {
+        self.log_access(AccessKind::Write, index, 1, byte as u32);
+        match self.bus.find(index) {
+            Some((device, offset)) => device.borrow_mut().write(offset, 1, byte as u32),
+            None => self.write_byte(index, byte),
+        }
+    }
+
+    #[inline]
+    fn read_byte(&self, index: u32) -> u8 {
         let (block_id, data_id) = get_ids(index);
 
         if let Some(ref cache) = *self.cache.borrow() {
@@ -40,45 +80,134 @@ impl Memory {
     }
 
     #[inline]
-    pub fn set_byte(&mut self, index: u32, byte: u8) {
+    fn write_byte(&mut self, index: u32, byte: u8) {
         self.cache = RefCell::new(None); // we invalidate the cache
         let (block_id, data_id) = get_ids(index);
 
-        self.blocks
+        let block = self.blocks
             .entry(block_id)
-            .or_insert_with(Block::new).data[data_id] = byte;
+            .or_insert_with(Block::new);
+
+        if let Some(ref mut journal) = self.journal {
+            journal.push((index, block.data[data_id]));
+        }
+        block.data[data_id] = byte;
+    }
+
+    /// Reads a byte without the side effects of the devices nor logging the
+    /// access, for the debugger and the other inspections.
+    pub fn peek_byte(&self, index: u32) -> u8 {
+        match self.bus.find(index) {
+            Some((device, offset)) => device.borrow().peek(offset, 1) as u8,
+            None => self.read_byte(index),
+        }
+    }
+
+    pub fn peek_half_word(&self, index: u32) -> u16 {
+        if let Some((device, offset)) = self.bus.find(index) {
+            return device.borrow().peek(offset, 2) as u16;
+        }
+        self.read_byte(index) as u16 | (self.read_byte(index.wrapping_add(1)) as u16) << 8
+    }
+
+    pub fn peek_word(&self, index: u32) -> u32 {
+        if let Some((device, offset)) = self.bus.find(index) {
+            return device.borrow().peek(offset, 4);
+        }
+        (0..4).fold(0, |word, i| word | (self.read_byte(index.wrapping_add(i)) as u32) << (8 * i))
+    }
+
+    /// Iterates over the allocated blocks as `(base address, data)`.
+    pub fn blocks<'a>(&'a self) -> impl Iterator<Item = (u32, &'a [u8])> + 'a {
+        self.blocks
+            .iter()
+            .map(|(&block_id, block)| ((block_id << BLOCK_BIT_LEN) as u32, &block.data[..]))
+    }
+
+    /// Start recording the previous value of every byte written.
+    pub fn start_journal(&mut self) {
+        self.journal = Some(Vec::new());
+    }
+
+    /// Stop recording and return the `(address, previous value)` pairs in write order.
+    pub fn take_journal(&mut self) -> Vec<(u32, u8)> {
+        self.journal.take().unwrap_or_default()
+    }
+
+    /// Start recording every read and write access.
+    pub fn start_access_log(&self) {
+        *self.accesses.borrow_mut() = Some(Vec::new());
+    }
+
+    /// Stop recording and return the accesses in order.
+    pub fn take_access_log(&self) -> Vec<MemoryAccess> {
+        self.accesses.borrow_mut().take().unwrap_or_default()
+    }
+
+    #[inline]
+    fn log_access(&self, kind: AccessKind, addr: u32, size: u8, value: u32) {
+        if let Some(ref mut accesses) = *self.accesses.borrow_mut() {
+            accesses.push(MemoryAccess { kind, addr, size, value });
+        }
     }
 
     pub fn get_half_word(&self, index: u32) -> u16 {
-        let _0 = self.get_byte(index + 0) as u16;
-        let _1 = self.get_byte(index + 1) as u16;
+        if let Some((device, offset)) = self.bus.find(index) {
+            let half_word = device.borrow_mut().read(offset, 2) as u16;
+            self.log_access(AccessKind::Read, index, 2, half_word as u32);
+            return half_word;
+        }
 
-        _0 | (_1 << 8)
+        let _0 = self.read_byte(index + 0) as u16;
+        let _1 = self.read_byte(index + 1) as u16;
+
+        let half_word = _0 | (_1 << 8);
+        self.log_access(AccessKind::Read, index, 2, half_word as u32);
+        half_word
     }
 
     pub fn set_half_word(&mut self, index: u32, half_word: u16) {
-        self.set_byte(index + 0, half_word as u8);
-        self.set_byte(index + 1, (half_word >> 8) as u8);
+        self.log_access(AccessKind::Write, index, 2, half_word as u32);
+        if let Some((device, offset)) = self.bus.find(index) {
+            device.borrow_mut().write(offset, 2, half_word as u32);
+            return;
+        }
+        self.write_byte(index + 0, half_word as u8);
+        self.write_byte(index + 1, (half_word >> 8) as u8);
     }
 
     pub fn get_word(&self, index: u32) -> u32 {
-        let _0 = self.get_byte(index + 0) as u32;
-        let _1 = self.get_byte(index + 1) as u32;
-        let _2 = self.get_byte(index + 2) as u32;
-        let _3 = self.get_byte(index + 3) as u32;
+        if let Some((device, offset)) = self.bus.find(index) {
+            let word = device.borrow_mut().read(offset, 4);
+            self.log_access(AccessKind::Read, index, 4, word);
+            return word;
+        }
 
-        _0 | (_1 << 8) | (_2 << 16) | (_3 << 24)
+        let _0 = self.read_byte(index + 0) as u32;
+        let _1 = self.read_byte(index + 1) as u32;
+        let _2 = self.read_byte(index + 2) as u32;
+        let _3 = self.read_byte(index + 3) as u32;
+
+        let word = _0 | (_1 << 8) | (_2 << 16) | (_3 << 24);
+        self.log_access(AccessKind::Read, index, 4, word);
+        word
     } 
 
     pub fn set_word(&mut self, index: u32, word: u32) {
-        self.set_byte(index + 0, word as u8);
-        self.set_byte(index + 1, (word >> 8) as u8);
-        self.set_byte(index + 2, (word >> 16) as u8);
-        self.set_byte(index + 3, (word >> 24) as u8);
+        self.log_access(AccessKind::Write, index, 4, word);
+        if let Some((device, offset)) = self.bus.find(index) {
+            device.borrow_mut().write(offset, 4, word);
+            return;
+        }
+        self.write_byte(index + 0, word as u8);
+        self.write_byte(index + 1, (word >> 8) as u8);
+        self.write_byte(index + 2, (word >> 16) as u8);
+        self.write_byte(index + 3, (word >> 24) as u8);
     }
 }
 
 const BLOCK_BIT_LEN: usize = 8;
+pub const BLOCK_SIZE: usize = 1 << BLOCK_BIT_LEN;
 
 struct Block {
     pub data: [u8; 1 << BLOCK_BIT_LEN]
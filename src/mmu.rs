@@ -0,0 +1,165 @@
+//! MIPS32 address translation.
+//!
+//! The virtual address space is split in segments: kuseg (0x00000000,
+//! mapped, the only one allowed in user mode), kseg0 (0x80000000, unmapped)
+//! and kseg1 (0xa0000000, unmapped) both seeing the first 512 MiB of
+//! physical memory, and kseg2/kseg3 (0xc0000000, mapped). Mapped addresses
+//! go through a software managed TLB whose entries map pairs of even and
+//! odd pages. Without an MMU the memory is flat, virtual and physical
+//! addresses being the same.
+
+use cop0::{self, Cop0, Exception};
+use memory::AccessKind;
+
+const ENTRY_LO_GLOBAL: u32 = 1 << 0;
+const ENTRY_LO_VALID: u32 = 1 << 1;
+const ENTRY_LO_DIRTY: u32 = 1 << 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TlbEntry {
+    pub page_mask: u32,
+    pub entry_hi: u32,
+    pub entry_lo0: u32, // even page
+    pub entry_lo1: u32, // odd page
+}
+
+impl TlbEntry {
+    /// Entry written by `tlbwi` and `tlbwr`, global only when both pages are.
+    pub fn from_cop0(cop0: &Cop0) -> TlbEntry {
+        let global = cop0.entry_lo0 & cop0.entry_lo1 & ENTRY_LO_GLOBAL;
+        TlbEntry {
+            page_mask: cop0.page_mask,
+            entry_hi: cop0.entry_hi,
+            entry_lo0: (cop0.entry_lo0 & !ENTRY_LO_GLOBAL) | global,
+            entry_lo1: (cop0.entry_lo1 & !ENTRY_LO_GLOBAL) | global,
+        }
+    }
+
+    /// Whether one of the pages is valid.
+    pub fn valid(&self) -> bool {
+        (self.entry_lo0 | self.entry_lo1) & ENTRY_LO_VALID != 0
+    }
+
+    pub fn global(&self) -> bool {
+        self.entry_lo0 & ENTRY_LO_GLOBAL != 0
+    }
+
+    /// First bit above the offset in a page, selecting the odd page.
+    fn odd_page_bit(&self) -> u32 {
+        ((self.page_mask | 0x1fff) >> 1) + 1
+    }
+
+    pub fn matches(&self, vaddr: u32, asid: u32) -> bool {
+        let vpn2_mask = !(self.page_mask | 0x1fff);
+        (self.entry_hi ^ vaddr) & vpn2_mask == 0 &&
+            (self.global() || self.entry_hi & cop0::ENTRY_HI_ASID == asid)
+    }
+}
+
+/// TLB entries. The entry overwritten by the last write is kept so that the
+/// execution history can undo it.
+#[derive(Debug, Clone)]
+pub struct Tlb {
+    pub entries: Vec<TlbEntry>,
+    last_write: Option<(usize, TlbEntry)>,
+}
+
+impl Tlb {
+    pub fn new(size: usize) -> Tlb {
+        Tlb {
+            entries: vec![TlbEntry::default(); size],
+            last_write: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        for entry in &mut self.entries {
+            *entry = TlbEntry::default();
+        }
+        self.last_write = None;
+    }
+
+    /// Index of the entry mapping `vaddr` for the address space `asid`.
+    pub fn probe(&self, vaddr: u32, asid: u32) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.matches(vaddr, asid))
+    }
+
+    pub fn read(&self, index: u32) -> TlbEntry {
+        self.entries[index as usize % self.entries.len()]
+    }
+
+    pub fn write(&mut self, index: u32, entry: TlbEntry) {
+        let index = index as usize % self.entries.len();
+        self.last_write = Some((index, self.entries[index]));
+        self.entries[index] = entry;
+    }
+
+    /// Index and previous value of the entry written since the last call.
+    pub fn take_last_write(&mut self) -> Option<(usize, TlbEntry)> {
+        self.last_write.take()
+    }
+
+    /// Puts back an entry, without recording it as a write.
+    pub fn restore(&mut self, index: usize, entry: TlbEntry) {
+        self.entries[index] = entry;
+    }
+}
+
+/// Physical address of an address of kseg0 or kseg1, mapped without the TLB.
+pub fn unmapped(vaddr: u32) -> Option<u32> {
+    match vaddr >> 29 {
+        4 | 5 => Some(vaddr & 0x1fffffff),
+        _ => None,
+    }
+}
+
+/// Translates the virtual address `vaddr` accessed as `kind`, `tlb` being
+/// `None` when there is no MMU.
+pub fn translate(cop0: &Cop0, tlb: Option<&Tlb>, vaddr: u32, kind: AccessKind) -> Result<u32, Exception> {
+    let (address_error, tlb_error) = match kind {
+        AccessKind::Read => (cop0::EXC_ADEL, cop0::EXC_TLBL),
+        AccessKind::Write => (cop0::EXC_ADES, cop0::EXC_TLBS),
+    };
+    if cop0.user_mode() && vaddr >= 0x80000000 {
+        return Err(Exception::address(address_error, vaddr));
+    }
+
+    let tlb = match tlb {
+        Some(tlb) => tlb,
+        None => return Ok(vaddr),
+    };
+    if let Some(paddr) = unmapped(vaddr) {
+        return Ok(paddr);
+    }
+    // kuseg is unmapped while the error level is set, e.g. after a reset
+    if vaddr < 0x80000000 && cop0.status & cop0::STATUS_ERL != 0 {
+        return Ok(vaddr);
+    }
+
+    let entry = match tlb.probe(vaddr, cop0.entry_hi & cop0::ENTRY_HI_ASID) {
+        Some(index) => tlb.entries[index],
+        None => {
+            return Err(Exception { code: tlb_error, bad_vaddr: Some(vaddr), refill: true });
+        },
+    };
+    let odd_page_bit = entry.odd_page_bit();
+    let entry_lo = if vaddr & odd_page_bit != 0 { entry.entry_lo1 } else { entry.entry_lo0 };
+    if entry_lo & ENTRY_LO_VALID == 0 {
+        return Err(Exception::address(tlb_error, vaddr));
+    }
+    if kind == AccessKind::Write && entry_lo & ENTRY_LO_DIRTY == 0 {
+        return Err(Exception::address(cop0::EXC_MOD, vaddr));
+    }
+
+    let offset_mask = odd_page_bit - 1;
+    let frame = (entry_lo >> 6) << 12;
+    Ok((frame & !offset_mask) | (vaddr & offset_mask))
+}
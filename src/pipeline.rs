@@ -0,0 +1,314 @@
+//! Cycle-approximate timing of a classic MIPS R2000/R3000 5-stage pipeline.
+//!
+//! Instructions flow in order through IF, ID, EX, MEM and WB, with full
+//! forwarding. Branches and jumps are resolved in ID so the delay slot hides
+//! their latency, but their register operands are needed one cycle earlier
+//! than for the other instructions. Loaded values are available after MEM,
+//! hi/lo (and the result of `mul` and of the Release 6 multiplications and
+//! divisions) after the multiply/divide latency.
+//!
+//! With a branch predictor, a mispredicted branch also redirects the fetch of
+//! the instruction following its delay slot, which costs a penalty.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use cache::CacheHierarchy;
+use cpu::Cpu;
+use diagram::Diagram;
+use instruction::{Instruction, InstructionClass};
+use predictor::BranchPredictor;
+use trace::{Observer, Record};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimingConfig {
+    pub fetch_latency: u32, // extra cycles of each instruction fetch
+    pub load_latency: u32, // extra cycles of each data read
+    pub store_latency: u32, // extra cycles of each data write
+    pub mult_latency: u32, // cycles before the result of a multiplication is ready
+    pub div_latency: u32, // cycles before the result of a division is ready
+    pub mispredict_penalty: u32, // cycles lost by a mispredicted branch
+}
+
+impl Default for TimingConfig {
+    fn default() -> TimingConfig {
+        TimingConfig {
+            fetch_latency: 0,
+            load_latency: 0,
+            store_latency: 0,
+            mult_latency: 12,
+            div_latency: 35,
+            mispredict_penalty: 2,
+        }
+    }
+}
+
+impl TimingConfig {
+    /// Sets a latency from its name: fetch, load, store, mult, div or mispredict.
+    pub fn set(&mut self, name: &str, cycles: u32) -> Result<(), String> {
+        match name {
+            "fetch" => self.fetch_latency = cycles,
+            "load" => self.load_latency = cycles,
+            "store" => self.store_latency = cycles,
+            "mult" => self.mult_latency = cycles,
+            "div" => self.div_latency = cycles,
+            "mispredict" => self.mispredict_penalty = cycles,
+            _ => return Err(format!("Unknown latency {}.", name)),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Stall {
+    LoadUse,
+    BranchOperand,
+    MulDiv,
+    InstructionFetch,
+    DataMemory,
+    Mispredict,
+}
+
+impl Stall {
+    pub const ALL: [Stall; 6] = [
+        Stall::LoadUse,
+        Stall::BranchOperand,
+        Stall::MulDiv,
+        Stall::InstructionFetch,
+        Stall::DataMemory,
+        Stall::Mispredict,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Stall::LoadUse => "load-use",
+            Stall::BranchOperand => "branch operand",
+            Stall::MulDiv => "mul/div",
+            Stall::InstructionFetch => "instruction fetch",
+            Stall::DataMemory => "data memory",
+            Stall::Mispredict => "branch mispredict",
+        }
+    }
+}
+
+/// Cycles at which an instruction enters each stage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Schedule {
+    pub fetch: u64,
+    pub decode: u64,
+    pub execute: u64,
+    pub memory: u64,
+    pub writeback: u64,
+    pub end: u64, // first cycle after writeback
+    pub stall: Option<Stall>, // main cause of the stall cycles
+}
+
+#[derive(Debug)]
+pub struct Pipeline {
+    pub config: TimingConfig,
+    pub instructions: u64,
+    pub stalls: BTreeMap<Stall, u64>,
+    /// Memory latencies come from these caches instead of the configuration.
+    pub caches: Option<CacheHierarchy>,
+    /// Branches are predicted, the mispredicted ones cost the penalty.
+    pub predictor: Option<BranchPredictor>,
+    /// Diagram of the scheduled instructions.
+    pub diagram: Option<Diagram>,
+    last: Option<Schedule>,
+    // cycle from which each register can be forwarded to EX, and the stall waiting for it causes
+    ready: [(u64, Stall); 32],
+    hilo_ready: u64,
+    muldiv_busy: u64,
+    redirect: Option<u64>, // instruction whose fetch follows a misprediction
+}
+
+impl Pipeline {
+    pub fn new(config: TimingConfig) -> Pipeline {
+        Pipeline {
+            config,
+            instructions: 0,
+            stalls: BTreeMap::new(),
+            caches: None,
+            predictor: None,
+            diagram: None,
+            last: None,
+            ready: [(0, Stall::LoadUse); 32],
+            hilo_ready: 0,
+            muldiv_busy: 0,
+            redirect: None,
+        }
+    }
+
+    /// Total cycles so far, including the filling of the pipeline.
+    pub fn cycles(&self) -> u64 {
+        self.last.map_or(0, |schedule| schedule.end)
+    }
+
+    pub fn cpi(&self) -> f64 {
+        if self.instructions == 0 {
+            0.0
+        } else {
+            self.cycles() as f64 / self.instructions as f64
+        }
+    }
+
+    /// Schedules the next executed instruction and returns its stage cycles.
+    pub fn schedule(&mut self, record: &Record, cpu: &Cpu) -> Schedule {
+        let config = self.config;
+        let last = self.last.unwrap_or_default();
+        let first = self.last.is_none();
+        let inst = &record.instruction(cpu.isa);
+        let class = inst.class();
+
+        let (fetch_latency, data_latency) = match self.caches {
+            Some(ref mut caches) => caches.simulate(record, cpu),
+            None => (config.fetch_latency, match class {
+                InstructionClass::Load => config.load_latency,
+                InstructionClass::Store => config.store_latency,
+                _ => 0,
+            }),
+        };
+
+        let penalty = if self.redirect == Some(self.instructions) { config.mispredict_penalty } else { 0 };
+        if let Some(ref mut predictor) = self.predictor {
+            if predictor.simulate(record, cpu) == Some(true) {
+                self.redirect = Some(self.instructions + 2); // after the delay slot
+            }
+        }
+
+        // IF and ID, the fetch latency and mispredictions stall the front of the pipeline
+        let fetch = last.decode;
+        let decode_ready = if first { fetch + 1 } else { (fetch + 1).max(last.execute) };
+        let decode = decode_ready.max(fetch + 1 + fetch_latency as u64 + penalty as u64);
+        let mispredict = (penalty as u64).min(decode - decode_ready);
+
+        // EX waits for the operands, needed in ID by branches and jumps
+        let execute_ready = if first { decode + 1 } else { (decode + 1).max(last.memory) };
+        let mut execute = execute_ready;
+        let mut stall = None;
+        let in_decode = class == InstructionClass::Branch || class == InstructionClass::Jump;
+        for reg in inst.sources() {
+            let (ready, kind) = self.ready[reg as usize];
+            let (ready, kind) = if in_decode {
+                (ready + 1, if kind == Stall::LoadUse { kind } else { Stall::BranchOperand })
+            } else {
+                (ready, kind)
+            };
+            if ready > execute {
+                execute = ready;
+                stall = Some(kind);
+            }
+        }
+        let reads_hilo = match *inst {
+            Instruction::MFHI(_) | Instruction::MFLO(_) => Some(self.hilo_ready),
+            _ if class == InstructionClass::MulDiv || is_hilo_write(inst) => Some(self.muldiv_busy),
+            _ => None,
+        };
+        if let Some(ready) = reads_hilo {
+            if ready > execute {
+                execute = ready;
+                stall = Some(Stall::MulDiv);
+            }
+        }
+
+        // MEM and WB, the data memory latency holds the instruction in MEM
+        let memory = if first { execute + 1 } else { (execute + 1).max(last.writeback) };
+        let latency = data_latency as u64;
+        let writeback = memory + 1 + latency;
+
+        // only the cycles that delay the end of the instruction are stalls, the
+        // other ones overlap with stalls of the previous instructions
+        let ideal_end = if first { 5 } else { last.end + 1 };
+        let mut remaining = writeback + 1 - ideal_end;
+        let delays = [
+            (Stall::DataMemory, latency),
+            (stall.unwrap_or(Stall::LoadUse), execute - execute_ready),
+            (Stall::Mispredict, mispredict),
+            (Stall::InstructionFetch, decode - decode_ready - mispredict),
+        ];
+        let mut main_stall = (0, None);
+        for &(kind, cycles) in delays.iter() {
+            let cycles = cycles.min(remaining);
+            self.add_stall(kind, cycles);
+            remaining -= cycles;
+            if cycles > main_stall.0 {
+                main_stall = (cycles, Some(kind));
+            }
+        }
+
+        // availability of the results
+        let latency = match *inst {
+            Instruction::DIV(..) | Instruction::DIVU(..) | Instruction::DIVR6(..) |
+            Instruction::DIVUR6(..) | Instruction::MOD(..) | Instruction::MODU(..) => config.div_latency,
+            _ => config.mult_latency,
+        } as u64;
+        match *inst {
+            Instruction::MULT(..) | Instruction::MULTU(..) |
+            Instruction::MADD(..) | Instruction::MADDU(..) |
+            Instruction::MSUB(..) | Instruction::MSUBU(..) |
+            Instruction::DIV(..) | Instruction::DIVU(..) => {
+                self.hilo_ready = execute + latency;
+                self.muldiv_busy = execute + latency;
+            },
+            _ if is_hilo_write(inst) => self.hilo_ready = execute + 1,
+            _ => {},
+        }
+        if let Some(reg) = inst.destination() {
+            self.ready[reg as usize] = match *inst {
+                _ if class == InstructionClass::MulDiv => (execute + latency, Stall::MulDiv),
+                _ if class == InstructionClass::Load => (writeback, Stall::LoadUse),
+                _ => (execute + 1, Stall::BranchOperand),
+            };
+        }
+
+        let schedule = Schedule {
+            fetch,
+            decode,
+            execute,
+            memory,
+            writeback,
+            end: writeback + 1,
+            stall: main_stall.1,
+        };
+        if let Some(ref mut diagram) = self.diagram {
+            diagram.add(record, inst, schedule);
+        }
+        self.last = Some(schedule);
+        self.instructions += 1;
+        schedule
+    }
+
+    pub fn report<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let cycles = self.cycles();
+        writeln!(writer, "Cycles: {}", cycles)?;
+        writeln!(writer, "Instructions: {}", self.instructions)?;
+        writeln!(writer, "CPI: {:.3}", self.cpi())?;
+
+        let total: u64 = self.stalls.values().sum();
+        writeln!(writer, "Stall cycles: {}", total)?;
+        for kind in Stall::ALL.iter() {
+            let count = self.stalls.get(kind).cloned().unwrap_or(0);
+            if count != 0 {
+                let percent = count as f64 * 100.0 / cycles as f64;
+                writeln!(writer, "  {:<18} {:>12} {:>6.2}%", kind.name(), count, percent)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn add_stall(&mut self, kind: Stall, cycles: u64) {
+        if cycles != 0 {
+            *self.stalls.entry(kind).or_insert(0) += cycles;
+        }
+    }
+}
+
+impl Observer for Pipeline {
+    fn observe(&mut self, record: &Record, cpu: &Cpu) {
+        self.schedule(record, cpu);
+    }
+}
+
+fn is_hilo_write(inst: &Instruction) -> bool {
+    matches!(*inst, Instruction::MTHI(_) | Instruction::MTLO(_))
+}
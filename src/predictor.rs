@@ -0,0 +1,270 @@
+//! Branch predictor simulation. Every conditional branch executed is
+//! predicted and then used to train the predictor; jumps and the always taken
+//! `b` and `bal` need no prediction and are only counted.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+
+use cpu::Cpu;
+use instruction::{Instruction, InstructionClass};
+use trace::{Observer, Record};
+
+/// Direction predictor of conditional branches.
+pub trait Predictor: fmt::Debug {
+    fn predict(&self, pc: u32, target: u32) -> bool;
+    fn update(&mut self, pc: u32, target: u32, taken: bool);
+}
+
+pub const PREDICTORS: [&str; 6] = ["not-taken", "btfn", "1-bit", "2-bit", "gshare", "tournament"];
+
+/// Creates a predictor from its name, with tables of `2^bits` entries.
+pub fn create(name: &str, bits: u32) -> Result<Box<dyn Predictor>, String> {
+    if bits == 0 || bits > 24 {
+        return Err(format!("Invalid predictor size of {} bits.", bits));
+    }
+    Ok(match name {
+        "not-taken" => Box::new(NotTaken),
+        "btfn" => Box::new(Btfn),
+        "1-bit" => Box::new(OneBit { taken: vec![false; 1 << bits] }),
+        "2-bit" => Box::new(Counters::new(bits)),
+        "gshare" => Box::new(Gshare::new(bits)),
+        "tournament" => Box::new(Tournament {
+            local: Counters::new(bits),
+            global: Gshare::new(bits),
+            chooser: Counters::new(bits),
+        }),
+        _ => return Err(format!("Unknown predictor {}.", name)),
+    })
+}
+
+#[derive(Debug)]
+struct NotTaken;
+
+impl Predictor for NotTaken {
+    fn predict(&self, _pc: u32, _target: u32) -> bool {
+        false
+    }
+
+    fn update(&mut self, _pc: u32, _target: u32, _taken: bool) {}
+}
+
+/// Backward taken, forward not taken.
+#[derive(Debug)]
+struct Btfn;
+
+impl Predictor for Btfn {
+    fn predict(&self, pc: u32, target: u32) -> bool {
+        target <= pc
+    }
+
+    fn update(&mut self, _pc: u32, _target: u32, _taken: bool) {}
+}
+
+fn index(pc: u32, len: usize) -> usize {
+    (pc >> 2) as usize & (len - 1)
+}
+
+/// Last direction of each branch.
+#[derive(Debug)]
+struct OneBit {
+    taken: Vec<bool>,
+}
+
+impl Predictor for OneBit {
+    fn predict(&self, pc: u32, _target: u32) -> bool {
+        self.taken[index(pc, self.taken.len())]
+    }
+
+    fn update(&mut self, pc: u32, _target: u32, taken: bool) {
+        let index = index(pc, self.taken.len());
+        self.taken[index] = taken;
+    }
+}
+
+/// 2-bit saturating counters, starting weakly not taken.
+#[derive(Debug)]
+struct Counters {
+    counters: Vec<u8>,
+}
+
+impl Counters {
+    fn new(bits: u32) -> Counters {
+        Counters { counters: vec![1; 1 << bits] }
+    }
+
+    fn get(&self, index: usize) -> bool {
+        self.counters[index & (self.counters.len() - 1)] >= 2
+    }
+
+    fn train(&mut self, index: usize, taken: bool) {
+        let len = self.counters.len();
+        let counter = &mut self.counters[index & (len - 1)];
+        *counter = if taken { (*counter + 1).min(3) } else { counter.saturating_sub(1) };
+    }
+}
+
+impl Predictor for Counters {
+    fn predict(&self, pc: u32, _target: u32) -> bool {
+        self.get((pc >> 2) as usize)
+    }
+
+    fn update(&mut self, pc: u32, _target: u32, taken: bool) {
+        self.train((pc >> 2) as usize, taken);
+    }
+}
+
+/// 2-bit counters indexed by the address xor the global history.
+#[derive(Debug)]
+struct Gshare {
+    history: usize,
+    counters: Counters,
+}
+
+impl Gshare {
+    fn new(bits: u32) -> Gshare {
+        Gshare { history: 0, counters: Counters::new(bits) }
+    }
+
+    fn index(&self, pc: u32) -> usize {
+        (pc >> 2) as usize ^ self.history
+    }
+}
+
+impl Predictor for Gshare {
+    fn predict(&self, pc: u32, _target: u32) -> bool {
+        self.counters.get(self.index(pc))
+    }
+
+    fn update(&mut self, pc: u32, _target: u32, taken: bool) {
+        let index = self.index(pc);
+        self.counters.train(index, taken);
+        self.history = ((self.history << 1) | taken as usize) & (self.counters.counters.len() - 1);
+    }
+}
+
+/// Chooses per branch between 2-bit counters and gshare, with 2-bit counters
+/// trained toward the one that was right when they disagree.
+#[derive(Debug)]
+struct Tournament {
+    local: Counters,
+    global: Gshare,
+    chooser: Counters, // taken means global
+}
+
+impl Predictor for Tournament {
+    fn predict(&self, pc: u32, target: u32) -> bool {
+        if self.chooser.predict(pc, target) {
+            self.global.predict(pc, target)
+        } else {
+            self.local.predict(pc, target)
+        }
+    }
+
+    fn update(&mut self, pc: u32, target: u32, taken: bool) {
+        let local = self.local.predict(pc, target);
+        let global = self.global.predict(pc, target);
+        if local != global {
+            self.chooser.update(pc, target, global == taken);
+        }
+        self.local.update(pc, target, taken);
+        self.global.update(pc, target, taken);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchSite {
+    pub executed: u64,
+    pub taken: u64,
+    pub mispredicted: u64,
+}
+
+#[derive(Debug)]
+pub struct BranchPredictor {
+    pub name: String,
+    pub sites: BTreeMap<u32, BranchSite>,
+    pub jumps: u64, // jumps and always taken branches
+    predictor: Box<dyn Predictor>,
+}
+
+impl BranchPredictor {
+    pub fn new(name: &str, bits: u32) -> Result<BranchPredictor, String> {
+        Ok(BranchPredictor {
+            name: name.to_string(),
+            sites: BTreeMap::new(),
+            jumps: 0,
+            predictor: create(name, bits)?,
+        })
+    }
+
+    pub fn branches(&self) -> u64 {
+        self.sites.values().map(|site| site.executed).sum()
+    }
+
+    pub fn mispredictions(&self) -> u64 {
+        self.sites.values().map(|site| site.mispredicted).sum()
+    }
+
+    /// Predicts the executed instruction if it is a conditional branch,
+    /// returns whether the prediction was wrong.
+    pub fn simulate(&mut self, record: &Record, cpu: &Cpu) -> Option<bool> {
+        let inst = record.instruction(cpu.isa);
+        let target = match inst.branch_target(record.pc) {
+            Some(target) if inst.is_conditional_branch() => target,
+            _ => {
+                if matches!(inst.class(), InstructionClass::Branch | InstructionClass::Jump) {
+                    self.jumps += 1;
+                }
+                return None;
+            },
+        };
+
+        let taken = cpu.branch_destination(&inst) == target;
+        let predicted = self.predictor.predict(record.pc, target);
+        self.predictor.update(record.pc, target, taken);
+
+        let site = self.sites.entry(record.pc).or_default();
+        site.executed += 1;
+        site.taken += taken as u64;
+        site.mispredicted += (predicted != taken) as u64;
+        Some(predicted != taken)
+    }
+
+    pub fn report<W: Write>(&self, writer: &mut W, cpu: &Cpu, top: usize) -> io::Result<()> {
+        let branches = self.branches();
+        let mispredictions = self.mispredictions();
+        writeln!(writer, "Predictor: {}", self.name)?;
+        writeln!(writer, "Conditional branches: {}", branches)?;
+        writeln!(writer, "Mispredictions: {}", mispredictions)?;
+        writeln!(writer, "Accuracy: {:.2}%", accuracy(branches, mispredictions))?;
+        writeln!(writer, "Jumps and unconditional branches: {}", self.jumps)?;
+
+        let mut sites: Vec<_> = self.sites.iter().collect();
+        sites.sort_by(|a, b| b.1.mispredicted.cmp(&a.1.mispredicted).then(a.0.cmp(b.0)));
+        writeln!(writer, "\n{:>10} {:>10} {:>8} {:>9}  branch", "executed", "mispred", "taken %", "accuracy")?;
+        for (&pc, site) in sites.into_iter().take(top) {
+            let inst = Instruction::from_word(cpu.peek_word(pc).unwrap_or(0), cpu.isa);
+            let symbol = cpu.symbols.describe(pc).map(|name| format!(" <{}>", name)).unwrap_or_default();
+            writeln!(writer, "{:>10} {:>10} {:>7.2}% {:>8.2}%  {:#010x}: {}{}",
+                     site.executed, site.mispredicted,
+                     site.taken as f64 * 100.0 / site.executed as f64,
+                     accuracy(site.executed, site.mispredicted),
+                     pc, inst, symbol)?;
+        }
+        Ok(())
+    }
+}
+
+impl Observer for BranchPredictor {
+    fn observe(&mut self, record: &Record, cpu: &Cpu) {
+        self.simulate(record, cpu);
+    }
+}
+
+fn accuracy(branches: u64, mispredictions: u64) -> f64 {
+    if branches == 0 {
+        100.0
+    } else {
+        (branches - mispredictions) as f64 * 100.0 / branches as f64
+    }
+}
@@ -0,0 +1,227 @@
+//! Execution profile of a program, collected as a trace observer.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use cpu::Cpu;
+use instruction::{Instruction, InstructionClass};
+use symbols::SymbolTable;
+use trace::{Observer, Record};
+
+/// Straight-line sequence of executed instructions, split after the delay
+/// slot of every branch and jump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: u32,
+    pub end: u32, // last instruction, included
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    pub total: u64,
+    pub pc_counts: HashMap<u32, u64>,
+    pub class_counts: HashMap<InstructionClass, u64>,
+    pub branches_taken: u64,
+    pub branches_not_taken: u64,
+    blocks: HashMap<u32, BasicBlock>,
+    current_block: Option<u32>,
+    last_pc: u32,
+    in_delay_slot: bool,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    pub fn count(&self, pc: u32) -> u64 {
+        self.pc_counts.get(&pc).cloned().unwrap_or(0)
+    }
+
+    /// Executed basic blocks, the hottest first.
+    pub fn blocks(&self) -> Vec<BasicBlock> {
+        let mut blocks: Vec<BasicBlock> = self.blocks.values().cloned().collect();
+        blocks.sort_by_key(|block| (Reverse(block.executed()), block.start));
+        blocks
+    }
+
+    /// Instruction counts per symbol, the hottest first. Unknown code is under `None`.
+    pub fn symbols(&self, symbols: &SymbolTable) -> Vec<(Option<String>, u64)> {
+        let mut counts: HashMap<Option<String>, u64> = HashMap::new();
+        for (&pc, &count) in &self.pc_counts {
+            let name = symbols.lookup(pc).map(|symbol| symbol.name.clone());
+            *counts.entry(name).or_insert(0) += count;
+        }
+
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        counts
+    }
+
+    pub fn report<W: Write>(&self, writer: &mut W, cpu: &Cpu, top: usize) -> io::Result<()> {
+        writeln!(writer, "Executed instructions: {}", self.total)?;
+
+        writeln!(writer, "\nInstruction mix:")?;
+        for class in InstructionClass::ALL.iter() {
+            let count = self.class_counts.get(class).cloned().unwrap_or(0);
+            if count != 0 {
+                writeln!(writer, "  {:<8} {:>12} {:>6.2}%", class.name(), count, self.percent(count))?;
+            }
+        }
+        let branches = self.branches_taken + self.branches_not_taken;
+        if branches != 0 {
+            writeln!(writer, "  branches taken: {} ({:.2}%), not taken: {}",
+                     self.branches_taken,
+                     self.branches_taken as f64 * 100.0 / branches as f64,
+                     self.branches_not_taken)?;
+        }
+
+        writeln!(writer, "\nHottest functions:")?;
+        for (name, count) in self.symbols(&cpu.symbols).into_iter().take(top) {
+            let name = name.unwrap_or_else(|| "<unknown>".to_string());
+            writeln!(writer, "  {:>12} {:>6.2}%  {}", count, self.percent(count), name)?;
+        }
+
+        writeln!(writer, "\nHottest basic blocks:")?;
+        for block in self.blocks().into_iter().take(top) {
+            let executed = block.executed();
+            writeln!(writer, "  {:>12} {:>6.2}%  {:#010x}-{:#010x} {}x  {}",
+                     executed, self.percent(executed), block.start, block.end, block.count,
+                     describe(cpu, block.start))?;
+        }
+
+        writeln!(writer, "\nHottest instructions:")?;
+        let mut pcs: Vec<(u32, u64)> = self.pc_counts.iter().map(|(&pc, &count)| (pc, count)).collect();
+        pcs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (pc, count) in pcs.into_iter().take(top) {
+            let inst = Instruction::from_word(cpu.peek_word(pc).unwrap_or(0), cpu.isa);
+            writeln!(writer, "  {:>12} {:>6.2}%  {:#010x} {:<24} {}", count, self.percent(count), pc, inst, describe(cpu, pc))?;
+        }
+        Ok(())
+    }
+
+    /// Disassembly of every executed function with the count of each instruction.
+    pub fn annotate<W: Write>(&self, writer: &mut W, cpu: &Cpu) -> io::Result<()> {
+        let mut pcs: Vec<u32> = self.pc_counts.keys().cloned().collect();
+        pcs.sort();
+
+        let mut index = 0;
+        while index < pcs.len() {
+            let pc = pcs[index];
+            // disassemble the whole symbol, or only the executed run of instructions without one
+            let (name, start, end) = match cpu.symbols.lookup(pc) {
+                Some(symbol) if symbol.size != 0 => (symbol.name.clone(), symbol.addr, symbol.addr.wrapping_add(symbol.size)),
+                Some(symbol) => {
+                    let mut last = index;
+                    while last + 1 < pcs.len() && cpu.symbols.lookup(pcs[last + 1]) == Some(symbol) {
+                        last += 1;
+                    }
+                    (symbol.name.clone(), symbol.addr, pcs[last].wrapping_add(4))
+                },
+                None => {
+                    let mut last = index;
+                    while last + 1 < pcs.len() && pcs[last + 1] == pcs[last].wrapping_add(4)
+                        && cpu.symbols.lookup(pcs[last + 1]).is_none() {
+                        last += 1;
+                    }
+                    ("<unknown>".to_string(), pc, pcs[last].wrapping_add(4))
+                },
+            };
+
+            let total: u64 = pcs[index..].iter()
+                .take_while(|&&pc| pc >= start && pc < end)
+                .map(|&pc| self.count(pc))
+                .sum();
+            writeln!(writer, "{} ({:.2}%):", name, self.percent(total))?;
+
+            let mut addr = start;
+            while addr < end {
+                let count = self.count(addr);
+                let inst = Instruction::from_word(cpu.peek_word(addr).unwrap_or(0), cpu.isa);
+                if count == 0 {
+                    writeln!(writer, "  {:>12}          {:#010x}  {}", "", addr, inst)?;
+                } else {
+                    writeln!(writer, "  {:>12} {:>6.2}%  {:#010x}  {}", count, self.percent(count), addr, inst)?;
+                }
+                addr = addr.wrapping_add(4);
+            }
+            writeln!(writer)?;
+
+            while index < pcs.len() && pcs[index] >= start && pcs[index] < end {
+                index += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            count as f64 * 100.0 / self.total as f64
+        }
+    }
+}
+
+impl BasicBlock {
+    pub fn instruction_count(&self) -> u32 {
+        (self.end.wrapping_sub(self.start) >> 2) + 1
+    }
+
+    /// Instructions executed in this block.
+    pub fn executed(&self) -> u64 {
+        self.count * self.instruction_count() as u64
+    }
+}
+
+impl Observer for Profiler {
+    fn observe(&mut self, record: &Record, cpu: &Cpu) {
+        let inst = record.instruction(cpu.isa);
+        let class = inst.class();
+
+        self.total += 1;
+        *self.pc_counts.entry(record.pc).or_insert(0) += 1;
+        *self.class_counts.entry(class).or_insert(0) += 1;
+
+        if let Some(target) = inst.branch_target(record.pc) {
+            if cpu.branch_destination(&inst) == target {
+                self.branches_taken += 1;
+            } else {
+                self.branches_not_taken += 1;
+            }
+        }
+
+        // a block continues while instructions follow each other
+        let continues = self.current_block.is_some()
+            && self.last_pc.wrapping_add(4) == record.pc;
+        if continues {
+            let block = self.blocks.get_mut(&self.current_block.unwrap()).unwrap();
+            if record.pc > block.end {
+                block.end = record.pc;
+            }
+        } else {
+            let block = self.blocks.entry(record.pc).or_insert(BasicBlock {
+                start: record.pc,
+                end: record.pc,
+                count: 0,
+            });
+            block.count += 1;
+            self.current_block = Some(record.pc);
+        }
+        self.last_pc = record.pc;
+
+        // a compact branch ends its block, having no delay slot
+        if self.in_delay_slot || record.signal.is_some() || inst.is_compact() {
+            self.current_block = None;
+        }
+        // a branch likely not taken skips its delay slot
+        self.in_delay_slot = (class == InstructionClass::Branch || class == InstructionClass::Jump) &&
+            !inst.is_compact() && cpu.pc == record.pc.wrapping_add(4);
+    }
+}
+
+fn describe(cpu: &Cpu, pc: u32) -> String {
+    cpu.symbols.describe(pc).unwrap_or_default()
+}
@@ -0,0 +1,445 @@
+//! On-disk snapshots of the complete machine state.
+//!
+//! All values are little-endian. After the magic and the version come the
+//! registers, hi/lo, pc/npc, the instruction count, the ISA (the release
+//! number as a byte), whether pc is in a delay slot, whether it is in a
+//! forbidden slot and the bare-metal mode (a byte each), the COP0 registers,
+//! the TLB entries (their number first, 0 without MMU), the line linked by
+//! `ll`, the breakpoints, the loaded regions, the input events given so far
+//! and the state of the random generator, the state of the devices and the
+//! memory. An optional value is a byte telling whether it is present,
+//! followed by the value when it is, and a string is its length followed by
+//! its UTF-8 bytes. Blocks full of zeroes are skipped and the others are
+//! split in 32 chunks, only the non-zero chunks being written after a
+//! bitmask.
+//!
+//! The devices are not created by a restore: the snapshot gives the state of
+//! the ones attached to the bus it is restored with, which must be the same.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use cop0::{self, Cop0};
+use cpu::Cpu;
+use device::Bus;
+use input::{Event, Input};
+use instruction::Isa;
+use loader::Region;
+use memory;
+use mmu::{Tlb, TlbEntry};
+
+const MAGIC: &[u8; 8] = b"MIPSSNAP";
+const VERSION: u32 = 3;
+const CHUNKS_PER_BLOCK: usize = 32;
+
+const EVENT_STDIN: u8 = 0;
+const EVENT_TIME: u8 = 1;
+const EVENT_RANDOM: u8 = 2;
+const EVENT_POLL: u8 = 3;
+
+pub fn save_to_path<P: AsRef<Path>>(cpu: &Cpu, path: P) -> Result<(), String> {
+    let file = File::create(path).map_err(|err| err.to_string())?;
+    let mut writer = BufWriter::new(file);
+    save(cpu, &mut writer)
+        .and_then(|_| writer.flush())
+        .map_err(|err| err.to_string())
+}
+
+/// Restores a snapshot on a cpu whose devices are the ones of `bus`.
+pub fn restore_from_path<P: AsRef<Path>>(path: P, bus: Bus) -> Result<Cpu, String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    restore(&mut BufReader::new(file), bus)
+}
+
+pub fn save<W: Write>(cpu: &Cpu, writer: &mut W) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    write_u32(writer, VERSION)?;
+
+    for i in 1..32 {
+        write_u32(writer, cpu.get_register(i))?;
+    }
+    write_u32(writer, cpu.hi)?;
+    write_u32(writer, cpu.lo)?;
+    write_u32(writer, cpu.pc)?;
+    write_u32(writer, cpu.npc)?;
+    write_u64(writer, cpu.instruction_count)?;
+    let release = match cpu.isa {
+        Isa::R2 => 2,
+        Isa::R6 => 6,
+    };
+    let (delay_slot, forbidden_slot) = cpu.branch_slots();
+    writer.write_all(&[release, delay_slot as u8, forbidden_slot as u8, cpu.bare_metal as u8])?;
+    for &word in &cpu.cop0.words() {
+        write_u32(writer, word)?;
+    }
+    let entries = cpu.tlb.as_ref().map_or(&[][..], |tlb| &tlb.entries[..]);
+    write_u32(writer, entries.len() as u32)?;
+    for entry in entries {
+        write_u32(writer, entry.page_mask)?;
+        write_u32(writer, entry.entry_hi)?;
+        write_u32(writer, entry.entry_lo0)?;
+        write_u32(writer, entry.entry_lo1)?;
+    }
+    write_option(writer, cpu.link)?;
+
+    let mut breakpoints: Vec<u32> = cpu.breakpoints
+        .iter()
+        .cloned()
+        .chain(cpu.skipped_breakpoint())
+        .collect();
+    breakpoints.sort();
+    write_u32(writer, breakpoints.len() as u32)?;
+    for bp in breakpoints {
+        write_u32(writer, bp)?;
+    }
+    write_option(writer, cpu.skipped_breakpoint())?;
+
+    write_u32(writer, cpu.regions.len() as u32)?;
+    for region in &cpu.regions {
+        write_string(writer, &region.name)?;
+        write_u32(writer, region.start)?;
+        write_u32(writer, region.last)?;
+    }
+
+    let events = cpu.input.events();
+    write_u64(writer, cpu.input.rng_state())?;
+    write_u32(writer, events.len() as u32)?;
+    for (count, event) in events {
+        write_u64(writer, count)?;
+        match event {
+            Event::Stdin(bytes) => {
+                writer.write_all(&[EVENT_STDIN])?;
+                write_u32(writer, bytes.len() as u32)?;
+                writer.write_all(&bytes)?;
+            },
+            Event::Time(time) => {
+                writer.write_all(&[EVENT_TIME])?;
+                write_u64(writer, time)?;
+            },
+            Event::Random(value) => {
+                writer.write_all(&[EVENT_RANDOM])?;
+                write_u32(writer, value)?;
+            },
+            Event::Poll(byte) => writer.write_all(&[EVENT_POLL, byte])?,
+        }
+    }
+
+    let mappings: Vec<_> = cpu.memory.bus.iter().collect();
+    write_u32(writer, mappings.len() as u32)?;
+    for mapping in mappings {
+        let device = mapping.device.borrow();
+        let words = device.words();
+        write_u32(writer, mapping.base)?;
+        write_string(writer, device.name())?;
+        write_u32(writer, words.len() as u32)?;
+        for word in words {
+            write_u32(writer, word)?;
+        }
+    }
+
+    let chunk_size = memory::BLOCK_SIZE / CHUNKS_PER_BLOCK;
+    let blocks: Vec<_> = cpu.memory
+        .blocks()
+        .filter(|&(_, data)| data.iter().any(|&byte| byte != 0))
+        .collect();
+    write_u32(writer, memory::BLOCK_SIZE as u32)?;
+    write_u32(writer, blocks.len() as u32)?;
+    for (addr, data) in blocks {
+        let mut mask = 0u32;
+        for (i, chunk) in data.chunks(chunk_size).enumerate() {
+            if chunk.iter().any(|&byte| byte != 0) {
+                mask |= 1 << i;
+            }
+        }
+
+        write_u32(writer, addr)?;
+        write_u32(writer, mask)?;
+        for (i, chunk) in data.chunks(chunk_size).enumerate() {
+            if mask & (1 << i) != 0 {
+                writer.write_all(chunk)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn restore<R: Read>(reader: &mut R, bus: Bus) -> Result<Cpu, String> {
+    restore_inner(reader, bus).map_err(|err| err.to_string())
+}
+
+fn restore_inner<R: Read>(reader: &mut R, bus: Bus) -> io::Result<Cpu> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("Not a snapshot file.".to_string()));
+    }
+    let version = read_u32(reader)?;
+    if version != VERSION {
+        return Err(invalid_data(format!("Unsupported snapshot version {}.", version)));
+    }
+
+    let mut cpu = Cpu::new();
+    for i in 1..32 {
+        let value = read_u32(reader)?;
+        cpu.set_register(i, value);
+    }
+    cpu.hi = read_u32(reader)?;
+    cpu.lo = read_u32(reader)?;
+    cpu.pc = read_u32(reader)?;
+    cpu.npc = read_u32(reader)?;
+    cpu.instruction_count = read_u64(reader)?;
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    cpu.isa = match bytes[0] {
+        2 => Isa::R2,
+        6 => Isa::R6,
+        release => return Err(invalid_data(format!("Unsupported ISA release {}.", release))),
+    };
+    cpu.set_branch_slots(bytes[1] != 0, bytes[2] != 0);
+    cpu.bare_metal = bytes[3] != 0;
+    let mut cop0_words = [0; cop0::WORDS];
+    for word in cop0_words.iter_mut() {
+        *word = read_u32(reader)?;
+    }
+    cpu.cop0 = Cop0::from_words(cop0_words);
+    let tlb_size = read_u32(reader)?;
+    if tlb_size != cpu.cop0.tlb_size {
+        return Err(invalid_data(format!("Invalid TLB size {}.", tlb_size)));
+    }
+    if tlb_size > 0 {
+        let mut tlb = Tlb::new(tlb_size as usize);
+        for entry in &mut tlb.entries {
+            *entry = TlbEntry {
+                page_mask: read_u32(reader)?,
+                entry_hi: read_u32(reader)?,
+                entry_lo0: read_u32(reader)?,
+                entry_lo1: read_u32(reader)?,
+            };
+        }
+        cpu.tlb = Some(tlb);
+    }
+    cpu.link = read_option(reader)?;
+
+    let bp_count = read_u32(reader)?;
+    for _ in 0..bp_count {
+        let bp = read_u32(reader)?;
+        cpu.breakpoints.insert(bp);
+    }
+    if let Some(bp) = read_option(reader)? {
+        cpu.skip_breakpoint(bp);
+    }
+
+    let region_count = read_u32(reader)?;
+    for _ in 0..region_count {
+        cpu.regions.push(Region {
+            name: read_string(reader)?,
+            start: read_u32(reader)?,
+            last: read_u32(reader)?,
+        });
+    }
+
+    let rng_state = read_u64(reader)?;
+    let event_count = read_u32(reader)?;
+    let mut events = Vec::new();
+    for _ in 0..event_count {
+        let count = read_u64(reader)?;
+        let mut kind = [0];
+        reader.read_exact(&mut kind)?;
+        let event = match kind[0] {
+            EVENT_STDIN => Event::Stdin(read_bytes(reader)?),
+            EVENT_TIME => Event::Time(read_u64(reader)?),
+            EVENT_RANDOM => Event::Random(read_u32(reader)?),
+            EVENT_POLL => {
+                let mut byte = [0];
+                reader.read_exact(&mut byte)?;
+                Event::Poll(byte[0])
+            },
+            kind => return Err(invalid_data(format!("Invalid input event kind {}.", kind))),
+        };
+        events.push((count, event));
+    }
+    cpu.input = Input::from_events(events, rng_state);
+
+    // the devices of the snapshot must be the ones of the bus, in the same order
+    let device_count = read_u32(reader)? as usize;
+    let mut states = Vec::new();
+    for _ in 0..device_count {
+        let base = read_u32(reader)?;
+        let name = read_string(reader)?;
+        let word_count = read_u32(reader)?;
+        let words = (0..word_count).map(|_| read_u32(reader)).collect::<io::Result<Vec<u32>>>()?;
+        states.push((base, name, words));
+    }
+    let mappings: Vec<_> = bus.iter().collect();
+    for (i, &(base, ref name, _)) in states.iter().enumerate() {
+        let attached = match mappings.get(i) {
+            Some(mapping) => mapping.base == base && mapping.device.borrow().name() == name,
+            None => false,
+        };
+        if !attached {
+            return Err(invalid_data(format!("The snapshot has a {} at {:#010x}, it must be attached.", name, base)));
+        }
+    }
+    if let Some(mapping) = mappings.get(states.len()) {
+        return Err(invalid_data(format!("The {} at {:#010x} is not in the snapshot.",
+                                        mapping.device.borrow().name(), mapping.base)));
+    }
+
+    let block_size = read_u32(reader)? as usize;
+    if block_size == 0 || !block_size.is_multiple_of(CHUNKS_PER_BLOCK) {
+        return Err(invalid_data(format!("Invalid block size {}.", block_size)));
+    }
+    let chunk_size = block_size / CHUNKS_PER_BLOCK;
+    let mut chunk = vec![0; chunk_size];
+
+    let block_count = read_u32(reader)?;
+    for _ in 0..block_count {
+        let addr = read_u32(reader)?;
+        let mask = read_u32(reader)?;
+        for i in 0..CHUNKS_PER_BLOCK {
+            if mask & (1 << i) == 0 {
+                continue;
+            }
+            reader.read_exact(&mut chunk)?;
+            let chunk_addr = addr.wrapping_add((i * chunk_size) as u32);
+            for (j, &byte) in chunk.iter().enumerate() {
+                cpu.memory.set_byte(chunk_addr.wrapping_add(j as u32), byte);
+            }
+        }
+    }
+
+    for (mapping, (_, _, words)) in bus.iter().zip(states) {
+        mapping.device.borrow_mut().set_words(&words).map_err(invalid_data)?;
+    }
+    cpu.memory.bus = bus;
+    Ok(cpu)
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_u64<W: Write>(writer: &mut W, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_option<W: Write>(writer: &mut W, value: Option<u32>) -> io::Result<()> {
+    match value {
+        Some(value) => {
+            writer.write_all(&[1])?;
+            write_u32(writer, value)
+        },
+        None => writer.write_all(&[0]),
+    }
+}
+
+fn write_string<W: Write>(writer: &mut W, value: &str) -> io::Result<()> {
+    write_u32(writer, value.len() as u32)?;
+    writer.write_all(value.as_bytes())
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_option<R: Read>(reader: &mut R) -> io::Result<Option<u32>> {
+    let mut present = [0];
+    reader.read_exact(&mut present)?;
+    if present[0] != 0 {
+        read_u32(reader).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_bytes<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let len = read_u32(reader)?;
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len as usize {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated snapshot."));
+    }
+    Ok(bytes)
+}
+
+fn read_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    String::from_utf8(read_bytes(reader)?).map_err(|_| invalid_data("Invalid string.".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use device::Device;
+    use device::timer::Timer;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn timer_bus(timer: Timer) -> Bus {
+        let mut bus = Bus::new();
+        bus.attach(0xffff_0100, Rc::new(RefCell::new(timer)), None).unwrap();
+        bus
+    }
+
+    #[test]
+    fn restore_gives_the_saved_state() {
+        let mut timer = Timer::new();
+        timer.write(4, 4, 1000);
+        timer.write(0, 4, 1);
+        timer.tick(10);
+
+        let mut cpu = Cpu::new();
+        cpu.set_register(8, 0xdead_beef);
+        cpu.pc = 0x8000_0180;
+        cpu.npc = 0x8000_0184;
+        cpu.bare_metal = true;
+        cpu.enable_mmu(4);
+        cpu.tlb.as_mut().unwrap().write(2, TlbEntry { page_mask: 0, entry_hi: 0x0040_0000, entry_lo0: 0x17, entry_lo1: 0x57 });
+        cpu.regions.push(Region { name: ".text".to_string(), start: 0x0040_0000, last: 0x0040_00ff });
+        cpu.input = Input::from_events(vec![(3, Event::Stdin(b"abc\n".to_vec())), (5, Event::Poll(b'x')),
+                                            (8, Event::Time(1234)), (9, Event::Random(42))], 77);
+        cpu.memory.set_word(0x0040_0000, 0x2402_000a);
+        cpu.memory.bus = timer_bus(timer);
+
+        let mut bytes = Vec::new();
+        save(&cpu, &mut bytes).unwrap();
+        let restored = restore(&mut &bytes[..], timer_bus(Timer::new())).unwrap();
+
+        assert_eq!(restored.get_register(8), 0xdead_beef);
+        assert_eq!((restored.pc, restored.npc), (0x8000_0180, 0x8000_0184));
+        assert!(restored.bare_metal);
+        assert_eq!(restored.tlb.as_ref().unwrap().read(2), cpu.tlb.as_ref().unwrap().read(2));
+        assert_eq!(restored.regions, cpu.regions);
+        assert_eq!(restored.input.events(), cpu.input.events());
+        assert_eq!(restored.input.rng_state(), 77);
+        assert_eq!(restored.memory.get_word(0x0040_0000), 0x2402_000a);
+        let device = restored.memory.bus.iter().next().unwrap().device.borrow();
+        assert_eq!(device.words(), vec![1, 1000, 990, 0]);
+    }
+
+    #[test]
+    fn restore_needs_the_saved_devices() {
+        let mut cpu = Cpu::new();
+        cpu.memory.bus = timer_bus(Timer::new());
+        let mut bytes = Vec::new();
+        save(&cpu, &mut bytes).unwrap();
+
+        assert!(restore(&mut &bytes[..], Bus::new()).is_err());
+        cpu.memory.bus = Bus::new();
+        bytes.clear();
+        save(&cpu, &mut bytes).unwrap();
+        assert!(restore(&mut &bytes[..], timer_bus(Timer::new())).is_err());
+    }
+}
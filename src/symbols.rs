@@ -0,0 +1,132 @@
+use elf;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: u32,
+    pub size: u32,
+    pub code: bool, // defined in an executable section
+}
+
+impl Symbol {
+    pub fn contains(&self, addr: u32) -> bool {
+        addr >= self.addr && (addr - self.addr) < self.size.max(1)
+    }
+}
+
+/// Code and data symbols of the loaded program, sorted by address.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable {
+            symbols: Vec::new(),
+        }
+    }
+
+    pub fn from_elf(file: &elf::File) -> SymbolTable {
+        let mut table = SymbolTable::new();
+
+        for section in &file.sections {
+            let symbols = match file.get_symbols(section) {
+                Ok(symbols) => symbols,
+                Err(_) => continue,
+            };
+
+            for symbol in symbols {
+                let is_named_location = matches!(
+                    symbol.symtype,
+                    elf::types::STT_NOTYPE | elf::types::STT_OBJECT | elf::types::STT_FUNC
+                );
+                if is_named_location && symbol.shndx != 0 && !symbol.name.is_empty() {
+                    let code = file.sections
+                        .get(symbol.shndx as usize)
+                        .is_some_and(|section| section.shdr.flags.0 & elf::types::SHF_EXECINSTR.0 != 0);
+                    table.insert(Symbol {
+                        name: symbol.name,
+                        addr: symbol.value as u32,
+                        size: symbol.size as u32,
+                        code,
+                    });
+                }
+            }
+        }
+        table
+    }
+
+    pub fn insert(&mut self, symbol: Symbol) {
+        let index = match self.symbols.binary_search_by_key(&symbol.addr, |s| s.addr) {
+            Ok(index) | Err(index) => index,
+        };
+        self.symbols.insert(index, symbol);
+    }
+
+    /// Adds the symbols of another program.
+    pub fn extend(&mut self, other: SymbolTable) {
+        for symbol in other.symbols {
+            self.insert(symbol);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn iter(&self) -> ::std::slice::Iter<'_, Symbol> {
+        self.symbols.iter()
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|s| s.name == name)
+    }
+
+    /// Symbol containing `addr`. A symbol without size spans until the next one.
+    pub fn lookup(&self, addr: u32) -> Option<&Symbol> {
+        let index = match self.symbols.binary_search_by_key(&addr, |s| s.addr) {
+            Ok(mut index) => {
+                // prefer the last symbol at that address
+                while index + 1 < self.symbols.len() && self.symbols[index + 1].addr == addr {
+                    index += 1;
+                }
+                index
+            },
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+
+        let symbol = &self.symbols[index];
+        if symbol.size == 0 || symbol.contains(addr) {
+            Some(symbol)
+        } else {
+            None
+        }
+    }
+
+    /// Address range of the code symbol at `index`. A symbol without size
+    /// ends at the next symbol, the last one at `default_end`.
+    pub fn extent(&self, index: usize, default_end: u32) -> (u32, u32) {
+        let symbol = &self.symbols[index];
+        if symbol.size != 0 {
+            return (symbol.addr, symbol.addr.wrapping_add(symbol.size));
+        }
+        let end = self.symbols[index + 1..]
+            .iter()
+            .find(|next| next.addr > symbol.addr && next.code == symbol.code)
+            .map_or(default_end, |next| next.addr);
+        (symbol.addr, end.max(symbol.addr))
+    }
+
+    /// `symbol+offset` representation of `addr`, if it has a symbol.
+    pub fn describe(&self, addr: u32) -> Option<String> {
+        self.lookup(addr).map(|symbol| {
+            if symbol.addr == addr {
+                symbol.name.clone()
+            } else {
+                format!("{}+{:#x}", symbol.name, addr - symbol.addr)
+            }
+        })
+    }
+}
@@ -1,20 +1,34 @@
-use std::io::{self, Read, Write};
+use std::io::{self, Write};
 
 use regex::Regex;
 
 use cpu::{Cpu, Signal, PCOperation};
+use memory::AccessKind;
 use utils;
 
 pub fn call_syscall(cpu: &mut Cpu) -> Result<PCOperation, Signal> {
     let syscall_value = cpu.get_register(2);
-    match syscall_value {
-        1 => print_int(cpu),
-        4 => print_string(cpu),
+    let res = match syscall_value {
+        1 => {
+            print_int(cpu);
+            Ok(())
+        },
+        4 => {
+            print_string(cpu)?;
+            Ok(())
+        },
         5 => read_int(cpu),
-        8 => read_string(cpu),
+        8 => {
+            read_string(cpu)?;
+            Ok(())
+        },
         10 => return Err(Signal::Exit),
+        30 => time(cpu),
+        41 => random_int(cpu),
+        42 => random_int_range(cpu),
         _ => panic!("Wrong syscall number"),
-    }
+    };
+    res.map_err(Signal::Trap)?;
     Ok(PCOperation::Offset(4))
 }
 
@@ -23,45 +37,125 @@ fn print_int(cpu: &mut Cpu) {
     print!("{}", i);
 }
 
-fn print_string(cpu: &mut Cpu) {
+fn print_string(cpu: &mut Cpu) -> Result<(), Signal> {
     let mut addr = cpu.get_register(4);
     let mut buff = String::new();
-    while cpu.memory.get_byte(addr) != 0 {
-        buff.push(cpu.memory.get_byte(addr) as char);
+    loop {
+        let byte = cpu.load_byte(addr)?;
+        if byte == 0 {
+            break;
+        }
+        buff.push(byte as char);
         addr += 1;
     }
     print!("{}", buff);
     io::stdout().flush().unwrap();
+    Ok(())
 }
 
-fn read_int(cpu: &mut Cpu) {
+fn read_int(cpu: &mut Cpu) -> Result<(), String> {
     lazy_static! {
         static ref INT_REGEX: Regex = Regex::new(r"^\s*([+-]?[0-9]+).*").unwrap();
     }
 
-    let mut line = String::new();
-    io::stdin().read_line(&mut line).unwrap();
+    let count = cpu.instruction_count;
+    let line = String::from_utf8_lossy(&cpu.input.read_line(count)?).into_owned();
 
     let capt = INT_REGEX.captures(&line).unwrap();
     let result: i32 = (&capt[0]).parse().unwrap();
 
     cpu.set_register(2, utils::i2u(result));
+    Ok(())
 }
 
-fn read_string(cpu: &mut Cpu) {
+fn read_string(cpu: &mut Cpu) -> Result<(), Signal> {
     // really not sure about this implementation
     let mut addr = cpu.get_register(4);
     let len = cpu.get_register(5) as usize;
-    let stdin = io::stdin();
-
-    for c in stdin
-        .lock()
-        .bytes()
-        .take(len - 1)
-        .map(Result::unwrap)
-        .take_while(|c| *c != b'\n') {
-        cpu.memory.set_byte(addr, c);
+    let count = cpu.instruction_count;
+
+    // faulting once the line is read would lose it, the syscall being retried
+    for i in 0..len.max(1) as u32 {
+        cpu.translate(addr.wrapping_add(i), AccessKind::Write)?;
+    }
+    let line = cpu.input
+        .read_until(count, len.saturating_sub(1), b'\n')
+        .map_err(Signal::Trap)?;
+    for c in line.into_iter().take_while(|c| *c != b'\n') {
+        cpu.store_byte(addr, c)?;
         addr += 1;
     }
-    cpu.memory.set_byte(addr, 0);
+    cpu.store_byte(addr, 0)
+}
+
+fn time(cpu: &mut Cpu) -> Result<(), String> {
+    let count = cpu.instruction_count;
+    let time = cpu.input.time(count)?;
+
+    cpu.set_register(4, time as u32);
+    cpu.set_register(5, (time >> 32) as u32);
+    Ok(())
+}
+
+fn random_int(cpu: &mut Cpu) -> Result<(), String> {
+    let count = cpu.instruction_count;
+    let value = cpu.input.random(count)?;
+
+    cpu.set_register(4, value);
+    Ok(())
+}
+
+fn random_int_range(cpu: &mut Cpu) -> Result<(), String> {
+    let bound = cpu.get_register(5);
+    if bound == 0 {
+        return Err("random range with an upper bound of 0".to_string());
+    }
+
+    let count = cpu.instruction_count;
+    let value = cpu.input.random(count)?;
+
+    cpu.set_register(4, value % bound);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+    use input::Input;
+
+    // time, random int and random int range at instructions 3, 5 and 7
+    fn time_and_random(cpu: &mut Cpu) -> Vec<u32> {
+        let mut results = Vec::new();
+        for &(count, number) in &[(3, 30), (5, 41), (7, 42)] {
+            cpu.instruction_count = count;
+            cpu.set_register(2, number);
+            cpu.set_register(5, 10);
+            call_syscall(cpu).unwrap();
+            results.push(cpu.get_register(4));
+            results.push(cpu.get_register(5));
+        }
+        results
+    }
+
+    #[test]
+    fn time_and_random_are_replayed() {
+        let path = env::temp_dir().join(format!("mips_emu_syscall_{}.log", process::id()));
+
+        let mut cpu = Cpu::new();
+        cpu.input = Input::record(&path).unwrap();
+        let recorded = time_and_random(&mut cpu);
+        assert!(recorded[4] < 10);
+
+        let mut cpu = Cpu::new();
+        cpu.input = Input::replay(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(time_and_random(&mut cpu), recorded);
+
+        cpu.instruction_count = 4;
+        cpu.set_register(2, 41);
+        assert!(call_syscall(&mut cpu).is_err());
+    }
 }
@@ -0,0 +1,190 @@
+//! Compact trace encoding.
+//!
+//! After the magic and the version, each record is: the count delta with
+//! the previous record (LEB128), pc and word (u32), the register changes
+//! (LEB128 count, then u8 register, u32 old, u32 new), the memory accesses
+//! (LEB128 count, then u8 `write << 7 | size`, u32 address, u32 value) and
+//! a signal tag followed by its payload. Integers are little-endian.
+//!
+//! An exception is its code (u8), flags (u8, bit 0 when the bad address
+//! follows, bit 1 for a TLB refill) and the optional bad address (u32).
+
+use std::io::{self, Read, Write};
+
+use cop0::Exception;
+use cpu::Signal;
+use memory::{AccessKind, MemoryAccess};
+use super::{Record, RegisterChange};
+
+pub const MAGIC: &[u8; 8] = b"MIPSTRCE";
+const VERSION: u32 = 2;
+
+const SIGNAL_NONE: u8 = 0;
+const SIGNAL_TRAP: u8 = 1;
+const SIGNAL_BREAKPOINT: u8 = 2;
+const SIGNAL_START_OF_HISTORY: u8 = 3;
+const SIGNAL_EXIT: u8 = 4;
+const SIGNAL_EXCEPTION: u8 = 5;
+
+pub fn write_header<W: Write>(writer: &mut W) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())
+}
+
+pub fn read_header<R: Read>(reader: &mut R) -> io::Result<()> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("Not a binary trace.".to_string()));
+    }
+    let version = read_u32(reader)?;
+    if version != VERSION {
+        return Err(invalid_data(format!("Unsupported trace version {}.", version)));
+    }
+    Ok(())
+}
+
+pub fn write_record<W: Write>(writer: &mut W, record: &Record, last_count: &mut u64) -> io::Result<()> {
+    write_varint(writer, record.count.wrapping_sub(*last_count))?;
+    *last_count = record.count;
+    writer.write_all(&record.pc.to_le_bytes())?;
+    writer.write_all(&record.word.to_le_bytes())?;
+
+    write_varint(writer, record.registers.len() as u64)?;
+    for change in &record.registers {
+        writer.write_all(&[change.reg as u8])?;
+        writer.write_all(&change.old.to_le_bytes())?;
+        writer.write_all(&change.new.to_le_bytes())?;
+    }
+
+    write_varint(writer, record.memory.len() as u64)?;
+    for access in &record.memory {
+        let write_flag = if access.kind == AccessKind::Write { 0x80 } else { 0 };
+        writer.write_all(&[write_flag | access.size])?;
+        writer.write_all(&access.addr.to_le_bytes())?;
+        writer.write_all(&access.value.to_le_bytes())?;
+    }
+
+    match record.signal {
+        None => writer.write_all(&[SIGNAL_NONE]),
+        Some(Signal::Trap(ref reason)) => {
+            writer.write_all(&[SIGNAL_TRAP])?;
+            write_varint(writer, reason.len() as u64)?;
+            writer.write_all(reason.as_bytes())
+        },
+        Some(Signal::Breakpoint(pc)) => {
+            writer.write_all(&[SIGNAL_BREAKPOINT])?;
+            writer.write_all(&pc.to_le_bytes())
+        },
+        Some(Signal::StartOfHistory) => writer.write_all(&[SIGNAL_START_OF_HISTORY]),
+        Some(Signal::Exit) => writer.write_all(&[SIGNAL_EXIT]),
+        Some(Signal::Exception(exception)) => {
+            let flags = exception.bad_vaddr.is_some() as u8 | (exception.refill as u8) << 1;
+            writer.write_all(&[SIGNAL_EXCEPTION, exception.code as u8, flags])?;
+            match exception.bad_vaddr {
+                Some(vaddr) => writer.write_all(&vaddr.to_le_bytes()),
+                None => Ok(()),
+            }
+        },
+    }
+}
+
+/// Returns `None` at the end of the trace.
+pub fn read_record<R: Read>(reader: &mut R, last_count: &mut u64) -> io::Result<Option<Record>> {
+    let mut first = [0];
+    if reader.read(&mut first)? == 0 {
+        return Ok(None);
+    }
+    let delta = read_varint_from(reader, first[0])?;
+    let count = last_count.wrapping_add(delta);
+    *last_count = count;
+
+    let pc = read_u32(reader)?;
+    let word = read_u32(reader)?;
+
+    let mut registers = Vec::new();
+    for _ in 0..read_varint(reader)? {
+        let reg = read_u8(reader)? as u32;
+        let old = read_u32(reader)?;
+        let new = read_u32(reader)?;
+        registers.push(RegisterChange { reg, old, new });
+    }
+
+    let mut memory = Vec::new();
+    for _ in 0..read_varint(reader)? {
+        let flags = read_u8(reader)?;
+        let kind = if flags & 0x80 != 0 { AccessKind::Write } else { AccessKind::Read };
+        let addr = read_u32(reader)?;
+        let value = read_u32(reader)?;
+        memory.push(MemoryAccess { kind, addr, size: flags & 0x7f, value });
+    }
+
+    let signal = match read_u8(reader)? {
+        SIGNAL_NONE => None,
+        SIGNAL_TRAP => {
+            let len = read_varint(reader)? as usize;
+            let mut reason = vec![0; len];
+            reader.read_exact(&mut reason)?;
+            Some(Signal::Trap(String::from_utf8_lossy(&reason).into_owned()))
+        },
+        SIGNAL_BREAKPOINT => Some(Signal::Breakpoint(read_u32(reader)?)),
+        SIGNAL_START_OF_HISTORY => Some(Signal::StartOfHistory),
+        SIGNAL_EXIT => Some(Signal::Exit),
+        SIGNAL_EXCEPTION => {
+            let code = read_u8(reader)? as u32;
+            let flags = read_u8(reader)?;
+            let bad_vaddr = if flags & 1 != 0 { Some(read_u32(reader)?) } else { None };
+            Some(Signal::Exception(Exception { code, bad_vaddr, refill: flags & 2 != 0 }))
+        },
+        tag => return Err(invalid_data(format!("Unknown signal tag {}.", tag))),
+    };
+
+    Ok(Some(Record { count, pc, word, registers, memory, signal }))
+}
+
+fn write_varint<W: Write>(writer: &mut W, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let first = read_u8(reader)?;
+    read_varint_from(reader, first)
+}
+
+fn read_varint_from<R: Read>(reader: &mut R, first: u8) -> io::Result<u64> {
+    let mut value = (first & 0x7f) as u64;
+    let mut byte = first;
+    let mut shift = 7;
+    while byte & 0x80 != 0 {
+        if shift >= 64 {
+            return Err(invalid_data("Varint too long.".to_string()));
+        }
+        byte = read_u8(reader)?;
+        value |= ((byte & 0x7f) as u64) << shift;
+        shift += 7;
+    }
+    Ok(value)
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut bytes = [0];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,309 @@
+//! JSON Lines trace encoding, one object per instruction:
+//!
+//! `{"count":0,"pc":4194304,"word":604110858,"asm":"addiu $2, $0, 10",
+//! "regs":[{"reg":2,"old":0,"new":10}],"mem":[],"signal":null}`
+//!
+//! Memory accesses are `{"op":"read"|"write","addr":..,"size":..,"value":..}`
+//! and signals are `{"kind":"trap","reason":".."}`, `{"kind":"breakpoint",
+//! "pc":..}`, `{"kind":"start_of_history"}`, `{"kind":"exit"}` or
+//! `{"kind":"exception","code":..,"bad_vaddr":..|null,"refill":0|1}`.
+
+use std::io::{self, Write};
+
+use cop0::Exception;
+use cpu::Signal;
+use instruction::Isa;
+use memory::{AccessKind, MemoryAccess};
+use super::{Record, RegisterChange};
+
+pub fn write_record<W: Write>(writer: &mut W, record: &Record, isa: Isa) -> io::Result<()> {
+    write!(writer, "{{\"count\":{},\"pc\":{},\"word\":{},\"asm\":{},\"regs\":[",
+           record.count, record.pc, record.word, quote(&record.instruction(isa).to_string()))?;
+
+    for (i, change) in record.registers.iter().enumerate() {
+        if i > 0 {
+            write!(writer, ",")?;
+        }
+        write!(writer, "{{\"reg\":{},\"old\":{},\"new\":{}}}", change.reg, change.old, change.new)?;
+    }
+    write!(writer, "],\"mem\":[")?;
+
+    for (i, access) in record.memory.iter().enumerate() {
+        if i > 0 {
+            write!(writer, ",")?;
+        }
+        let op = match access.kind {
+            AccessKind::Read => "read",
+            AccessKind::Write => "write",
+        };
+        write!(writer, "{{\"op\":\"{}\",\"addr\":{},\"size\":{},\"value\":{}}}",
+               op, access.addr, access.size, access.value)?;
+    }
+    write!(writer, "],\"signal\":")?;
+
+    match record.signal {
+        None => write!(writer, "null")?,
+        Some(Signal::Trap(ref reason)) => write!(writer, "{{\"kind\":\"trap\",\"reason\":{}}}", quote(reason))?,
+        Some(Signal::Breakpoint(pc)) => write!(writer, "{{\"kind\":\"breakpoint\",\"pc\":{}}}", pc)?,
+        Some(Signal::StartOfHistory) => write!(writer, "{{\"kind\":\"start_of_history\"}}")?,
+        Some(Signal::Exit) => write!(writer, "{{\"kind\":\"exit\"}}")?,
+        Some(Signal::Exception(exception)) => {
+            write!(writer, "{{\"kind\":\"exception\",\"code\":{},\"bad_vaddr\":", exception.code)?;
+            match exception.bad_vaddr {
+                Some(vaddr) => write!(writer, "{}", vaddr)?,
+                None => write!(writer, "null")?,
+            }
+            write!(writer, ",\"refill\":{}}}", exception.refill as u8)?;
+        },
+    }
+    writeln!(writer, "}}")
+}
+
+pub fn parse_record(line: &str) -> Result<Record, String> {
+    let value = Parser { input: line.as_bytes(), pos: 0 }.parse()?;
+
+    let mut registers = Vec::new();
+    for change in value.get("regs")?.as_array()? {
+        registers.push(RegisterChange {
+            reg: change.get("reg")?.as_u32()?,
+            old: change.get("old")?.as_u32()?,
+            new: change.get("new")?.as_u32()?,
+        });
+    }
+
+    let mut memory = Vec::new();
+    for access in value.get("mem")?.as_array()? {
+        let kind = match access.get("op")?.as_str()? {
+            "read" => AccessKind::Read,
+            "write" => AccessKind::Write,
+            op => return Err(format!("unknown memory operation {}", op)),
+        };
+        memory.push(MemoryAccess {
+            kind,
+            addr: access.get("addr")?.as_u32()?,
+            size: access.get("size")?.as_u32()? as u8,
+            value: access.get("value")?.as_u32()?,
+        });
+    }
+
+    let signal = match *value.get("signal")? {
+        Value::Null => None,
+        ref signal => Some(match signal.get("kind")?.as_str()? {
+            "trap" => Signal::Trap(signal.get("reason")?.as_str()?.to_string()),
+            "breakpoint" => Signal::Breakpoint(signal.get("pc")?.as_u32()?),
+            "start_of_history" => Signal::StartOfHistory,
+            "exit" => Signal::Exit,
+            "exception" => Signal::Exception(Exception {
+                code: signal.get("code")?.as_u32()?,
+                bad_vaddr: match *signal.get("bad_vaddr")? {
+                    Value::Null => None,
+                    ref vaddr => Some(vaddr.as_u32()?),
+                },
+                refill: signal.get("refill")?.as_u32()? != 0,
+            }),
+            kind => return Err(format!("unknown signal {}", kind)),
+        }),
+    };
+
+    Ok(Record {
+        count: value.get("count")?.as_u64()?,
+        pc: value.get("pc")?.as_u32()?,
+        word: value.get("word")?.as_u32()?,
+        registers,
+        memory,
+        signal,
+    })
+}
+
+fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The subset of JSON written by the tracer: no booleans, floats nor negative numbers.
+#[derive(Debug)]
+enum Value {
+    Null,
+    Number(u64),
+    Str(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    fn get(&self, key: &str) -> Result<&Value, String> {
+        match *self {
+            Value::Object(ref fields) => fields
+                .iter()
+                .find(|field| field.0 == key)
+                .map(|field| &field.1)
+                .ok_or_else(|| format!("missing field {}", key)),
+            _ => Err(format!("expected an object with a field {}", key)),
+        }
+    }
+
+    fn as_u64(&self) -> Result<u64, String> {
+        match *self {
+            Value::Number(n) => Ok(n),
+            _ => Err("expected a number".to_string()),
+        }
+    }
+
+    fn as_u32(&self) -> Result<u32, String> {
+        let n = self.as_u64()?;
+        if n > u32::MAX as u64 {
+            return Err(format!("{} does not fit in 32 bits", n));
+        }
+        Ok(n as u32)
+    }
+
+    fn as_str(&self) -> Result<&str, String> {
+        match *self {
+            Value::Str(ref s) => Ok(s),
+            _ => Err("expected a string".to_string()),
+        }
+    }
+
+    fn as_array(&self) -> Result<&[Value], String> {
+        match *self {
+            Value::Array(ref values) => Ok(values),
+            _ => Err("expected an array".to_string()),
+        }
+    }
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn parse(mut self) -> Result<Value, String> {
+        let value = self.value()?;
+        if self.peek().is_some() {
+            return Err(format!("trailing characters at {}", self.pos));
+        }
+        Ok(value)
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        while self.input.get(self.pos).is_some_and(|c| c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+        self.input.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), String> {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!("expected `{}` at {}", c as char, self.pos))
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Value) -> Result<Value, String> {
+        if self.input[self.pos..].starts_with(keyword.as_bytes()) {
+            self.pos += keyword.len();
+            Ok(value)
+        } else {
+            Err(format!("unexpected character at {}", self.pos))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some(b'n') => self.keyword("null", Value::Null),
+            Some(b'"') => self.string().map(Value::Str),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut values = Vec::new();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(Value::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    if self.peek() == Some(b']') {
+                        self.pos += 1;
+                        return Ok(Value::Array(values));
+                    }
+                    self.expect(b',')?;
+                }
+            },
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(Value::Object(fields));
+                }
+                loop {
+                    self.peek();
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    fields.push((key, self.value()?));
+                    if self.peek() == Some(b'}') {
+                        self.pos += 1;
+                        return Ok(Value::Object(fields));
+                    }
+                    self.expect(b',')?;
+                }
+            },
+            Some(c) if c.is_ascii_digit() => {
+                let start = self.pos;
+                while self.input.get(self.pos).is_some_and(|c| c.is_ascii_digit()) {
+                    self.pos += 1;
+                }
+                let digits = ::std::str::from_utf8(&self.input[start..self.pos]).unwrap();
+                digits.parse().map(Value::Number).map_err(|_| format!("invalid number {}", digits))
+            },
+            Some(_) => Err(format!("unexpected character at {}", self.pos)),
+            None => Err("unexpected end of input".to_string()),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            let c = *self.input.get(self.pos).ok_or("unterminated string")?;
+            self.pos += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = *self.input.get(self.pos).ok_or("unterminated string")?;
+                    self.pos += 1;
+                    match escaped {
+                        b'n' => bytes.push(b'\n'),
+                        b't' => bytes.push(b'\t'),
+                        b'r' => bytes.push(b'\r'),
+                        b'u' => {
+                            let hex = self.input.get(self.pos..self.pos + 4).ok_or("invalid escape")?;
+                            self.pos += 4;
+                            let code = ::std::str::from_utf8(hex).ok()
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .and_then(::std::char::from_u32)
+                                .ok_or("invalid escape")?;
+                            let mut buffer = [0; 4];
+                            bytes.extend_from_slice(code.encode_utf8(&mut buffer).as_bytes());
+                        },
+                        c => bytes.push(c),
+                    }
+                },
+                c => bytes.push(c),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| "invalid utf-8 in string".to_string())
+    }
+}
@@ -0,0 +1,249 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use cpu::{Cpu, Signal};
+use instruction::{Instruction, InstructionClass, Isa};
+use memory::{AccessKind, MemoryAccess};
+
+mod binary;
+mod json;
+pub mod qemu;
+
+/// Register ids used for hi and lo in traces, next to the 32 general registers.
+pub const HI: u32 = 32;
+pub const LO: u32 = 33;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterChange {
+    pub reg: u32,
+    pub old: u32,
+    pub new: u32,
+}
+
+/// Everything observable about the execution of one instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub count: u64,
+    pub pc: u32,
+    pub word: u32,
+    pub registers: Vec<RegisterChange>,
+    pub memory: Vec<MemoryAccess>,
+    pub signal: Option<Signal>,
+}
+
+impl Record {
+    pub fn instruction(&self, isa: Isa) -> Instruction {
+        Instruction::from_word(self.word, isa)
+    }
+}
+
+pub fn register_name(reg: u32) -> String {
+    match reg {
+        HI => "hi".to_string(),
+        LO => "lo".to_string(),
+        reg => format!("${}", reg),
+    }
+}
+
+/// Gets notified by `Cpu::run` after the execution of each instruction.
+pub trait Observer: fmt::Debug {
+    fn observe(&mut self, record: &Record, cpu: &Cpu);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Binary,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "json" | "jsonl" => Some(Format::Json),
+            "binary" | "bin" => Some(Format::Binary),
+            _ => None,
+        }
+    }
+}
+
+/// Selects the traced instructions. Empty criteria match everything.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub ranges: Vec<(u32, u32)>, // start, end (excluded)
+    pub symbols: Vec<String>,
+    pub classes: Vec<InstructionClass>,
+}
+
+impl Filter {
+    pub fn matches(&self, record: &Record, cpu: &Cpu) -> bool {
+        let in_location = (self.ranges.is_empty() && self.symbols.is_empty())
+            || self.ranges.iter().any(|&(start, end)| record.pc >= start && record.pc < end)
+            || cpu.symbols.lookup(record.pc).is_some_and(|symbol| self.symbols.contains(&symbol.name));
+        let in_class = self.classes.is_empty()
+            || self.classes.contains(&record.instruction(cpu.isa).class());
+
+        in_location && in_class
+    }
+}
+
+#[derive(Debug)]
+pub struct TraceWriter {
+    format: Format,
+    output: BufWriter<File>,
+    filter: Filter,
+    last_count: u64,
+    failed: bool,
+}
+
+impl TraceWriter {
+    pub fn create<P: AsRef<Path>>(path: P, format: Format, filter: Filter) -> Result<TraceWriter, String> {
+        let file = File::create(path).map_err(|err| err.to_string())?;
+        let mut output = BufWriter::new(file);
+        if format == Format::Binary {
+            binary::write_header(&mut output).map_err(|err| err.to_string())?;
+        }
+
+        Ok(TraceWriter {
+            format,
+            output,
+            filter,
+            last_count: 0,
+            failed: false,
+        })
+    }
+
+    /// Writes `record`, its instruction being decoded for `isa`.
+    pub fn write(&mut self, record: &Record, isa: Isa) -> io::Result<()> {
+        match self.format {
+            Format::Json => json::write_record(&mut self.output, record, isa),
+            Format::Binary => binary::write_record(&mut self.output, record, &mut self.last_count),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+impl Observer for TraceWriter {
+    fn observe(&mut self, record: &Record, cpu: &Cpu) {
+        if self.failed || !self.filter.matches(record, cpu) {
+            return;
+        }
+        if let Err(err) = self.write(record, cpu.isa) {
+            println!("Trace disabled: {}", err);
+            self.failed = true;
+        }
+    }
+}
+
+/// Reads a trace file, the format is detected from its header.
+pub struct TraceReader {
+    format: Format,
+    input: BufReader<File>,
+    last_count: u64,
+    line: usize,
+}
+
+impl TraceReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<TraceReader, String> {
+        let file = File::open(path).map_err(|err| err.to_string())?;
+        let mut input = BufReader::new(file);
+
+        let is_binary = input.fill_buf()
+            .map(|buf| buf.starts_with(binary::MAGIC))
+            .map_err(|err| err.to_string())?;
+        let format = if is_binary {
+            binary::read_header(&mut input).map_err(|err| err.to_string())?;
+            Format::Binary
+        } else {
+            Format::Json
+        };
+
+        Ok(TraceReader {
+            format,
+            input,
+            last_count: 0,
+            line: 0,
+        })
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+}
+
+impl Iterator for TraceReader {
+    type Item = Result<Record, String>;
+
+    fn next(&mut self) -> Option<Result<Record, String>> {
+        match self.format {
+            Format::Binary => binary::read_record(&mut self.input, &mut self.last_count)
+                .map_err(|err| err.to_string())
+                .transpose(),
+            Format::Json => loop {
+                let mut line = String::new();
+                match self.input.read_line(&mut line) {
+                    Ok(0) => return None,
+                    Ok(_) => {},
+                    Err(err) => return Some(Err(err.to_string())),
+                }
+                self.line += 1;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let line_number = self.line;
+                return Some(json::parse_record(&line)
+                    .map_err(|err| format!("line {}: {}", line_number, err)));
+            },
+        }
+    }
+}
+
+/// Describes the first difference between two records, if any.
+pub fn compare(expected: &Record, actual: &Record) -> Option<String> {
+    if expected.pc != actual.pc {
+        return Some(format!("pc {:#x} != {:#x}", expected.pc, actual.pc));
+    }
+    if expected.word != actual.word {
+        return Some(format!("instruction {:#010x} != {:#010x}", expected.word, actual.word));
+    }
+
+    let mut regs = expected.registers.iter().map(|c| (c.reg, c.new)).collect::<Vec<_>>();
+    let mut other_regs = actual.registers.iter().map(|c| (c.reg, c.new)).collect::<Vec<_>>();
+    regs.sort();
+    other_regs.sort();
+    if regs != other_regs {
+        return Some(format!("registers {} != {}", describe_registers(&regs), describe_registers(&other_regs)));
+    }
+
+    if expected.memory != actual.memory {
+        return Some(format!("memory {} != {}", describe_memory(&expected.memory), describe_memory(&actual.memory)));
+    }
+    if expected.signal != actual.signal {
+        return Some(format!("signal {:?} != {:?}", expected.signal, actual.signal));
+    }
+    None
+}
+
+fn describe_registers(regs: &[(u32, u32)]) -> String {
+    let regs: Vec<String> = regs.iter()
+        .map(|&(reg, value)| format!("{}={:#x}", register_name(reg), value))
+        .collect();
+    format!("[{}]", regs.join(", "))
+}
+
+fn describe_memory(accesses: &[MemoryAccess]) -> String {
+    let accesses: Vec<String> = accesses.iter()
+        .map(|access| {
+            let op = match access.kind {
+                AccessKind::Read => "read",
+                AccessKind::Write => "write",
+            };
+            format!("{}{} {:#x}={:#x}", op, access.size * 8, access.addr, access.value)
+        })
+        .collect();
+    format!("[{}]", accesses.join(", "))
+}
@@ -0,0 +1,109 @@
+//! Register dumps of a QEMU log (`qemu-mipsel -one-insn-per-tb -d cpu`).
+//!
+//! Each dump starts with a `pc=... HI=... LO=...` line followed by
+//! `GPRnn:` lines of name/value pairs, and describes the state before the
+//! execution of the instruction at pc. Any other line is ignored.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QemuState {
+    pub pc: u32,
+    pub hi: u32,
+    pub lo: u32,
+    pub registers: [u32; 32],
+}
+
+pub struct QemuLogReader {
+    input: BufReader<File>,
+    line: usize,
+    pending: Option<(QemuState, u32)>, // the state and a mask of the registers read
+}
+
+impl QemuLogReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<QemuLogReader, String> {
+        let file = File::open(path).map_err(|err| err.to_string())?;
+        Ok(QemuLogReader {
+            input: BufReader::new(file),
+            line: 0,
+            pending: None,
+        })
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<Option<QemuState>, String> {
+        let line = line.trim();
+
+        if line.starts_with("pc=") {
+            let mut state = QemuState { pc: 0, hi: 0, lo: 0, registers: [0; 32] };
+            for token in line.split_whitespace() {
+                let mut parts = token.splitn(2, '=');
+                let (key, value) = match (parts.next(), parts.next()) {
+                    (Some(key), Some(value)) => (key, parse_hex(value)?),
+                    _ => continue,
+                };
+                match key {
+                    "pc" => state.pc = value,
+                    "HI" => state.hi = value,
+                    "LO" => state.lo = value,
+                    _ => {},
+                }
+            }
+            let finished = self.pending.take().map(|(state, _)| state);
+            self.pending = Some((state, 0));
+            return Ok(finished);
+        }
+
+        if line.starts_with("GPR") {
+            let (state, mask) = match self.pending {
+                Some((ref mut state, ref mut mask)) => (state, mask),
+                None => return Ok(None),
+            };
+            let mut tokens = line.split_whitespace();
+            let first = tokens.next().unwrap().trim_start_matches("GPR").trim_end_matches(':');
+            let first: usize = first.parse().map_err(|_| format!("Invalid register line {}.", line))?;
+
+            let values: Vec<&str> = tokens.skip(1).step_by(2).collect();
+            for (i, value) in values.iter().enumerate() {
+                if first + i < 32 {
+                    state.registers[first + i] = parse_hex(value)?;
+                    *mask |= 1 << (first + i);
+                }
+            }
+            if *mask == 0xffffffff {
+                return Ok(self.pending.take().map(|(state, _)| state));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl Iterator for QemuLogReader {
+    type Item = Result<QemuState, String>;
+
+    fn next(&mut self) -> Option<Result<QemuState, String>> {
+        loop {
+            let mut line = String::new();
+            match self.input.read_line(&mut line) {
+                Ok(0) => return self.pending.take().map(|(state, _)| Ok(state)),
+                Ok(_) => {},
+                Err(err) => return Some(Err(err.to_string())),
+            }
+            self.line += 1;
+
+            let line_number = self.line;
+            match self.parse_line(&line) {
+                Ok(Some(state)) => return Some(Ok(state)),
+                Ok(None) => {},
+                Err(err) => return Some(Err(format!("line {}: {}", line_number, err))),
+            }
+        }
+    }
+}
+
+fn parse_hex(s: &str) -> Result<u32, String> {
+    let digits = s.trim_start_matches("0x");
+    u32::from_str_radix(digits, 16).map_err(|_| format!("Invalid value {}.", s))
+}
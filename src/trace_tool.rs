@@ -0,0 +1,307 @@
+extern crate clap;
+extern crate elf;
+extern crate lib_mips_emu;
+
+use std::cell::RefCell;
+use std::process;
+use std::rc::Rc;
+
+use clap::{Arg, App, ArgMatches, SubCommand};
+
+use lib_mips_emu::cpu::{Cpu, Signal};
+use lib_mips_emu::instruction::{self, Isa};
+use lib_mips_emu::memory::AccessKind;
+use lib_mips_emu::trace::{self, Filter, Format, Observer, Record, TraceReader, TraceWriter};
+use lib_mips_emu::trace::qemu::{QemuLogReader, QemuState};
+
+fn main() {
+    let matches = App::new("MIPS trace tool")
+        .version("1.0")
+        .author("Paul CACHEUX <paulcacheux@gmail.com>")
+        .subcommand(SubCommand::with_name("convert")
+            .about("Converts a trace to another format.")
+            .arg(Arg::with_name("INPUT")
+                 .required(true)
+                 .index(1))
+            .arg(Arg::with_name("OUTPUT")
+                 .required(true)
+                 .index(2))
+            .arg(Arg::with_name("format")
+                 .help("Format of the output, by default the other format than the input one.")
+                 .long("format")
+                 .value_name("FORMAT")
+                 .possible_values(&["json", "binary"]))
+            .arg(isa_arg()))
+        .subcommand(SubCommand::with_name("diff")
+            .about("Reports the first difference between two traces.")
+            .arg(Arg::with_name("EXPECTED")
+                 .required(true)
+                 .index(1))
+            .arg(Arg::with_name("ACTUAL")
+                 .required(true)
+                 .index(2))
+            .arg(isa_arg()))
+        .subcommand(SubCommand::with_name("check")
+            .about("Runs a program and compares each instruction against a reference trace.")
+            .arg(Arg::with_name("PROGRAM")
+                 .required(true)
+                 .index(1))
+            .arg(Arg::with_name("REFERENCE")
+                 .help("A trace recorded by mips_emu_driver, or a QEMU log with --qemu.")
+                 .required(true)
+                 .index(2))
+            .arg(Arg::with_name("qemu")
+                 .help("The reference is the log of qemu-mipsel -one-insn-per-tb -d cpu.")
+                 .long("qemu"))
+            .arg(Arg::with_name("mmu")
+                 .help("Translates the addresses with a TLB, as mips_emu_driver --mmu.")
+                 .long("mmu"))
+            .arg(Arg::with_name("tlb-entries")
+                 .help("Sets the number of TLB entries.")
+                 .long("tlb-entries")
+                 .value_name("COUNT")
+                 .possible_values(&["16", "32", "48", "64"])
+                 .default_value("32"))
+            .arg(isa_arg()))
+        .get_matches();
+
+    let res = match matches.subcommand() {
+        ("convert", Some(matches)) => convert(
+            matches.value_of("INPUT").unwrap(),
+            matches.value_of("OUTPUT").unwrap(),
+            matches.value_of("format").and_then(Format::from_name),
+            isa(matches),
+        ),
+        ("diff", Some(matches)) => diff(
+            matches.value_of("EXPECTED").unwrap(),
+            matches.value_of("ACTUAL").unwrap(),
+            isa(matches),
+        ),
+        ("check", Some(matches)) => check(
+            matches.value_of("PROGRAM").unwrap(),
+            matches.value_of("REFERENCE").unwrap(),
+            matches.is_present("qemu"),
+            if matches.is_present("mmu") {
+                matches.value_of("tlb-entries").unwrap().parse().ok()
+            } else {
+                None
+            },
+            isa(matches),
+        ),
+        _ => Err(matches.usage().to_string()),
+    };
+
+    match res {
+        Ok(true) => {},
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        },
+    }
+}
+
+fn isa_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("isa")
+        .help("Sets the revision of MIPS32 the instructions are decoded for.")
+        .long("isa")
+        .value_name("ISA")
+        .possible_values(&instruction::ISAS)
+        .default_value("r2")
+}
+
+fn isa(matches: &ArgMatches) -> Isa {
+    matches.value_of("isa").and_then(Isa::from_name).unwrap_or_default()
+}
+
+fn convert(input: &str, output: &str, format: Option<Format>, isa: Isa) -> Result<bool, String> {
+    let reader = TraceReader::open(input)?;
+    let format = format.unwrap_or(match reader.format() {
+        Format::Json => Format::Binary,
+        Format::Binary => Format::Json,
+    });
+
+    let mut writer = TraceWriter::create(output, format, Filter::default())?;
+    for record in reader {
+        writer.write(&record?, isa).map_err(|err| err.to_string())?;
+    }
+    writer.flush().map_err(|err| err.to_string())?;
+    Ok(true)
+}
+
+fn diff(expected: &str, actual: &str, isa: Isa) -> Result<bool, String> {
+    let mut expected = TraceReader::open(expected)?;
+    let mut actual = TraceReader::open(actual)?;
+
+    let mut index = 0;
+    loop {
+        match (expected.next().transpose()?, actual.next().transpose()?) {
+            (None, None) => {
+                println!("Traces are identical ({} records).", index);
+                return Ok(true);
+            },
+            (Some(record), None) => {
+                println!("Record {}: actual trace ends, expected pc={:#x}.", index, record.pc);
+                return Ok(false);
+            },
+            (None, Some(record)) => {
+                println!("Record {}: expected trace ends, actual pc={:#x}.", index, record.pc);
+                return Ok(false);
+            },
+            (Some(a), Some(b)) => {
+                if let Some(difference) = trace::compare(&a, &b) {
+                    println!("Record {} (count {}, pc={:#x}): {}", index, a.count, a.pc, a.instruction(isa));
+                    println!("  {}", difference);
+                    return Ok(false);
+                }
+            },
+        }
+        index += 1;
+    }
+}
+
+/// Keeps the record of the last executed instruction.
+#[derive(Debug, Default)]
+struct LastRecord(Option<Record>);
+
+impl Observer for LastRecord {
+    fn observe(&mut self, record: &Record, _cpu: &Cpu) {
+        self.0 = Some(record.clone());
+    }
+}
+
+fn check(program: &str, reference: &str, qemu: bool, tlb_entries: Option<usize>, isa: Isa) -> Result<bool, String> {
+    let elf_file = elf::File::open_path(program).map_err(|err| format!("{:?}", err))?;
+    let mut cpu = Cpu::new();
+    cpu.isa = isa;
+    if let Some(entries) = tlb_entries {
+        cpu.enable_mmu(entries);
+    }
+    cpu.load_elf(elf_file)?;
+    let last = Rc::new(RefCell::new(LastRecord::default()));
+    cpu.observers.push(last.clone());
+
+    if qemu {
+        check_against_qemu(&mut cpu, &last, QemuLogReader::open(reference)?)
+    } else {
+        check_against_trace(&mut cpu, &last, TraceReader::open(reference)?)
+    }
+}
+
+fn check_against_trace(cpu: &mut Cpu, last: &Rc<RefCell<LastRecord>>, mut reference: TraceReader) -> Result<bool, String> {
+    loop {
+        let expected = match reference.next().transpose()? {
+            Some(expected) => expected,
+            None => {
+                println!("Reference exhausted after {} instructions, no divergence.", cpu.instruction_count);
+                return Ok(true);
+            },
+        };
+
+        let signal = cpu.run(true, false);
+        let actual = last.borrow_mut().0.take().unwrap();
+        if let Some(difference) = trace::compare(&expected, &actual) {
+            print_divergence(&actual, &difference, cpu.isa);
+            println!("  expected: {}", describe_effects(&expected));
+            println!("  actual:   {}", describe_effects(&actual));
+            return Ok(false);
+        }
+
+        if let Some(signal @ Signal::Exit) | Some(signal @ Signal::Trap(_)) = signal {
+            return Ok(end_of_program(&signal, reference.next().is_none()));
+        }
+    }
+}
+
+fn check_against_qemu(cpu: &mut Cpu, last: &Rc<RefCell<LastRecord>>, mut reference: QemuLogReader) -> Result<bool, String> {
+    // the initial registers (stack pointer...) are set by the QEMU loader, not the program
+    let initial = reference.next().transpose()?.ok_or("Empty QEMU log.")?;
+    if initial.pc != cpu.pc {
+        println!("Entry point differs: expected pc={:#x}, actual pc={:#x}", initial.pc, cpu.pc);
+        return Ok(false);
+    }
+    for i in 1..32 {
+        cpu.set_register(i, initial.registers[i as usize]);
+    }
+    cpu.hi = initial.hi;
+    cpu.lo = initial.lo;
+
+    loop {
+        let signal = cpu.run(true, false);
+        let actual = last.borrow_mut().0.take().unwrap();
+
+        if let Some(signal @ Signal::Exit) | Some(signal @ Signal::Trap(_)) = signal {
+            return Ok(end_of_program(&signal, reference.next().is_none()));
+        }
+
+        let expected = match reference.next().transpose()? {
+            Some(expected) => expected,
+            None => {
+                println!("Reference exhausted after {} instructions, no divergence.", cpu.instruction_count);
+                return Ok(true);
+            },
+        };
+
+        let differences = compare_state(&expected, cpu);
+        if !differences.is_empty() {
+            print_divergence(&actual, "state after the instruction differs", cpu.isa);
+            for difference in differences {
+                println!("  {}", difference);
+            }
+            println!("  memory writes: {}", describe_effects(&Record { registers: Vec::new(), ..actual }));
+            return Ok(false);
+        }
+    }
+}
+
+fn compare_state(expected: &QemuState, cpu: &Cpu) -> Vec<String> {
+    let mut differences = Vec::new();
+    if expected.pc != cpu.pc {
+        differences.push(format!("pc: expected {:#x}, actual {:#x}", expected.pc, cpu.pc));
+    }
+    for i in 1..32 {
+        let actual = cpu.get_register(i);
+        if expected.registers[i as usize] != actual {
+            differences.push(format!("${}: expected {:#x}, actual {:#x}", i, expected.registers[i as usize], actual));
+        }
+    }
+    if expected.hi != cpu.hi {
+        differences.push(format!("hi: expected {:#x}, actual {:#x}", expected.hi, cpu.hi));
+    }
+    if expected.lo != cpu.lo {
+        differences.push(format!("lo: expected {:#x}, actual {:#x}", expected.lo, cpu.lo));
+    }
+    differences
+}
+
+fn print_divergence(record: &Record, difference: &str, isa: Isa) {
+    println!("Divergence at instruction {} (pc={:#x}): {}", record.count, record.pc, record.instruction(isa));
+    println!("  {}", difference);
+}
+
+fn end_of_program(signal: &Signal, reference_ended: bool) -> bool {
+    if reference_ended {
+        println!("{} No divergence.", signal);
+    } else {
+        println!("{} The reference continues.", signal);
+    }
+    reference_ended
+}
+
+/// Register changes and memory writes of a record.
+fn describe_effects(record: &Record) -> String {
+    let mut effects: Vec<String> = record.registers
+        .iter()
+        .map(|change| format!("{}={:#x}", trace::register_name(change.reg), change.new))
+        .collect();
+    effects.extend(record.memory
+        .iter()
+        .filter(|access| access.kind == AccessKind::Write)
+        .map(|access| format!("mem{}[{:#x}]={:#x}", access.size * 8, access.addr, access.value)));
+
+    if effects.is_empty() {
+        "nothing".to_string()
+    } else {
+        effects.join(", ")
+    }
+}